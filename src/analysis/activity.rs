@@ -0,0 +1,336 @@
+use std::{fs::File, io::BufWriter};
+
+use serde::{Deserialize, Serialize};
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::BoundsTimeline,
+    data::{to_rfc3339, PixelData},
+    metadata::DatasetMetadata,
+    palette::WHITE,
+    parse::GzippedBinPixelDataReader,
+};
+
+/// Number of updates in every bin of the activity series.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivitySeries {
+    pub start_ms: u32,
+    pub resolution_ms: u32,
+    pub updates: Vec<u64>,
+    /// Updates placing white, which the final whiteout consisted of.
+    pub white_updates: Vec<u64>,
+}
+
+impl ActivitySeries {
+    fn bin_start(&self, bin: usize) -> u32 {
+        self.start_ms + bin as u32 * self.resolution_ms
+    }
+
+    fn per_second(&self, updates: f64) -> f64 {
+        updates * 1000.0 / self.resolution_ms as f64
+    }
+
+    /// Returns the mean number of updates of the bins within `radius` bins of every bin.
+    pub fn baseline(&self, radius: usize) -> Vec<f64> {
+        let mut prefix = vec![0u64; self.updates.len() + 1];
+        for (i, &updates) in self.updates.iter().enumerate() {
+            prefix[i + 1] = prefix[i] + updates;
+        }
+        (0..self.updates.len())
+            .map(|i| {
+                let (start, end) = (
+                    i.saturating_sub(radius),
+                    (i + radius + 1).min(self.updates.len()),
+                );
+                (prefix[end] - prefix[start]) as f64 / (end - start) as f64
+            })
+            .collect()
+    }
+}
+
+/// Counts the updates in every `resolution_ms` of the time window.
+pub fn activity_series(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    window: TimeWindow,
+    resolution_ms: u32,
+) -> Result<ActivitySeries, Whatever> {
+    ensure_whatever!(resolution_ms > 0, "Resolution must be greater than zero");
+    let mut series = ActivitySeries {
+        start_ms: window.start_ms,
+        resolution_ms,
+        updates: Vec::new(),
+        white_updates: Vec::new(),
+    };
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        if ms < window.start_ms {
+            continue;
+        }
+        let bin = ((ms - window.start_ms) / resolution_ms) as usize;
+        if bin >= series.updates.len() {
+            series.updates.resize(bin + 1, 0);
+            series.white_updates.resize(bin + 1, 0);
+        }
+        series.updates[bin] += 1;
+        if pixel_data.pixel_color.palette_index() == WHITE {
+            series.white_updates[bin] += 1;
+        }
+    }
+    Ok(series)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActivityEventKind {
+    /// Much more activity than usual.
+    Spike,
+    /// Much less activity than usual.
+    Lull,
+    /// A spike right after the canvas was expanded.
+    Expansion,
+    /// No updates at all.
+    Outage,
+    /// A spike of mostly white updates.
+    Whiteout,
+}
+
+impl ActivityEventKind {
+    pub fn label(self) -> &'static str {
+        match self {
+            ActivityEventKind::Spike => "Spike",
+            ActivityEventKind::Lull => "Lull",
+            ActivityEventKind::Expansion => "Canvas expansion",
+            ActivityEventKind::Outage => "Outage",
+            ActivityEventKind::Whiteout => "Whiteout",
+        }
+    }
+}
+
+/// A span of unusual activity.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ActivityEvent {
+    pub kind: ActivityEventKind,
+    pub start_ms: u32,
+    /// Exclusive.
+    pub end_ms: u32,
+    /// Highest rate during a spike, lowest during a lull, in updates per second.
+    pub peak_rate: f64,
+    /// Usual rate around the event in updates per second.
+    pub baseline_rate: f64,
+}
+
+impl ActivityEvent {
+    pub fn contains(&self, miliseconds_since_first_pixel: u32) -> bool {
+        (self.start_ms..self.end_ms).contains(&miliseconds_since_first_pixel)
+    }
+}
+
+pub struct DetectionOptions {
+    /// Bins on every side of a bin that make up its baseline.
+    pub baseline_radius: usize,
+    /// Bins with more than this times their baseline are spikes.
+    pub spike_factor: f64,
+    /// Bins with less than this times their baseline are lulls.
+    pub lull_factor: f64,
+}
+
+/// Finds consecutive bins with unusually high or low activity compared to the mean of the bins
+/// around them. Spikes are labeled as expansions if the canvas bounds grew during them, or as
+/// whiteouts if most of their updates are white. Lulls without any updates are outages.
+pub fn detect_events(
+    series: &ActivitySeries,
+    options: &DetectionOptions,
+    canvas_bounds: &BoundsTimeline,
+) -> Vec<ActivityEvent> {
+    let baseline = series.baseline(options.baseline_radius);
+    let kind_of = |bin: usize| {
+        let updates = series.updates[bin] as f64;
+        if updates > options.spike_factor * baseline[bin] {
+            Some(ActivityEventKind::Spike)
+        } else if updates < options.lull_factor * baseline[bin] {
+            Some(ActivityEventKind::Lull)
+        } else {
+            None
+        }
+    };
+
+    let mut events = Vec::new();
+    let mut bin = 0;
+    while bin < series.updates.len() {
+        let Some(kind) = kind_of(bin) else {
+            bin += 1;
+            continue;
+        };
+        let start = bin;
+        while bin < series.updates.len() && kind_of(bin) == Some(kind) {
+            bin += 1;
+        }
+        let bins = start..bin;
+        let (start_ms, end_ms) = (series.bin_start(start), series.bin_start(bin));
+        let updates: u64 = series.updates[bins.clone()].iter().sum();
+        let white_updates: u64 = series.white_updates[bins.clone()].iter().sum();
+        let peak = match kind {
+            ActivityEventKind::Spike => series.updates[bins.clone()].iter().max(),
+            _ => series.updates[bins.clone()].iter().min(),
+        };
+        let expanded = canvas_bounds.stages.iter().any(|stage| {
+            stage.miliseconds_since_first_pixel > 0
+                && (start_ms.saturating_sub(series.resolution_ms)..end_ms)
+                    .contains(&stage.miliseconds_since_first_pixel)
+        });
+        let kind = match kind {
+            ActivityEventKind::Spike if expanded => ActivityEventKind::Expansion,
+            ActivityEventKind::Spike if white_updates * 2 > updates => ActivityEventKind::Whiteout,
+            ActivityEventKind::Lull if updates == 0 => ActivityEventKind::Outage,
+            kind => kind,
+        };
+        events.push(ActivityEvent {
+            kind,
+            start_ms,
+            end_ms,
+            peak_rate: series.per_second(*peak.unwrap() as f64),
+            baseline_rate: series
+                .per_second(baseline[bins.clone()].iter().sum::<f64>() / bins.len() as f64),
+        });
+    }
+    events
+}
+
+/// A row of the activity table.
+#[derive(Serialize)]
+struct ActivityRecord {
+    time: String,
+    miliseconds_since_first_pixel: u32,
+    updates: u64,
+    updates_per_second: f64,
+    baseline_per_second: f64,
+}
+
+pub struct ActivityOptions {
+    pub window: TimeWindow,
+    pub resolution_ms: u32,
+    pub detection: DetectionOptions,
+}
+
+/// Writes the activity series as a table and the detected events as JSON to `events_output` if
+/// given. With `save_events` the events and the end of the series are also stored in the dataset
+/// metadata, where the player marks them on its timeline and names the one playing in its window
+/// title.
+pub fn write_activity(
+    data_path: &str,
+    options: &ActivityOptions,
+    format: TableFormat,
+    output: &str,
+    events_output: Option<&str>,
+    save_events: bool,
+) -> Result<(), Whatever> {
+    let mut metadata = DatasetMetadata::load_or_default(data_path)?;
+    let series = activity_series(
+        GzippedBinPixelDataReader::new(data_path)?,
+        options.window,
+        options.resolution_ms,
+    )?;
+    let events = detect_events(&series, &options.detection, &metadata.canvas_bounds);
+
+    let baseline = series.baseline(options.detection.baseline_radius);
+    let records: Vec<_> = series
+        .updates
+        .iter()
+        .enumerate()
+        .map(|(bin, &updates)| ActivityRecord {
+            time: to_rfc3339(series.bin_start(bin)),
+            miliseconds_since_first_pixel: series.bin_start(bin),
+            updates,
+            updates_per_second: series.per_second(updates as f64),
+            baseline_per_second: series.per_second(baseline[bin]),
+        })
+        .collect();
+    write_records(&records, format, output)?;
+
+    for event in &events {
+        println!(
+            "{} from {} to {}: {:.1} updates/s, usually {:.1}",
+            event.kind.label(),
+            to_rfc3339(event.start_ms),
+            to_rfc3339(event.end_ms),
+            event.peak_rate,
+            event.baseline_rate
+        );
+    }
+    if let Some(events_output) = events_output {
+        let file = File::create(events_output)
+            .with_whatever_context(|_| format!("Failed to create {events_output}"))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &events)
+            .whatever_context("Failed to write events")?;
+    }
+    if save_events {
+        metadata.activity_events = events;
+        metadata.activity_end_ms = Some(series.bin_start(series.updates.len()));
+        metadata.save(data_path)?;
+        println!(
+            "Saved the events to {}",
+            DatasetMetadata::path_for(data_path)
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_detect_events() {
+        use super::{detect_events, ActivityEventKind, ActivitySeries, DetectionOptions};
+        use crate::bounds::{BoundsStage, BoundsTimeline, CanvasBounds};
+
+        let mut updates = vec![10; 30];
+        updates[5] = 100;
+        updates[6] = 80;
+        updates[12] = 0;
+        updates[20] = 90;
+        updates[25] = 1;
+        let mut white_updates = vec![0; 30];
+        white_updates[20] = 80;
+        let series = ActivitySeries {
+            start_ms: 0,
+            resolution_ms: 1000,
+            updates,
+            white_updates,
+        };
+        let options = DetectionOptions {
+            baseline_radius: 5,
+            spike_factor: 3.0,
+            lull_factor: 0.25,
+        };
+        let canvas_bounds = BoundsTimeline {
+            stages: vec![
+                BoundsStage {
+                    miliseconds_since_first_pixel: 0,
+                    bounds: CanvasBounds::FULL,
+                },
+                BoundsStage {
+                    miliseconds_since_first_pixel: 5200,
+                    bounds: CanvasBounds::FULL,
+                },
+            ],
+        };
+
+        let events: Vec<_> = detect_events(&series, &options, &canvas_bounds)
+            .iter()
+            .map(|event| (event.kind, event.start_ms, event.end_ms))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (ActivityEventKind::Expansion, 5000, 7000),
+                (ActivityEventKind::Outage, 12000, 13000),
+                (ActivityEventKind::Whiteout, 20000, 21000),
+                (ActivityEventKind::Lull, 25000, 26000),
+            ]
+        );
+    }
+}
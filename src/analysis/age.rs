@@ -0,0 +1,149 @@
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{CanvasState, CANVAS_WIDTH},
+    data::{to_utc, EventTime, PixelData},
+    export::write_npy,
+    keyframe,
+    ramp::{normalize, ColorRamp},
+};
+
+/// The canvas together with the time every pixel last changed its color. Placing the color a
+/// pixel already has doesn't count as a change.
+pub struct AgeState {
+    pub canvas: CanvasState,
+    /// Row-major time of the last change of every canvas pixel. Pixels that never changed count
+    /// from the start of the event.
+    pub last_change: Vec<u32>,
+}
+
+impl Default for AgeState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AgeState {
+    pub fn new() -> Self {
+        let canvas = CanvasState::new();
+        let last_change = vec![0; canvas.as_bytes().len() / 3];
+        Self {
+            canvas,
+            last_change,
+        }
+    }
+
+    pub fn apply(&mut self, pixel_data: &PixelData) {
+        let last_change = &mut self.last_change;
+        self.canvas.apply_with_changes(pixel_data, |(x, y), _, _| {
+            last_change[y as usize * CANVAS_WIDTH as usize + x as usize] =
+                pixel_data.miliseconds_since_first_pixel;
+        });
+    }
+
+    /// Returns the row-major age in milliseconds of every pixel of the region at the given time.
+    pub fn ages(&self, region: CanvasBounds, miliseconds_since_first_pixel: u32) -> Vec<u32> {
+        let mut ages = Vec::with_capacity(region.area());
+        for y in region.y1..region.y2 {
+            for x in region.x1..region.x2 {
+                let last_change = self.last_change[y as usize * CANVAS_WIDTH as usize + x as usize];
+                ages.push(miliseconds_since_first_pixel.saturating_sub(last_change));
+            }
+        }
+        ages
+    }
+}
+
+/// Replays every update up to and including the given time. Keyframes don't store when pixels
+/// changed, so this always starts from the beginning.
+pub fn age_state_at(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    miliseconds_since_first_pixel: u32,
+) -> Result<AgeState, Whatever> {
+    let mut state = AgeState::new();
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        if pixel_data.miliseconds_since_first_pixel > miliseconds_since_first_pixel {
+            break;
+        }
+        state.apply(&pixel_data);
+    }
+    Ok(state)
+}
+
+pub struct AgeMapOptions {
+    pub time: EventTime,
+    pub region: CanvasBounds,
+    pub ramp: ColorRamp,
+    pub log_scale: bool,
+    /// Ages at or above this are drawn with the coldest color. Defaults to the oldest pixel.
+    pub max_age_ms: Option<u32>,
+    pub scale: u32,
+}
+
+/// Writes the age map at the given time as PNG, recently changed pixels with the hottest color of
+/// the ramp. The ages in milliseconds are also written to `raw_output` as a `(height, width)`
+/// `.npy` array of `u32` if given.
+pub fn write_age_map(
+    data_path: &str,
+    options: &AgeMapOptions,
+    output: &str,
+    raw_output: Option<&str>,
+) -> Result<(), Whatever> {
+    let ms = options.time.miliseconds_since_first_pixel;
+    let updates = keyframe::updates_from(data_path, None, 0, options.region)?;
+    let state = age_state_at(updates, ms)?;
+    let ages = state.ages(options.region, ms);
+
+    let max_age = options
+        .max_age_ms
+        .unwrap_or_else(|| ages.iter().copied().max().unwrap_or(0));
+    ensure_whatever!(max_age > 0, "Maximum age must be greater than zero");
+    let values: Vec<f32> = ages
+        .iter()
+        .map(|&age| 1.0 - normalize(age.min(max_age) as f64, max_age as f64, options.log_scale))
+        .collect();
+    options
+        .ramp
+        .render(options.region.width(), options.region.height(), &values)
+        .scaled(options.scale)
+        .write_png(output)?;
+
+    if let Some(raw_output) = raw_output {
+        let shape = [
+            options.region.height() as usize,
+            options.region.width() as usize,
+        ];
+        write_npy(raw_output, &shape, &ages)?;
+    }
+    println!("Wrote the age map at {} to {}", to_utc(ms), output);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_ages() {
+        use super::AgeState;
+        use crate::{
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, r| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x, y: 0 },
+            pixel_color: PixelColor { r, g: 0, b: 0 },
+            user: 0,
+        };
+        let mut state = AgeState::new();
+        state.apply(&pixel(100, 0, 0));
+        state.apply(&pixel(200, 1, 0));
+        // Same color again, so the pixel doesn't change.
+        state.apply(&pixel(300, 1, 0));
+
+        let region: CanvasBounds = "0,0,2,0".parse().unwrap();
+        assert_eq!(state.ages(region, 1000), vec![900, 800, 1000]);
+    }
+}
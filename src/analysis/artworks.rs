@@ -0,0 +1,281 @@
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    atlas::Atlas,
+    bounds::CanvasBounds,
+    canvas::{CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{to_rfc3339, PixelData},
+    keyframe,
+    palette::PaletteCanvas,
+};
+
+/// The artworks every canvas pixel belongs to, in compressed rows.
+struct PixelOwners {
+    /// Start of the owners of every pixel in `owners`, and the end of the last.
+    offsets: Vec<u32>,
+    owners: Vec<u32>,
+}
+
+impl PixelOwners {
+    fn new(atlas: &Atlas) -> Self {
+        let index = |(x, y): (u32, u32)| (y * CANVAS_WIDTH + x) as usize;
+        let mut offsets = vec![0u32; (CANVAS_WIDTH * CANVAS_HEIGHT) as usize + 1];
+        for artwork in &atlas.artworks {
+            for point in artwork.pixels() {
+                offsets[index(point) + 1] += 1;
+            }
+        }
+        for i in 1..offsets.len() {
+            offsets[i] += offsets[i - 1];
+        }
+        let mut next = offsets.clone();
+        let mut owners = vec![0; *offsets.last().unwrap() as usize];
+        for (artwork_index, artwork) in atlas.artworks.iter().enumerate() {
+            for point in artwork.pixels() {
+                owners[next[index(point)] as usize] = artwork_index as u32;
+                next[index(point)] += 1;
+            }
+        }
+        Self { offsets, owners }
+    }
+
+    fn of(&self, (x, y): (u32, u32)) -> &[u32] {
+        let index = (y * CANVAS_WIDTH + x) as usize;
+        &self.owners[self.offsets[index] as usize..self.offsets[index + 1] as usize]
+    }
+}
+
+/// How an artwork fared during the time window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtworkStats {
+    pub pixels: usize,
+    /// Pixels covered by updates. Shape fills count for every covered pixel.
+    pub placements: u64,
+    /// Updates that changed the color of a pixel.
+    pub overwrites: u64,
+    /// Since when the share of pixels with their final color never dropped below the settle
+    /// share again.
+    pub settled_ms: u32,
+    /// Time from settling to the last update of the window.
+    pub survival_ms: u32,
+}
+
+/// Replays the updates from `canvas` and collects the stats of every artwork during the time
+/// window, with `last` as the canvas at its end.
+pub fn artwork_stats(
+    mut canvas: PaletteCanvas,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    last: &PaletteCanvas,
+    atlas: &Atlas,
+    window: TimeWindow,
+    settle_share: f64,
+) -> Result<Vec<ArtworkStats>, Whatever> {
+    let owners = PixelOwners::new(atlas);
+    let mut stats: Vec<_> = atlas
+        .artworks
+        .iter()
+        .map(|artwork| ArtworkStats {
+            pixels: artwork.pixels().count(),
+            placements: 0,
+            overwrites: 0,
+            settled_ms: window.start_ms,
+            survival_ms: 0,
+        })
+        .collect();
+    let settled_pixels: Vec<_> = stats
+        .iter()
+        .map(|stats| (stats.pixels as f64 * settle_share).ceil() as usize)
+        .collect();
+    // Pixels of every artwork that have their final color, counted once the window starts.
+    let mut finished: Option<Vec<usize>> = None;
+    // Whether every artwork is settled after the last update.
+    let mut settled = vec![true; stats.len()];
+    let mut end_ms = window.start_ms;
+
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        if ms < window.start_ms {
+            canvas.apply_with(&pixel_data, |_, _, _| {});
+            continue;
+        }
+        let finished = finished.get_or_insert_with(|| {
+            let mut finished = vec![0; atlas.artworks.len()];
+            for (index, artwork) in atlas.artworks.iter().enumerate() {
+                finished[index] = artwork
+                    .pixels()
+                    .filter(|&point| canvas.get(point) == last.get(point))
+                    .count();
+                settled[index] = finished[index] >= settled_pixels[index];
+            }
+            finished
+        });
+        end_ms = ms;
+        let mut touched = Vec::new();
+        canvas.apply_with(&pixel_data, |point, from, to| {
+            let wanted = last.get(point);
+            for &owner in owners.of(point) {
+                let owner = owner as usize;
+                stats[owner].placements += 1;
+                if from != to {
+                    stats[owner].overwrites += 1;
+                }
+                finished[owner] =
+                    finished[owner] + (to == wanted) as usize - (from == wanted) as usize;
+                touched.push(owner);
+            }
+        });
+        for owner in touched {
+            if finished[owner] < settled_pixels[owner] {
+                settled[owner] = false;
+            } else if !settled[owner] {
+                settled[owner] = true;
+                stats[owner].settled_ms = ms;
+            }
+        }
+    }
+    for stats in &mut stats {
+        stats.survival_ms = end_ms - stats.settled_ms;
+    }
+    Ok(stats)
+}
+
+pub struct ArtworkStatsOptions {
+    pub window: TimeWindow,
+    /// Share of the pixels that have to have their final color for an artwork to be settled.
+    pub settle_share: f64,
+}
+
+/// A row of the artwork table.
+#[derive(Serialize)]
+struct ArtworkRecord {
+    id: String,
+    name: String,
+    /// Two opposite corners of the bounding box in dataset coordinates, as accepted by
+    /// `--region`.
+    region: String,
+    pixels: usize,
+    placements: u64,
+    overwrites: u64,
+    settled: String,
+    settled_ms: u32,
+    /// Time from settling to the last update of the window.
+    survival_minutes: f64,
+}
+
+/// Writes the stats of every artwork of the atlas as a table.
+pub fn write_artwork_stats(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    atlas: &Atlas,
+    options: &ArtworkStatsOptions,
+    format: TableFormat,
+    output: &str,
+) -> Result<(), Whatever> {
+    ensure_whatever!(!atlas.artworks.is_empty(), "No artworks on the canvas");
+    let covering = atlas
+        .artworks
+        .iter()
+        .map(|artwork| artwork.bounds)
+        .reduce(|a, b| a.union(&b))
+        .unwrap_or(CanvasBounds::FULL);
+    let last = keyframe::canvas_at(data_path, keyframes_dir, options.window.end_ms, covering)?;
+    let (canvas, updates) =
+        keyframe::replay_from(data_path, keyframes_dir, options.window.start_ms, covering)?;
+    let stats = artwork_stats(
+        PaletteCanvas::from_canvas(&canvas),
+        updates,
+        &PaletteCanvas::from_canvas(&last),
+        atlas,
+        options.window,
+        options.settle_share,
+    )?;
+    let records: Vec<_> = atlas
+        .artworks
+        .iter()
+        .zip(&stats)
+        .map(|(artwork, stats)| ArtworkRecord {
+            id: artwork.id.clone(),
+            name: artwork.name.clone(),
+            region: artwork.bounds.to_string(),
+            pixels: stats.pixels,
+            placements: stats.placements,
+            overwrites: stats.overwrites,
+            settled: to_rfc3339(stats.settled_ms),
+            settled_ms: stats.settled_ms,
+            survival_minutes: stats.survival_ms as f64 / 60_000.0,
+        })
+        .collect();
+    write_records(&records, format, output)?;
+    println!(
+        "Wrote the stats of {} artworks to {}",
+        records.len(),
+        output
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_artwork_stats() {
+        use super::artwork_stats;
+        use crate::{
+            analysis::TimeWindow,
+            atlas::{Artwork, Atlas},
+            data::{Coordinate, PixelColor, PixelData},
+            palette::PaletteCanvas,
+        };
+
+        // Dataset (0, 0) to (1, 0) is canvas (1500, 999) to (1501, 999).
+        let artwork = Artwork::new(
+            "1".to_string(),
+            "Pair".to_string(),
+            vec![
+                [1500.0, 999.0],
+                [1502.0, 999.0],
+                [1502.0, 1000.0],
+                [1500.0, 1000.0],
+            ],
+        )
+        .unwrap();
+        let atlas = Atlas::new(vec![artwork]);
+        let pixel = |miliseconds_since_first_pixel, x, r| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x, y: 0 },
+            pixel_color: PixelColor { r, g: 0, b: 0 },
+            user: 0,
+        };
+        let pixels = vec![
+            pixel(10, 0, 0),
+            pixel(20, 1, 0),
+            pixel(30, 1, 255),
+            pixel(40, 1, 0),
+            pixel(50, 2, 0),
+            pixel(60, 0, 0),
+        ];
+        let mut last = PaletteCanvas::new();
+        for pixel_data in &pixels {
+            last.apply_with(pixel_data, |_, _, _| {});
+        }
+        let stats = artwork_stats(
+            PaletteCanvas::new(),
+            pixels.into_iter().map(Ok),
+            &last,
+            &atlas,
+            TimeWindow::ALL,
+            1.0,
+        )
+        .unwrap();
+
+        assert_eq!(stats[0].pixels, 2);
+        assert_eq!(stats[0].placements, 5);
+        assert_eq!(stats[0].overwrites, 4);
+        assert_eq!((stats[0].settled_ms, stats[0].survival_ms), (40, 20));
+    }
+}
@@ -0,0 +1,352 @@
+use std::{cmp::Reverse, collections::VecDeque};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{to_rfc3339, Coordinate, PixelData},
+    keyframe,
+    palette::{PaletteCanvas, WHITE},
+    timelapse::{self, FrameRange, TimelapseFormat, TimelapseOptions},
+};
+
+/// Number of overwrites in every cell of a grid over the canvas, for every bin of event time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverwriteCounts {
+    pub start_ms: u32,
+    pub bin_ms: u32,
+    pub cell_size: u32,
+    /// Row-major counts of every cell for every bin.
+    pub bins: Vec<Vec<u32>>,
+}
+
+impl OverwriteCounts {
+    fn columns(&self) -> u32 {
+        CANVAS_WIDTH.div_ceil(self.cell_size)
+    }
+
+    fn rows(&self) -> u32 {
+        CANVAS_HEIGHT.div_ceil(self.cell_size)
+    }
+
+    fn cell_bounds(&self, cell: usize) -> CanvasBounds {
+        let (x, y) = (
+            cell as u32 % self.columns() * self.cell_size,
+            cell as u32 / self.columns() * self.cell_size,
+        );
+        CanvasBounds {
+            x1: x,
+            y1: y,
+            x2: (x + self.cell_size).min(CANVAS_WIDTH),
+            y2: (y + self.cell_size).min(CANVAS_HEIGHT),
+        }
+    }
+
+    fn bin_start(&self, bin: usize) -> u32 {
+        self.start_ms + bin as u32 * self.bin_ms
+    }
+}
+
+/// Replays the updates from `canvas` and counts the overwrites during the time window in every
+/// cell and bin. An overwrite is a user placing a different color on a pixel that was already
+/// painted. Moderator fills aren't counted, and white pixels of `canvas` count as unpainted.
+pub fn count_overwrites(
+    mut canvas: PaletteCanvas,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    window: TimeWindow,
+    cell_size: u32,
+    bin_ms: u32,
+) -> Result<OverwriteCounts, Whatever> {
+    ensure_whatever!(cell_size > 0, "Cell size must be greater than zero");
+    ensure_whatever!(bin_ms > 0, "Bin duration must be greater than zero");
+    let mut counts = OverwriteCounts {
+        start_ms: window.start_ms,
+        bin_ms,
+        cell_size,
+        bins: Vec::new(),
+    };
+    let (columns, cells) = (
+        counts.columns(),
+        (counts.columns() * counts.rows()) as usize,
+    );
+    let mut painted: Vec<bool> = (0..CANVAS_HEIGHT)
+        .flat_map(|y| (0..CANVAS_WIDTH).map(move |x| (x, y)))
+        .map(|point| canvas.get(point) != WHITE)
+        .collect();
+
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        let counted =
+            window.contains(ms) && matches!(pixel_data.coordinate, Coordinate::Simple { .. });
+        let bin = (ms.saturating_sub(window.start_ms) / bin_ms) as usize;
+        if counted && bin >= counts.bins.len() {
+            counts.bins.resize(bin + 1, vec![0; cells]);
+        }
+        canvas.apply_with(&pixel_data, |(x, y), from, to| {
+            let index = (y * CANVAS_WIDTH + x) as usize;
+            if counted && from != to && painted[index] {
+                let cell = (y / cell_size * columns + x / cell_size) as usize;
+                counts.bins[bin][cell] += 1;
+            }
+            painted[index] = true;
+        });
+    }
+    Ok(counts)
+}
+
+/// A region with many overwrites over consecutive bins.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Battle {
+    /// Bounding box of the contested cells.
+    pub region: CanvasBounds,
+    pub start_ms: u32,
+    /// Exclusive.
+    pub end_ms: u32,
+    /// Overwrites in the contested cells.
+    pub overwrites: u64,
+    /// Most overwrites in one cell during one bin.
+    pub peak_overwrites: u32,
+    /// Number of contested cells and bins the battle consists of.
+    pub cells: usize,
+}
+
+/// Finds the cells with at least `min_overwrites` in a bin and groups the ones that touch in
+/// space or time into battles, ranked by their overwrites.
+pub fn find_battles(counts: &OverwriteCounts, min_overwrites: u32) -> Vec<Battle> {
+    let (columns, rows) = (counts.columns() as usize, counts.rows() as usize);
+    let cells = columns * rows;
+    let contested = |bin: usize, cell: usize| counts.bins[bin][cell] >= min_overwrites.max(1);
+    let mut visited = vec![false; counts.bins.len() * cells];
+
+    let mut battles = Vec::new();
+    for bin in 0..counts.bins.len() {
+        for cell in 0..cells {
+            if visited[bin * cells + cell] || !contested(bin, cell) {
+                continue;
+            }
+            visited[bin * cells + cell] = true;
+            let mut queue = VecDeque::from([(bin, cell)]);
+            let mut region = counts.cell_bounds(cell);
+            let (mut first_bin, mut last_bin) = (bin, bin);
+            let (mut overwrites, mut peak_overwrites, mut size) = (0, 0, 0);
+
+            while let Some((bin, cell)) = queue.pop_front() {
+                let count = counts.bins[bin][cell];
+                region = region.union(&counts.cell_bounds(cell));
+                first_bin = first_bin.min(bin);
+                last_bin = last_bin.max(bin);
+                overwrites += count as u64;
+                peak_overwrites = peak_overwrites.max(count);
+                size += 1;
+
+                let (x, y) = (cell % columns, cell / columns);
+                let neighbors = [
+                    (bin > 0).then(|| (bin - 1, cell)),
+                    (bin + 1 < counts.bins.len()).then_some((bin + 1, cell)),
+                    (x > 0).then(|| (bin, cell - 1)),
+                    (x + 1 < columns).then_some((bin, cell + 1)),
+                    (y > 0).then(|| (bin, cell - columns)),
+                    (y + 1 < rows).then_some((bin, cell + columns)),
+                ];
+                for (bin, cell) in neighbors.into_iter().flatten() {
+                    if !visited[bin * cells + cell] && contested(bin, cell) {
+                        visited[bin * cells + cell] = true;
+                        queue.push_back((bin, cell));
+                    }
+                }
+            }
+            battles.push(Battle {
+                region,
+                start_ms: counts.bin_start(first_bin),
+                end_ms: counts.bin_start(last_bin + 1),
+                overwrites,
+                peak_overwrites,
+                cells: size,
+            });
+        }
+    }
+    battles.sort_by_key(|battle| Reverse(battle.overwrites));
+    battles
+}
+
+pub struct BattleOptions {
+    pub window: TimeWindow,
+    pub cell_size: u32,
+    pub bin_ms: u32,
+    /// Overwrites a cell needs in one bin to be contested.
+    pub min_overwrites: u32,
+    /// Number of battles to keep.
+    pub top: usize,
+}
+
+/// Cropped timelapse frames written for every battle.
+pub struct BattleFrames {
+    /// Every battle gets a numbered directory of PNG frames in here.
+    pub dir: String,
+    pub interval_ms: u32,
+    pub scale: u32,
+}
+
+/// A row of the battle table.
+#[derive(Serialize)]
+struct BattleRecord {
+    rank: usize,
+    /// Two opposite corners in dataset coordinates, as accepted by `--region`.
+    region: String,
+    start: String,
+    end: String,
+    start_ms: u32,
+    end_ms: u32,
+    overwrites: u64,
+    peak_overwrites: u32,
+    cells: usize,
+}
+
+/// Writes the most contested regions of the time window as a table ranked by overwrites, and a
+/// timelapse of every one of them if `frames` is given.
+pub fn write_battles(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &BattleOptions,
+    format: TableFormat,
+    output: &str,
+    frames: Option<&BattleFrames>,
+) -> Result<(), Whatever> {
+    let (canvas, updates) = keyframe::replay_from(
+        data_path,
+        keyframes_dir,
+        options.window.start_ms,
+        CanvasBounds::FULL,
+    )?;
+    let counts = count_overwrites(
+        PaletteCanvas::from_canvas(&canvas),
+        updates,
+        options.window,
+        options.cell_size,
+        options.bin_ms,
+    )?;
+    let mut battles = find_battles(&counts, options.min_overwrites);
+    battles.truncate(options.top);
+
+    let records: Vec<_> = battles
+        .iter()
+        .enumerate()
+        .map(|(index, battle)| BattleRecord {
+            rank: index + 1,
+            region: battle.region.to_string(),
+            start: to_rfc3339(battle.start_ms),
+            end: to_rfc3339(battle.end_ms),
+            start_ms: battle.start_ms,
+            end_ms: battle.end_ms,
+            overwrites: battle.overwrites,
+            peak_overwrites: battle.peak_overwrites,
+            cells: battle.cells,
+        })
+        .collect();
+    write_records(&records, format, output)?;
+    println!("Wrote {} battles to {}", battles.len(), output);
+
+    if let Some(frames) = frames {
+        for (index, battle) in battles.iter().enumerate() {
+            let dir = format!("{}/battle-{:03}", frames.dir, index + 1);
+            let options = TimelapseOptions {
+                range: FrameRange {
+                    start_ms: battle.start_ms,
+                    end_ms: Some(battle.end_ms),
+                    interval_ms: frames.interval_ms,
+                },
+                region: battle.region,
+                scale: frames.scale,
+                format: TimelapseFormat::Png,
+                fps: 30,
+            };
+            let written = timelapse::write_timelapse(data_path, keyframes_dir, &options, &dir)?;
+            println!("Wrote {written} frames to {dir}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_find_battles() {
+        use super::{count_overwrites, find_battles};
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+            palette::PaletteCanvas,
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, y, r| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x, y },
+            pixel_color: PixelColor { r, g: 0, b: 0 },
+            user: 0,
+        };
+        let mut pixels = Vec::new();
+        // Two colors fighting over (0, 0) and the cell right of it, then a quiet period and a
+        // short fight far away.
+        for i in 0..20 {
+            pixels.push(pixel(i * 10, 0, 0, if i % 2 == 0 { 0 } else { 255 }));
+        }
+        for i in 0..10 {
+            pixels.push(pixel(100 + i * 10, 10, 0, if i % 2 == 0 { 0 } else { 255 }));
+        }
+        for i in 0..6 {
+            pixels.push(pixel(
+                500 + i * 10,
+                -1000,
+                500,
+                if i % 2 == 0 { 0 } else { 255 },
+            ));
+        }
+        // Painting over white or with a moderator fill isn't an overwrite.
+        pixels.push(PixelData {
+            miliseconds_since_first_pixel: 510,
+            coordinate: Coordinate::Rectangle {
+                x1: 0,
+                y1: 0,
+                x2: 5,
+                y2: -5,
+            },
+            pixel_color: PixelColor { r: 0, g: 0, b: 0 },
+            user: 0,
+        });
+        // In time order like the dataset, keeping the order of updates at the same time.
+        pixels.sort_by_key(|pixel_data| pixel_data.miliseconds_since_first_pixel);
+
+        let counts = count_overwrites(
+            PaletteCanvas::new(),
+            pixels.into_iter().map(Ok),
+            TimeWindow::ALL,
+            10,
+            100,
+        )
+        .unwrap();
+        let battles = find_battles(&counts, 3);
+
+        assert_eq!(battles.len(), 2);
+        assert_eq!(
+            battles[0].region,
+            "0,9,19,0".parse::<CanvasBounds>().unwrap()
+        );
+        assert_eq!((battles[0].start_ms, battles[0].end_ms), (0, 200));
+        assert_eq!(battles[0].overwrites, 19 + 9);
+        assert_eq!(battles[0].cells, 3);
+        assert_eq!(battles[1].overwrites, 5);
+        assert_eq!(
+            battles[1].region,
+            "-1000,509,-991,500".parse::<CanvasBounds>().unwrap()
+        );
+        assert_eq!((battles[1].start_ms, battles[1].end_ms), (500, 600));
+    }
+}
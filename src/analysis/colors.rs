@@ -0,0 +1,238 @@
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::{BoundsTimeline, CanvasBounds},
+    canvas::covered_bounds,
+    data::{to_rfc3339, PixelData},
+    metadata::DatasetMetadata,
+    palette::{hex, PaletteCanvas, PALETTE},
+    parse::GzippedBinPixelDataReader,
+};
+
+/// Color usage during one bucket of event time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorBucket {
+    pub start_ms: u32,
+    /// Number of updates overlapping the region with every palette color. Shape fills count once.
+    pub placements: [u64; PALETTE.len()],
+    /// Number of pixels of the opened part of the region with every palette color at the end of
+    /// the bucket.
+    pub pixels: [u64; PALETTE.len()],
+}
+
+/// Pixels of every palette color in the counted area of the canvas.
+struct ColorCounts {
+    area: Option<CanvasBounds>,
+    pixels: [u64; PALETTE.len()],
+}
+
+impl ColorCounts {
+    /// Counts the pixels again if the area changed, which only happens when the canvas opens up.
+    fn set_area(&mut self, canvas: &PaletteCanvas, area: Option<CanvasBounds>) {
+        if area == self.area {
+            return;
+        }
+        self.area = area;
+        self.pixels = [0; PALETTE.len()];
+        if let Some(area) = area {
+            for y in area.y1..area.y2 {
+                for x in area.x1..area.x2 {
+                    self.pixels[canvas.get((x, y)) as usize] += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Replays the updates and returns the color usage of the region for every `bucket_ms` of the
+/// time window, starting at its start. Everything before the window is replayed to count the
+/// pixels, and pixels outside of the opened canvas bounds aren't counted.
+pub fn color_usage(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    window: TimeWindow,
+    bucket_ms: u32,
+    region: CanvasBounds,
+    canvas_bounds: &BoundsTimeline,
+) -> Result<Vec<ColorBucket>, Whatever> {
+    ensure_whatever!(bucket_ms > 0, "Bucket duration must be greater than zero");
+    let opened_at = |ms| canvas_bounds.bounds_at(ms).intersection(&region);
+    let mut canvas = PaletteCanvas::new();
+    let mut counts = ColorCounts {
+        area: None,
+        pixels: [0; PALETTE.len()],
+    };
+
+    let new_bucket = |start_ms| ColorBucket {
+        start_ms,
+        placements: [0; PALETTE.len()],
+        pixels: [0; PALETTE.len()],
+    };
+    let finish = |mut bucket: ColorBucket, canvas: &PaletteCanvas, counts: &mut ColorCounts| {
+        let end_ms = bucket.start_ms.saturating_add(bucket_ms - 1);
+        counts.set_area(canvas, opened_at(end_ms));
+        bucket.pixels = counts.pixels;
+        bucket
+    };
+
+    let mut buckets = Vec::new();
+    let mut bucket = new_bucket(window.start_ms);
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        while ms as u64 >= bucket.start_ms as u64 + bucket_ms as u64 {
+            let start_ms = bucket.start_ms + bucket_ms;
+            buckets.push(finish(bucket, &canvas, &mut counts));
+            bucket = new_bucket(start_ms);
+        }
+        let in_region =
+            covered_bounds(&pixel_data.coordinate).is_some_and(|bounds| bounds.intersects(&region));
+        if ms >= window.start_ms && in_region {
+            bucket.placements[pixel_data.pixel_color.palette_index() as usize] += 1;
+        }
+        counts.set_area(&canvas, opened_at(ms));
+        let area = counts.area;
+        canvas.apply_with(&pixel_data, |point, previous, new| {
+            if area.is_some_and(|area| area.contains(point)) {
+                counts.pixels[previous as usize] -= 1;
+                counts.pixels[new as usize] += 1;
+            }
+        });
+    }
+    buckets.push(finish(bucket, &canvas, &mut counts));
+    Ok(buckets)
+}
+
+/// A row of the color usage table.
+#[derive(Serialize)]
+struct ColorUsageRecord {
+    time: String,
+    miliseconds_since_first_pixel: u32,
+    color: String,
+    placements: u64,
+    pixels: u64,
+}
+
+/// Writes the color usage of the region in every bucket as a table with a row per bucket and
+/// color.
+pub fn write_color_usage(
+    data_path: &str,
+    window: TimeWindow,
+    bucket_ms: u32,
+    region: CanvasBounds,
+    format: TableFormat,
+    output: &str,
+) -> Result<(), Whatever> {
+    let metadata = DatasetMetadata::load_or_default(data_path)?;
+    let buckets = color_usage(
+        GzippedBinPixelDataReader::new(data_path)?,
+        window,
+        bucket_ms,
+        region,
+        &metadata.canvas_bounds,
+    )?;
+
+    let mut records = Vec::with_capacity(buckets.len() * PALETTE.len());
+    for bucket in &buckets {
+        for color in 0..PALETTE.len() {
+            records.push(ColorUsageRecord {
+                time: to_rfc3339(bucket.start_ms),
+                miliseconds_since_first_pixel: bucket.start_ms,
+                color: hex(color as u8),
+                placements: bucket.placements[color],
+                pixels: bucket.pixels[color],
+            });
+        }
+    }
+    write_records(&records, format, output)?;
+    println!(
+        "Wrote the color usage of {} buckets to {}",
+        buckets.len(),
+        output
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_color_usage() {
+        use super::color_usage;
+        use crate::{
+            analysis::TimeWindow,
+            bounds::{BoundsStage, BoundsTimeline, CanvasBounds},
+            data::{Coordinate, PixelColor, PixelData},
+            palette::WHITE,
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, r| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x, y: 0 },
+            pixel_color: PixelColor { r, g: 0, b: 0 },
+            user: 0,
+        };
+        // Black is palette index 27.
+        let black = 27;
+        let pixels = vec![
+            pixel(0, 0, 0),
+            pixel(5, 1, 0),
+            pixel(12, 0, 255),
+            pixel(35, 1, 0),
+        ];
+        let canvas_bounds = BoundsTimeline {
+            stages: vec![
+                BoundsStage {
+                    miliseconds_since_first_pixel: 0,
+                    bounds: "0,0,9,-9".parse::<CanvasBounds>().unwrap(),
+                },
+                BoundsStage {
+                    miliseconds_since_first_pixel: 20,
+                    bounds: CanvasBounds::FULL,
+                },
+            ],
+        };
+        let window = TimeWindow {
+            start_ms: 5,
+            end_ms: u32::MAX,
+        };
+        let buckets = color_usage(
+            pixels.clone().into_iter().map(Ok),
+            window,
+            10,
+            CanvasBounds::FULL,
+            &canvas_bounds,
+        )
+        .unwrap();
+
+        let starts: Vec<_> = buckets.iter().map(|bucket| bucket.start_ms).collect();
+        assert_eq!(starts, vec![5, 15, 25, 35]);
+        // (0, 0) was painted over with red, which is closest to #FF4500 in the palette.
+        assert_eq!(buckets[0].placements[black], 1);
+        assert_eq!(buckets[0].placements[2], 1);
+        assert_eq!(buckets[0].pixels[black], 1);
+        assert_eq!(buckets[0].pixels[WHITE as usize], 98);
+        assert_eq!(buckets[1].placements.iter().sum::<u64>(), 0);
+        assert_eq!(buckets[1].pixels[black], 1);
+        assert_eq!(buckets[2].pixels[WHITE as usize], 6_000_000 - 2);
+        assert_eq!(buckets[3].placements[black], 1);
+
+        // Half of the region is opened at first, and only white pixels of it count.
+        let region = "5,0,14,-9".parse::<CanvasBounds>().unwrap();
+        let buckets = color_usage(
+            pixels.into_iter().map(Ok),
+            window,
+            10,
+            region,
+            &canvas_bounds,
+        )
+        .unwrap();
+        assert_eq!(buckets[0].placements.iter().sum::<u64>(), 0);
+        assert_eq!(buckets[0].pixels[WHITE as usize], 50);
+        assert_eq!(buckets[0].pixels.iter().sum::<u64>(), 50);
+        assert_eq!(buckets[2].pixels[WHITE as usize], 100);
+    }
+}
@@ -0,0 +1,225 @@
+use rayon::prelude::*;
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{
+    users::{group_by_user, is_placement},
+    write_records, TableFormat, TimeWindow,
+};
+use crate::{parse::GzippedBinPixelDataReader, user_table::UserTable};
+
+/// How regularly a user placed pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timing {
+    pub placements: usize,
+    /// Intervals shorter than the cooldown.
+    pub violations: usize,
+    pub fastest_ms: u32,
+    pub median_ms: u32,
+    /// Standard deviation of the intervals divided by their mean. Scripts that place as soon as
+    /// the cooldown ends get close to zero.
+    pub variation: f64,
+    /// Entropy of the intervals in bins, divided by the highest possible entropy for their
+    /// number. Zero if all intervals fall into one bin, one if no two do.
+    pub entropy: f64,
+    /// Mean of the share of violations, one minus the variation capped at one, and one minus the
+    /// entropy.
+    pub score: f64,
+}
+
+/// Measures the intervals between the placement times of a user. Returns `None` for fewer than
+/// three placements.
+pub fn timing(times: &[u32], cooldown_ms: u32, bin_ms: u32) -> Option<Timing> {
+    if times.len() < 3 {
+        return None;
+    }
+    let mut intervals: Vec<u32> = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let count = intervals.len() as f64;
+    let violations = intervals
+        .iter()
+        .filter(|&&interval| interval < cooldown_ms)
+        .count();
+
+    let mean = intervals
+        .iter()
+        .map(|&interval| interval as f64)
+        .sum::<f64>()
+        / count;
+    let variance = intervals
+        .iter()
+        .map(|&interval| (interval as f64 - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    let variation = if mean > 0.0 {
+        variance.sqrt() / mean
+    } else {
+        0.0
+    };
+
+    intervals.sort_unstable();
+    let mut entropy = 0.0;
+    let mut start = 0;
+    while start < intervals.len() {
+        let bin = intervals[start] / bin_ms.max(1);
+        let in_bin = intervals[start..]
+            .iter()
+            .take_while(|&&interval| interval / bin_ms.max(1) == bin)
+            .count();
+        let share = in_bin as f64 / count;
+        entropy -= share * share.log2();
+        start += in_bin;
+    }
+    let entropy = entropy / count.log2();
+
+    Some(Timing {
+        placements: times.len(),
+        violations,
+        fastest_ms: intervals[0],
+        median_ms: intervals[intervals.len() / 2],
+        variation,
+        entropy,
+        score: (violations as f64 / count + (1.0 - variation.min(1.0)) + (1.0 - entropy)) / 3.0,
+    })
+}
+
+pub struct CooldownOptions {
+    pub window: TimeWindow,
+    pub cooldown_ms: u32,
+    /// Width of the interval bins the entropy is measured with.
+    pub bin_ms: u32,
+    /// Users with fewer placements are left out.
+    pub min_placements: usize,
+    /// Users with a lower variation are flagged as regular.
+    pub max_variation: f64,
+    /// Users with a lower entropy are flagged as low entropy.
+    pub max_entropy: f64,
+    /// Number of the highest scoring users printed.
+    pub top_users: usize,
+}
+
+/// A row of the cooldown table.
+#[derive(Serialize)]
+struct TimingRecord {
+    /// The user hash, or the user id if the dataset has no user table.
+    user: String,
+    id: u32,
+    placements: usize,
+    violations: usize,
+    fastest_seconds: f64,
+    median_seconds: f64,
+    variation: f64,
+    entropy: f64,
+    score: f64,
+    /// `cooldown`, `regular` and `low-entropy`, separated by spaces.
+    flags: String,
+}
+
+/// Writes the timing of every user with enough placements as a table, the highest score first.
+/// Moderator fills aren't subject to the cooldown and are left out.
+pub fn write_cooldown_report(
+    data_path: &str,
+    options: &CooldownOptions,
+    format: TableFormat,
+    output: &str,
+) -> Result<(), Whatever> {
+    let times = group_by_user(
+        || Ok(GzippedBinPixelDataReader::new(data_path)?.filter(is_placement)),
+        options.window,
+        |pixel_data| pixel_data.miliseconds_since_first_pixel,
+    )?;
+    let users = UserTable::load_for(data_path)?;
+
+    let mut timings: Vec<(u32, Timing)> = (0..times.users() as u32)
+        .into_par_iter()
+        .filter(|&user| times.placements[user as usize] as usize >= options.min_placements)
+        .filter_map(|user| {
+            timing(times.of(user), options.cooldown_ms, options.bin_ms).map(|timing| (user, timing))
+        })
+        .collect();
+    ensure_whatever!(
+        !timings.is_empty(),
+        "No users with at least {} placements",
+        options.min_placements
+    );
+    timings.sort_by(|(_, a), (_, b)| b.score.total_cmp(&a.score));
+
+    let records: Vec<_> = timings
+        .iter()
+        .map(|(user, timing)| {
+            let flags = [
+                (timing.violations > 0, "cooldown"),
+                (timing.variation < options.max_variation, "regular"),
+                (timing.entropy < options.max_entropy, "low-entropy"),
+            ];
+            TimingRecord {
+                user: UserTable::name(users.as_ref(), *user),
+                id: *user,
+                placements: timing.placements,
+                violations: timing.violations,
+                fastest_seconds: timing.fastest_ms as f64 / 1000.0,
+                median_seconds: timing.median_ms as f64 / 1000.0,
+                variation: timing.variation,
+                entropy: timing.entropy,
+                score: timing.score,
+                flags: flags
+                    .iter()
+                    .filter(|(flagged, _)| *flagged)
+                    .map(|(_, flag)| *flag)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            }
+        })
+        .collect();
+    write_records(&records, format, output)?;
+
+    let count = |flag: &str| {
+        records
+            .iter()
+            .filter(|record| record.flags.contains(flag))
+            .count()
+    };
+    println!(
+        "{} users with at least {} placements: {} placed faster than the cooldown, {} regularly, \
+         {} with low entropy",
+        records.len(),
+        options.min_placements,
+        count("cooldown"),
+        count("regular"),
+        count("low-entropy")
+    );
+    for (rank, record) in records.iter().take(options.top_users).enumerate() {
+        println!(
+            "{:>3}. {} scored {:.3} with {} placements, {} under the cooldown",
+            rank + 1,
+            record.user,
+            record.score,
+            record.placements,
+            record.violations
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_timing() {
+        use super::timing;
+
+        // A script placing 500ms after every cooldown.
+        let script: Vec<u32> = (0..10).map(|i| i * 300_500).collect();
+        let script = timing(&script, 300_000, 1000).unwrap();
+        assert_eq!((script.violations, script.fastest_ms), (0, 300_500));
+        assert_eq!((script.variation, script.entropy), (0.0, 0.0));
+        assert!((script.score - 2.0 / 3.0).abs() < 1e-9);
+
+        // Intervals of 1, 2, 4 and 8 minutes, two of them under a 3 minute cooldown.
+        let human = timing(&[0, 60_000, 180_000, 420_000, 900_000], 180_000, 1000).unwrap();
+        assert_eq!(human.violations, 2);
+        assert_eq!(human.median_ms, 240_000);
+        assert_eq!(human.entropy, 1.0);
+        assert!(human.variation > 0.5 && human.score < script.score);
+
+        assert!(timing(&[0, 1], 1, 1).is_none());
+    }
+}
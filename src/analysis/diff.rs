@@ -0,0 +1,206 @@
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat};
+use crate::{
+    bounds::CanvasBounds,
+    data::{to_utc, EventTime},
+    export::RgbImage,
+    keyframe,
+    palette::{hex, PaletteCanvas, PALETTE},
+};
+
+/// Pixels that changed in a region between two canvases, by color.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegionDiff {
+    pub region: CanvasBounds,
+    pub changed: u64,
+    /// Number of changed pixels that had every palette color before.
+    pub removed: [u64; PALETTE.len()],
+    /// Number of changed pixels that have every palette color after.
+    pub added: [u64; PALETTE.len()],
+}
+
+/// Counts the pixels of the region with a different color in `after` than in `before`.
+pub fn count_changes(
+    before: &PaletteCanvas,
+    after: &PaletteCanvas,
+    region: CanvasBounds,
+) -> RegionDiff {
+    let mut diff = RegionDiff {
+        region,
+        changed: 0,
+        removed: [0; PALETTE.len()],
+        added: [0; PALETTE.len()],
+    };
+    for y in region.y1..region.y2 {
+        for x in region.x1..region.x2 {
+            let (from, to) = (before.get((x, y)), after.get((x, y)));
+            if from != to {
+                diff.changed += 1;
+                diff.removed[from as usize] += 1;
+                diff.added[to as usize] += 1;
+            }
+        }
+    }
+    diff
+}
+
+/// Renders the region of `after` with the pixels that didn't change since `before` dimmed.
+pub fn render_diff(
+    before: &PaletteCanvas,
+    after: &PaletteCanvas,
+    region: CanvasBounds,
+) -> RgbImage {
+    let mut image = RgbImage::new(region.width(), region.height());
+    for y in region.y1..region.y2 {
+        for x in region.x1..region.x2 {
+            let color = PALETTE[after.get((x, y)) as usize];
+            let color = if before.get((x, y)) == after.get((x, y)) {
+                color.map(|c| c / 4 + 32)
+            } else {
+                color
+            };
+            image.set((x - region.x1, y - region.y1), color);
+        }
+    }
+    image
+}
+
+pub struct DiffOptions {
+    pub from: EventTime,
+    pub to: EventTime,
+    /// Changes are counted for every region, and the image shows all of them.
+    pub regions: Vec<CanvasBounds>,
+    pub scale: u32,
+}
+
+/// A row of the diff table.
+#[derive(Serialize)]
+struct DiffRecord {
+    /// Two opposite corners in dataset coordinates, as accepted by `--region`.
+    region: String,
+    color: String,
+    removed: u64,
+    added: u64,
+}
+
+/// Rebuilds the canvas at both times and writes the changes as an image to `image_output` if
+/// given, and the changed pixels of every region by color as a table.
+pub fn write_diff(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &DiffOptions,
+    format: TableFormat,
+    output: &str,
+    image_output: Option<&str>,
+) -> Result<(), Whatever> {
+    ensure_whatever!(!options.regions.is_empty(), "No regions given");
+    ensure_whatever!(
+        options.from <= options.to,
+        "The diff has to start before it ends"
+    );
+    let (from_ms, to_ms) = (
+        options.from.miliseconds_since_first_pixel,
+        options.to.miliseconds_since_first_pixel,
+    );
+    let covering = options
+        .regions
+        .iter()
+        .copied()
+        .reduce(|a, b| a.union(&b))
+        .unwrap();
+    // Both canvases come from one replay.
+    let (canvas, updates) = keyframe::replay_from(data_path, keyframes_dir, from_ms, covering)?;
+    let mut after = PaletteCanvas::from_canvas(&canvas);
+    let mut before = None;
+    for pixel_data in updates {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > to_ms {
+            break;
+        }
+        if ms > from_ms && before.is_none() {
+            before = Some(after.clone());
+        }
+        after.apply_with(&pixel_data, |_, _, _| {});
+    }
+    let before = before.unwrap_or_else(|| after.clone());
+
+    if let Some(image_output) = image_output {
+        render_diff(&before, &after, covering)
+            .scaled(options.scale)
+            .write_png(image_output)?;
+    }
+    let diffs: Vec<_> = options
+        .regions
+        .iter()
+        .map(|&region| count_changes(&before, &after, region))
+        .collect();
+    let mut records = Vec::with_capacity(diffs.len() * PALETTE.len());
+    for diff in &diffs {
+        for color in 0..PALETTE.len() {
+            records.push(DiffRecord {
+                region: diff.region.to_string(),
+                color: hex(color as u8),
+                removed: diff.removed[color],
+                added: diff.added[color],
+            });
+        }
+    }
+    write_records(&records, format, output)?;
+
+    println!("Changes from {} to {}:", to_utc(from_ms), to_utc(to_ms));
+    for diff in &diffs {
+        println!(
+            "{}: {} of {} pixels changed",
+            diff.region,
+            diff.changed,
+            diff.region.area()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_count_changes() {
+        use super::{count_changes, render_diff};
+        use crate::{
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+            palette::{PaletteCanvas, WHITE},
+        };
+
+        let pixel = |x, r| PixelData {
+            miliseconds_since_first_pixel: 0,
+            coordinate: Coordinate::Simple { x, y: 0 },
+            pixel_color: PixelColor { r, g: 0, b: 0 },
+            user: 0,
+        };
+        let mut before = PaletteCanvas::new();
+        before.apply_with(&pixel(0, 0), |_, _, _| {});
+        before.apply_with(&pixel(3, 0), |_, _, _| {});
+        let mut after = before.clone();
+        after.apply_with(&pixel(0, 255), |_, _, _| {});
+        after.apply_with(&pixel(1, 0), |_, _, _| {});
+        after.apply_with(&pixel(2, 255), |_, _, _| {});
+        // Placing the same color again isn't a change.
+        after.apply_with(&pixel(3, 0), |_, _, _| {});
+
+        // Black is palette index 27 and red is closest to #FF4500 at index 2.
+        let region: CanvasBounds = "0,0,4,0".parse().unwrap();
+        let diff = count_changes(&before, &after, region);
+        assert_eq!(diff.changed, 3);
+        assert_eq!(diff.removed[27], 1);
+        assert_eq!(diff.removed[WHITE as usize], 2);
+        assert_eq!(diff.added[2], 2);
+        assert_eq!(diff.added[27], 1);
+
+        let image = render_diff(&before, &after, region);
+        assert_eq!(image.get((1, 0)), [0, 0, 0]);
+        assert_eq!(image.get((3, 0)), [32; 3]);
+        assert_eq!(image.get((4, 0)), [255 / 4 + 32; 3]);
+    }
+}
@@ -0,0 +1,376 @@
+use std::fs;
+
+use rayon::prelude::*;
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{
+    heatmap::UpdateCounts,
+    users::{group_by_user, is_placement},
+    write_records, TableFormat, TimeWindow,
+};
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{to_canvas_coords, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::Coordinate,
+    keyframe,
+    parse::GzippedBinPixelDataReader,
+    ramp::ColorRamp,
+    user_table::UserTable,
+};
+
+/// A sparse row of the user × tile matrix: the tile and time bin features a user placed in, with
+/// the number of placements scaled to unit length.
+pub type Row = Vec<(u32, f32)>;
+
+/// Counts the placements in every feature and scales the counts to unit length.
+pub fn to_row(features: &[u32]) -> Row {
+    let mut features = features.to_vec();
+    features.sort_unstable();
+    let mut row: Row = Vec::new();
+    for feature in features {
+        match row.last_mut() {
+            Some((last, count)) if *last == feature => *count += 1.0,
+            _ => row.push((feature, 1.0)),
+        }
+    }
+    let length = row
+        .iter()
+        .map(|(_, count)| count * count)
+        .sum::<f32>()
+        .sqrt();
+    for (_, count) in &mut row {
+        *count /= length;
+    }
+    row
+}
+
+fn similarity(row: &Row, centroid: &[f32]) -> f32 {
+    row.iter()
+        .map(|&(feature, weight)| weight * centroid[feature as usize])
+        .sum()
+}
+
+/// Returns the most similar centroid to the row, the first of equally similar ones.
+fn closest(row: &Row, centroids: &[Vec<f32>]) -> (usize, f32) {
+    centroids
+        .iter()
+        .map(|centroid| similarity(row, centroid))
+        .enumerate()
+        .fold((0, f32::MIN), |best, (cluster, similarity)| {
+            if similarity > best.1 {
+                (cluster, similarity)
+            } else {
+                best
+            }
+        })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Clustering {
+    /// Cluster of every row.
+    pub assignments: Vec<usize>,
+    /// Cosine similarity of every row to the centroid of its cluster.
+    pub similarities: Vec<f32>,
+    pub iterations: usize,
+}
+
+/// Clusters the rows by cosine similarity with spherical k-means. The first row is the first
+/// centroid and every next one is the row least similar to the centroids so far, so the result
+/// doesn't depend on a random seed.
+pub fn cluster(
+    rows: &[Row],
+    dimensions: usize,
+    clusters: usize,
+    max_iterations: usize,
+) -> Clustering {
+    let dense = |row: &Row| {
+        let mut centroid = vec![0.0; dimensions];
+        for &(feature, weight) in row {
+            centroid[feature as usize] = weight;
+        }
+        centroid
+    };
+    let mut centroids = Vec::new();
+    let mut best = vec![f32::MIN; rows.len()];
+    let mut next = 0;
+    while centroids.len() < clusters.min(rows.len()) {
+        centroids.push(dense(&rows[next]));
+        let centroid = centroids.last().unwrap();
+        best.par_iter_mut()
+            .zip(rows)
+            .for_each(|(best, row)| *best = best.max(similarity(row, centroid)));
+        next = (0..rows.len())
+            .min_by(|&a, &b| best[a].total_cmp(&best[b]))
+            .unwrap_or(0);
+    }
+
+    let mut clustering = Clustering {
+        assignments: vec![usize::MAX; rows.len()],
+        similarities: vec![0.0; rows.len()],
+        iterations: 0,
+    };
+    while clustering.iterations < max_iterations {
+        clustering.iterations += 1;
+        let closest: Vec<_> = rows
+            .par_iter()
+            .map(|row| closest(row, &centroids))
+            .collect();
+        let changed = closest
+            .iter()
+            .zip(&clustering.assignments)
+            .any(|((cluster, _), assigned)| cluster != assigned);
+        (clustering.assignments, clustering.similarities) = closest.into_iter().unzip();
+        if !changed {
+            break;
+        }
+
+        let mut sums = vec![vec![0.0f32; dimensions]; centroids.len()];
+        for (row, &cluster) in rows.iter().zip(&clustering.assignments) {
+            for &(feature, weight) in row {
+                sums[cluster][feature as usize] += weight;
+            }
+        }
+        // Clusters that lost all their rows keep their centroid.
+        for (centroid, sum) in centroids.iter_mut().zip(sums) {
+            let length = sum.iter().map(|weight| weight * weight).sum::<f32>().sqrt();
+            if length > 0.0 {
+                *centroid = sum.into_iter().map(|weight| weight / length).collect();
+            }
+        }
+    }
+    clustering
+}
+
+pub struct FactionOptions {
+    pub window: TimeWindow,
+    /// Width and height of a tile in pixels.
+    pub tile_size: u32,
+    /// Event time placements in the same tile count together over. Every centroid takes a float
+    /// for every tile and bin.
+    pub bin_ms: u32,
+    pub factions: usize,
+    /// Users with fewer placements are left out.
+    pub min_placements: u32,
+    pub max_iterations: usize,
+    /// Region of the footprint images.
+    pub region: CanvasBounds,
+    pub scale: u32,
+    pub ramp: ColorRamp,
+    pub log_scale: bool,
+}
+
+/// A row of the membership table.
+#[derive(Serialize)]
+struct MembershipRecord {
+    /// The user hash, or the user id if the dataset has no user table.
+    user: String,
+    id: u32,
+    faction: usize,
+    placements: u32,
+    /// Cosine similarity to the average member of the faction.
+    similarity: f32,
+}
+
+/// Clusters the users with enough placements into factions by the tiles and time bins they placed
+/// in. Writes the faction of every user as a table, the largest faction first, and a heatmap of
+/// the placements of every faction to `faction-01.png` and so on in `footprints_dir`.
+pub fn write_factions(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &FactionOptions,
+    format: TableFormat,
+    output: &str,
+    footprints_dir: &str,
+) -> Result<(), Whatever> {
+    ensure_whatever!(options.tile_size > 0, "Tile size must be greater than zero");
+    ensure_whatever!(options.bin_ms > 0, "Bin duration must be greater than zero");
+    ensure_whatever!(options.factions > 0, "Need at least one faction");
+    ensure_whatever!(
+        options.max_iterations > 0,
+        "Need at least one clustering iteration"
+    );
+    let columns = CANVAS_WIDTH.div_ceil(options.tile_size) as u64;
+    let tiles = columns * CANVAS_HEIGHT.div_ceil(options.tile_size) as u64;
+    // Feature ids are `u32`, with `u32::MAX` left for placements outside of the canvas.
+    let bins = options
+        .window
+        .end_ms
+        .saturating_sub(options.window.start_ms) as u64
+        / options.bin_ms as u64
+        + 1;
+    ensure_whatever!(
+        bins * tiles < u32::MAX as u64,
+        "{} tiles in {} bins are too many features, use larger tiles, longer bins or a shorter \
+         time window",
+        tiles,
+        bins
+    );
+    let features = group_by_user(
+        || Ok(GzippedBinPixelDataReader::new(data_path)?.filter(is_placement)),
+        options.window,
+        |pixel_data| {
+            let Coordinate::Simple { x, y } = pixel_data.coordinate else {
+                return u32::MAX;
+            };
+            let Some((x, y)) = to_canvas_coords((x, y)) else {
+                return u32::MAX;
+            };
+            let bin = ((pixel_data.miliseconds_since_first_pixel - options.window.start_ms)
+                / options.bin_ms) as u64;
+            let tile = (y / options.tile_size) as u64 * columns + (x / options.tile_size) as u64;
+            (bin * tiles + tile) as u32
+        },
+    )?;
+
+    let mut users: Vec<u32> = features
+        .ranking()
+        .into_iter()
+        .filter(|&user| features.placements[user as usize] >= options.min_placements)
+        .collect();
+    users.retain(|&user| features.of(user).iter().any(|&feature| feature != u32::MAX));
+    ensure_whatever!(
+        !users.is_empty(),
+        "No users with at least {} placements",
+        options.min_placements
+    );
+    let rows: Vec<Row> = users
+        .par_iter()
+        .map(|&user| {
+            let placed: Vec<u32> = features
+                .of(user)
+                .iter()
+                .copied()
+                .filter(|&feature| feature != u32::MAX)
+                .collect();
+            to_row(&placed)
+        })
+        .collect();
+    let dimensions = rows
+        .iter()
+        .flat_map(|row| row.last())
+        .map(|&(feature, _)| feature as usize + 1)
+        .max()
+        .unwrap_or(0);
+    let clustering = cluster(&rows, dimensions, options.factions, options.max_iterations);
+
+    // Number the factions by their size.
+    let clusters = options.factions.min(rows.len());
+    let mut sizes = vec![0usize; clusters];
+    for &cluster in &clustering.assignments {
+        sizes[cluster] += 1;
+    }
+    let mut order: Vec<usize> = (0..clusters)
+        .filter(|&cluster| sizes[cluster] > 0)
+        .collect();
+    order.sort_by_key(|&cluster| std::cmp::Reverse(sizes[cluster]));
+    let mut faction_of_cluster = vec![0; clusters];
+    for (faction, &cluster) in order.iter().enumerate() {
+        faction_of_cluster[cluster] = faction;
+    }
+    let mut faction_of_user = vec![None; features.users()];
+    for (&user, &cluster) in users.iter().zip(&clustering.assignments) {
+        faction_of_user[user as usize] = Some(faction_of_cluster[cluster]);
+    }
+
+    let user_table = UserTable::load_for(data_path)?;
+    let mut records: Vec<_> = users
+        .iter()
+        .zip(&clustering.similarities)
+        .map(|(&user, &similarity)| MembershipRecord {
+            user: UserTable::name(user_table.as_ref(), user),
+            id: user,
+            faction: faction_of_user[user as usize].unwrap() + 1,
+            placements: features.placements[user as usize],
+            similarity,
+        })
+        .collect();
+    records.sort_by_key(|record| record.faction);
+    write_records(&records, format, output)?;
+
+    let mut footprints = vec![UpdateCounts::new(options.region); order.len()];
+    let updates = keyframe::updates_from(
+        data_path,
+        keyframes_dir,
+        options.window.start_ms,
+        options.region,
+    )?;
+    for pixel_data in updates.filter(is_placement) {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > options.window.end_ms {
+            break;
+        }
+        if !options.window.contains(ms) {
+            continue;
+        }
+        if let Some(Some(faction)) = faction_of_user.get(pixel_data.user as usize) {
+            footprints[*faction].add(&pixel_data);
+        }
+    }
+    fs::create_dir_all(footprints_dir).whatever_context("Failed to create footprint directory")?;
+    for (faction, footprint) in footprints.iter().enumerate() {
+        let path = format!("{}/faction-{:02}.png", footprints_dir, faction + 1);
+        footprint
+            .render(options.ramp, options.log_scale)
+            .scaled(options.scale)
+            .write_png(&path)?;
+    }
+
+    println!(
+        "Clustered {} users into {} factions in {} iterations",
+        users.len(),
+        order.len(),
+        clustering.iterations
+    );
+    for (faction, &cluster) in order.iter().enumerate() {
+        let placements: u64 = records
+            .iter()
+            .filter(|record| record.faction == faction + 1)
+            .map(|record| record.placements as u64)
+            .sum();
+        println!(
+            "{:>3}. {} members with {} placements",
+            faction + 1,
+            sizes[cluster],
+            placements
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_cluster() {
+        use super::{cluster, to_row};
+
+        assert_eq!(
+            to_row(&[3, 1, 3]),
+            [(1, 1.0 / 5f32.sqrt()), (3, 2.0 / 5f32.sqrt())]
+        );
+
+        // Two groups placing in disjoint tiles, and a user in both who leans to the second.
+        let rows: Vec<_> = [
+            vec![0, 1, 1],
+            vec![0, 1],
+            vec![1, 2],
+            vec![5, 6],
+            vec![6, 7, 7],
+            vec![2, 6, 7, 7],
+        ]
+        .iter()
+        .map(|features| to_row(features))
+        .collect();
+        let clustering = cluster(&rows, 8, 2, 10);
+
+        assert_eq!(clustering.assignments, [0, 0, 0, 1, 1, 1]);
+        assert!(clustering.iterations <= 10);
+        assert!(clustering
+            .similarities
+            .iter()
+            .all(|&similarity| similarity > 0.0));
+        // More clusters than rows leaves every row on its own.
+        assert_eq!(cluster(&rows[..2], 8, 3, 10).assignments, [0, 1]);
+    }
+}
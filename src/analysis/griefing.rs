@@ -0,0 +1,377 @@
+use std::cmp::Reverse;
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::CanvasBounds,
+    data::{to_rfc3339, Coordinate, PixelData},
+    keyframe,
+    palette::{PaletteCanvas, WHITE},
+};
+
+pub struct GriefingOptions {
+    pub window: TimeWindow,
+    pub region: CanvasBounds,
+    /// Time a pixel has to hold its color for a change of it to count as damage.
+    pub min_stable_ms: u32,
+    /// Time a damaged pixel has to get its previous color back in to count as reverted.
+    pub revert_window_ms: u32,
+    /// Width and height of the grid cells damage is grouped in.
+    pub cell_size: u32,
+    /// Longest pause between damage in the same or neighboring cells of one event.
+    pub gap_ms: u32,
+    /// Events with fewer damaged pixels are left out.
+    pub min_pixels: usize,
+}
+
+/// Damage to a stable part of the canvas, and how quickly it was reverted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GriefEvent {
+    /// Bounding box of the damaged pixels.
+    pub region: CanvasBounds,
+    pub start_ms: u32,
+    /// Time of the last damage.
+    pub end_ms: u32,
+    /// Changes of stable pixels. A pixel counts again if it's damaged again after a revert.
+    pub pixels: usize,
+    /// Number of users who did the damage.
+    pub users: usize,
+    /// Time it took to revert every reverted pixel, sorted.
+    pub revert_latencies_ms: Vec<u32>,
+}
+
+/// A damaged pixel that hasn't been reverted yet.
+#[derive(Clone, Copy)]
+struct Damage {
+    previous: u8,
+    ms: u32,
+    event: usize,
+}
+
+fn root(parents: &mut [usize], mut event: usize) -> usize {
+    while parents[event] != event {
+        parents[event] = parents[parents[event]];
+        event = parents[event];
+    }
+    event
+}
+
+/// Replays the updates from `canvas` and finds the events in which users changed pixels of the
+/// region that had held their color for `min_stable_ms`. Damage in the same or neighboring cells
+/// with pauses of at most `gap_ms` belongs to one event. A damaged pixel is reverted if it gets
+/// its previous color back within `revert_window_ms`. Pixels count as stable from the start of
+/// the time window at the latest, white pixels never painted over aren't stable, and moderator
+/// fills aren't damage. The updates are expected in time order, like in the dataset.
+pub fn find_griefing(
+    mut canvas: PaletteCanvas,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    options: &GriefingOptions,
+) -> Result<Vec<GriefEvent>, Whatever> {
+    ensure_whatever!(options.cell_size > 0, "Cell size must be greater than zero");
+    let region = options.region;
+    let (columns, rows) = (
+        region.width().div_ceil(options.cell_size) as usize,
+        region.height().div_ceil(options.cell_size) as usize,
+    );
+    // Unpainted pixels have no stable color yet.
+    let mut last_change: Vec<u32> = (region.y1..region.y2)
+        .flat_map(|y| (region.x1..region.x2).map(move |x| (x, y)))
+        .map(|point| match canvas.get(point) {
+            WHITE => u32::MAX,
+            _ => options.window.start_ms,
+        })
+        .collect();
+    let mut damaged: Vec<Option<Damage>> = vec![None; region.area()];
+    // The event damage last happened in for every cell, and when.
+    let mut active: Vec<Option<(usize, u32)>> = vec![None; columns * rows];
+    let mut events: Vec<GriefEvent> = Vec::new();
+    let mut event_users: Vec<Vec<u32>> = Vec::new();
+    let mut parents: Vec<usize> = Vec::new();
+
+    let last_ms = options
+        .window
+        .end_ms
+        .saturating_add(options.revert_window_ms);
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > last_ms {
+            break;
+        }
+        let placed = matches!(pixel_data.coordinate, Coordinate::Simple { .. });
+        let counted = placed && options.window.contains(ms);
+        canvas.apply_with(&pixel_data, |point, from, to| {
+            let Some(index) = region.index_of(point) else {
+                return;
+            };
+            if from == to {
+                return;
+            }
+            let stable = last_change[index] != u32::MAX
+                && ms.saturating_sub(last_change[index]) >= options.min_stable_ms;
+            last_change[index] = ms;
+            if let Some(damage) = damaged[index] {
+                let latency = ms.saturating_sub(damage.ms);
+                if !placed || latency > options.revert_window_ms {
+                    damaged[index] = None;
+                } else if to == damage.previous {
+                    events[damage.event].revert_latencies_ms.push(latency);
+                    damaged[index] = None;
+                }
+                return;
+            }
+            if !counted || !stable {
+                return;
+            }
+
+            let (x, y) = (
+                (point.0 - region.x1) / options.cell_size,
+                (point.1 - region.y1) / options.cell_size,
+            );
+            let pixel = CanvasBounds {
+                x1: point.0,
+                y1: point.1,
+                x2: point.0 + 1,
+                y2: point.1 + 1,
+            };
+            let mut event = None;
+            for ny in y.saturating_sub(1)..(y + 2).min(rows as u32) {
+                for nx in x.saturating_sub(1)..(x + 2).min(columns as u32) {
+                    let Some((other, last_ms)) = active[(ny * columns as u32 + nx) as usize] else {
+                        continue;
+                    };
+                    if ms.saturating_sub(last_ms) > options.gap_ms {
+                        continue;
+                    }
+                    let other = root(&mut parents, other);
+                    match event {
+                        None => event = Some(other),
+                        Some(event) if event != other => parents[other] = event,
+                        _ => {}
+                    }
+                }
+            }
+            let event = event.unwrap_or_else(|| {
+                parents.push(events.len());
+                event_users.push(Vec::new());
+                events.push(GriefEvent {
+                    region: pixel,
+                    start_ms: ms,
+                    end_ms: ms,
+                    pixels: 0,
+                    users: 0,
+                    revert_latencies_ms: Vec::new(),
+                });
+                events.len() - 1
+            });
+            events[event].region = events[event].region.union(&pixel);
+            events[event].end_ms = ms;
+            events[event].pixels += 1;
+            event_users[event].push(pixel_data.user);
+            active[(y * columns as u32 + x) as usize] = Some((event, ms));
+            damaged[index] = Some(Damage {
+                previous: from,
+                ms,
+                event,
+            });
+        });
+    }
+
+    // Fold every merged event into the one it was merged into.
+    for event in (0..events.len()).rev() {
+        let into = root(&mut parents, event);
+        if into == event {
+            continue;
+        }
+        let merged = std::mem::take(&mut events[event].revert_latencies_ms);
+        let (region, start_ms, end_ms, pixels) = (
+            events[event].region,
+            events[event].start_ms,
+            events[event].end_ms,
+            events[event].pixels,
+        );
+        let users = std::mem::take(&mut event_users[event]);
+        let target = &mut events[into];
+        target.region = target.region.union(&region);
+        target.start_ms = target.start_ms.min(start_ms);
+        target.end_ms = target.end_ms.max(end_ms);
+        target.pixels += pixels;
+        target.revert_latencies_ms.extend(merged);
+        event_users[into].extend(users);
+    }
+    let mut grief_events: Vec<GriefEvent> = events
+        .into_iter()
+        .zip(event_users)
+        .enumerate()
+        .filter(|&(event, _)| parents[event] == event)
+        .map(|(_, (mut event, mut users))| {
+            users.sort_unstable();
+            users.dedup();
+            event.users = users.len();
+            event.revert_latencies_ms.sort_unstable();
+            event
+        })
+        .filter(|event| event.pixels >= options.min_pixels)
+        .collect();
+    grief_events.sort_by_key(|event| event.start_ms);
+    Ok(grief_events)
+}
+
+/// A row of the griefing table.
+#[derive(Serialize)]
+struct GriefRecord {
+    /// Two opposite corners in dataset coordinates, as accepted by `--region`.
+    region: String,
+    start: String,
+    end: String,
+    start_ms: u32,
+    end_ms: u32,
+    pixels: usize,
+    users: usize,
+    reverted: usize,
+    /// Empty if nothing was reverted.
+    median_revert_seconds: Option<f64>,
+    max_revert_seconds: Option<f64>,
+}
+
+/// Writes the griefing events of the time window in the region as a table in time order.
+pub fn write_griefing(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &GriefingOptions,
+    format: TableFormat,
+    output: &str,
+) -> Result<(), Whatever> {
+    let (canvas, updates) = keyframe::replay_from(
+        data_path,
+        keyframes_dir,
+        options.window.start_ms,
+        options.region,
+    )?;
+    let events = find_griefing(PaletteCanvas::from_canvas(&canvas), updates, options)?;
+    ensure_whatever!(
+        !events.is_empty(),
+        "No griefing in the region and time window"
+    );
+
+    let seconds = |ms: Option<&u32>| ms.map(|&ms| ms as f64 / 1000.0);
+    let records: Vec<_> = events
+        .iter()
+        .map(|event| GriefRecord {
+            region: event.region.to_string(),
+            start: to_rfc3339(event.start_ms),
+            end: to_rfc3339(event.end_ms),
+            start_ms: event.start_ms,
+            end_ms: event.end_ms,
+            pixels: event.pixels,
+            users: event.users,
+            reverted: event.revert_latencies_ms.len(),
+            median_revert_seconds: seconds(
+                event
+                    .revert_latencies_ms
+                    .get(event.revert_latencies_ms.len() / 2),
+            ),
+            max_revert_seconds: seconds(event.revert_latencies_ms.last()),
+        })
+        .collect();
+    write_records(&records, format, output)?;
+
+    let mut latencies: Vec<u32> = events
+        .iter()
+        .flat_map(|event| event.revert_latencies_ms.iter().copied())
+        .collect();
+    latencies.sort_unstable();
+    let damaged: usize = events.iter().map(|event| event.pixels).sum();
+    println!(
+        "{} events damaged {} pixels, {} ({:.1}%) reverted within {} seconds",
+        events.len(),
+        damaged,
+        latencies.len(),
+        latencies.len() as f64 * 100.0 / damaged as f64,
+        options.revert_window_ms / 1000
+    );
+    if let Some(median) = seconds(latencies.get(latencies.len() / 2)) {
+        println!("Median revert after {median:.1} seconds");
+    }
+    let mut largest: Vec<_> = records.iter().collect();
+    largest.sort_by_key(|record| Reverse(record.pixels));
+    for record in largest.iter().take(10) {
+        println!(
+            "{} at {}: {} pixels by {} users, {} reverted",
+            record.start, record.region, record.pixels, record.users, record.reverted
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_find_griefing() {
+        use super::{find_griefing, GriefingOptions};
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            canvas::to_dataset_coords,
+            data::{Coordinate, PixelColor, PixelData},
+            palette::PaletteCanvas,
+        };
+
+        let pixel = |miliseconds_since_first_pixel, (x, y), r, user| {
+            let (x, y) = to_dataset_coords((x, y));
+            Ok(PixelData {
+                miliseconds_since_first_pixel,
+                coordinate: Coordinate::Simple { x, y },
+                pixel_color: PixelColor { r, g: 0, b: 0 },
+                user,
+            })
+        };
+        let pixels = vec![
+            // Built at the start, red is palette index 2 and black 27.
+            pixel(0, (0, 0), 255, 0),
+            pixel(0, (1, 0), 255, 0),
+            pixel(0, (5, 0), 255, 0),
+            pixel(0, (9, 9), 255, 0),
+            // Damage across two cells, one pixel reverted after 5ms.
+            pixel(100, (0, 0), 0, 1),
+            pixel(101, (1, 0), 0, 2),
+            pixel(102, (5, 0), 0, 1),
+            // Painting a white pixel isn't damage.
+            pixel(103, (3, 3), 0, 4),
+            pixel(105, (0, 0), 255, 3),
+            // Changed again right away, too soon to be stable.
+            pixel(106, (0, 0), 0, 1),
+            // Far away and much later, reverted too late.
+            pixel(500, (9, 9), 0, 1),
+            pixel(600, (9, 9), 255, 0),
+        ];
+        let options = GriefingOptions {
+            window: TimeWindow::ALL,
+            region: "-1500,999,-1491,990".parse::<CanvasBounds>().unwrap(),
+            min_stable_ms: 50,
+            revert_window_ms: 50,
+            cell_size: 4,
+            gap_ms: 10,
+            min_pixels: 1,
+        };
+        let events = find_griefing(PaletteCanvas::new(), pixels.into_iter(), &options).unwrap();
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[0].region,
+            CanvasBounds {
+                x1: 0,
+                y1: 0,
+                x2: 6,
+                y2: 1
+            }
+        );
+        assert_eq!((events[0].start_ms, events[0].end_ms), (100, 102));
+        assert_eq!((events[0].pixels, events[0].users), (3, 2));
+        assert_eq!(events[0].revert_latencies_ms, [5]);
+        assert_eq!((events[1].start_ms, events[1].pixels), (500, 1));
+        assert!(events[1].revert_latencies_ms.is_empty());
+    }
+}
@@ -0,0 +1,163 @@
+use snafu::{prelude::*, Whatever};
+
+use super::TimeWindow;
+use crate::{
+    bounds::CanvasBounds,
+    canvas::for_each_covered_pixel,
+    data::PixelData,
+    export::{write_npy, RgbImage},
+    keyframe,
+    ramp::{normalize, ColorRamp},
+};
+
+/// Number of updates of every pixel in a region. Shape fills count for every covered pixel.
+#[derive(Debug, Clone)]
+pub struct UpdateCounts {
+    pub region: CanvasBounds,
+    /// Row-major counts of the region.
+    pub counts: Vec<u32>,
+}
+
+impl UpdateCounts {
+    pub fn new(region: CanvasBounds) -> Self {
+        Self {
+            region,
+            counts: vec![0; region.area()],
+        }
+    }
+
+    pub fn add(&mut self, pixel_data: &PixelData) {
+        for_each_covered_pixel(&pixel_data.coordinate, |point| {
+            if let Some(index) = self.region.index_of(point) {
+                self.counts[index] += 1;
+            }
+        });
+    }
+
+    pub fn max(&self) -> u32 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn render(&self, ramp: ColorRamp, log_scale: bool) -> RgbImage {
+        let max = self.max() as f64;
+        let values: Vec<f32> = self
+            .counts
+            .iter()
+            .map(|&count| normalize(count as f64, max, log_scale))
+            .collect();
+        ramp.render(self.region.width(), self.region.height(), &values)
+    }
+}
+
+/// Counts the updates of every pixel in the region during the time window.
+pub fn count_updates(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    window: TimeWindow,
+    region: CanvasBounds,
+) -> Result<UpdateCounts, Whatever> {
+    let mut counts = UpdateCounts::new(region);
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        if pixel_data.miliseconds_since_first_pixel > window.end_ms {
+            break;
+        }
+        if window.contains(pixel_data.miliseconds_since_first_pixel) {
+            counts.add(&pixel_data);
+        }
+    }
+    Ok(counts)
+}
+
+pub struct HeatmapOptions {
+    pub window: TimeWindow,
+    pub region: CanvasBounds,
+    pub ramp: ColorRamp,
+    pub log_scale: bool,
+    pub scale: u32,
+    /// Only count the updates of this user.
+    pub user: Option<u32>,
+}
+
+/// Writes the update counts as a heatmap PNG, and as a `(height, width)` `.npy` array of `u32`
+/// to `raw_output` if given.
+pub fn write_heatmap(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &HeatmapOptions,
+    output: &str,
+    raw_output: Option<&str>,
+) -> Result<(), Whatever> {
+    let updates = keyframe::updates_from(
+        data_path,
+        keyframes_dir,
+        options.window.start_ms,
+        options.region,
+    )?;
+    let updates = updates.filter(|pixel_data| match (pixel_data, options.user) {
+        (Ok(pixel_data), Some(user)) => pixel_data.user == user,
+        _ => true,
+    });
+    let counts = count_updates(updates, options.window, options.region)?;
+    ensure_whatever!(counts.max() > 0, "No updates in the region and time window");
+
+    counts
+        .render(options.ramp, options.log_scale)
+        .scaled(options.scale)
+        .write_png(output)?;
+    if let Some(raw_output) = raw_output {
+        let shape = [
+            options.region.height() as usize,
+            options.region.width() as usize,
+        ];
+        write_npy(raw_output, &shape, &counts.counts)?;
+    }
+    println!(
+        "Total updates: {}, most updated pixel: {}",
+        counts.counts.iter().map(|&count| count as u64).sum::<u64>(),
+        counts.max()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_count_updates() {
+        use super::count_updates;
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let pixel = |miliseconds_since_first_pixel, coordinate| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate,
+            pixel_color: PixelColor { r: 0, g: 0, b: 0 },
+            user: 0,
+        };
+        let pixels = vec![
+            pixel(0, Coordinate::Simple { x: 0, y: 0 }),
+            pixel(10, Coordinate::Simple { x: 0, y: 0 }),
+            pixel(
+                20,
+                Coordinate::Rectangle {
+                    x1: -1,
+                    y1: 1,
+                    x2: 1,
+                    y2: -1,
+                },
+            ),
+            pixel(30, Coordinate::Simple { x: 0, y: 0 }),
+        ];
+        let region: CanvasBounds = "-1,1,1,-1".parse().unwrap();
+        let window = TimeWindow {
+            start_ms: 10,
+            end_ms: 20,
+        };
+        let counts = count_updates(pixels.into_iter().map(Ok), window, region).unwrap();
+
+        // (0, 0) is at the center of the 3x3 region, the rectangle covers its top left 2x2.
+        assert_eq!(counts.counts, vec![1, 1, 0, 1, 2, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,75 @@
+use std::{fs::File, io::BufWriter};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+pub mod activity;
+pub mod age;
+pub mod artworks;
+pub mod battles;
+pub mod colors;
+pub mod cooldown;
+pub mod diff;
+pub mod factions;
+pub mod griefing;
+pub mod heatmap;
+pub mod moderation;
+pub mod search;
+pub mod segmentation;
+pub mod stability;
+pub mod template;
+pub mod transitions;
+pub mod users;
+
+/// A time range of the event, both ends inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub start_ms: u32,
+    pub end_ms: u32,
+}
+
+impl TimeWindow {
+    pub const ALL: TimeWindow = TimeWindow {
+        start_ms: 0,
+        end_ms: u32::MAX,
+    };
+
+    pub fn contains(&self, miliseconds_since_first_pixel: u32) -> bool {
+        (self.start_ms..=self.end_ms).contains(&miliseconds_since_first_pixel)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TableFormat {
+    Csv,
+    /// An array of objects with the same fields as the CSV columns.
+    Json,
+}
+
+/// Writes the records as rows of a table, for loading into a spreadsheet or notebook.
+pub fn write_records<T: Serialize>(
+    records: &[T],
+    format: TableFormat,
+    output: &str,
+) -> Result<(), Whatever> {
+    match format {
+        TableFormat::Csv => {
+            let mut writer = csv::Writer::from_path(output)
+                .with_whatever_context(|_| format!("Failed to create {output}"))?;
+            for record in records {
+                writer
+                    .serialize(record)
+                    .with_whatever_context(|_| format!("Failed to write {output}"))?;
+            }
+            writer
+                .flush()
+                .with_whatever_context(|_| format!("Failed to write {output}"))
+        }
+        TableFormat::Json => {
+            let file = File::create(output)
+                .with_whatever_context(|_| format!("Failed to create {output}"))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), records)
+                .with_whatever_context(|_| format!("Failed to write {output}"))
+        }
+    }
+}
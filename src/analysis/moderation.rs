@@ -0,0 +1,309 @@
+use std::{cmp::Reverse, fs};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{covered_bounds, for_each_covered_pixel, CanvasState, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{to_rfc3339, Coordinate, PixelColor, PixelData},
+    export::RgbImage,
+    keyframe,
+    palette::hex,
+    user_table::UserTable,
+};
+
+/// A rectangle or circle fill, which only admins and moderators could place.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModerationAction {
+    pub miliseconds_since_first_pixel: u32,
+    pub coordinate: Coordinate,
+    /// Bounding box of the covered pixels.
+    pub bounds: CanvasBounds,
+    pub color: PixelColor,
+    pub user: u32,
+    /// Number of covered pixels, clipped to the canvas.
+    pub area: usize,
+    /// Covered pixels inside the region that held another color. Pixels outside of it aren't
+    /// counted, since the canvas is only replayed up to date in the region.
+    pub overwritten: usize,
+    /// The canvas around the action right before and after it.
+    pub crops: Option<(RgbImage, RgbImage)>,
+}
+
+/// Replays the updates from `canvas` and calls `on_action` with every fill during the time window
+/// overlapping the region, in time order. With a `crop_margin` the actions come with crops of
+/// their bounds grown by the margin, clipped to the region. Only the canvas inside the region has
+/// to be up to date, as with [`keyframe::replay_from`].
+pub fn find_actions(
+    mut canvas: CanvasState,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    window: TimeWindow,
+    region: CanvasBounds,
+    crop_margin: Option<u32>,
+    mut on_action: impl FnMut(ModerationAction) -> Result<(), Whatever>,
+) -> Result<(), Whatever> {
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        let bounds = match pixel_data.coordinate {
+            Coordinate::Simple { .. } => None,
+            _ => covered_bounds(&pixel_data.coordinate),
+        };
+        let Some(bounds) =
+            bounds.filter(|bounds| window.contains(ms) && bounds.intersects(&region))
+        else {
+            canvas.apply(&pixel_data);
+            continue;
+        };
+
+        let crop = crop_margin.and_then(|margin| {
+            CanvasBounds {
+                x1: bounds.x1.saturating_sub(margin),
+                y1: bounds.y1.saturating_sub(margin),
+                x2: (bounds.x2 + margin).min(CANVAS_WIDTH),
+                y2: (bounds.y2 + margin).min(CANVAS_HEIGHT),
+            }
+            .intersection(&region)
+        });
+        let before = crop.map(|crop| RgbImage::from_canvas(&canvas, crop));
+        let mut area = 0;
+        for_each_covered_pixel(&pixel_data.coordinate, |_| area += 1);
+        let mut overwritten = 0;
+        canvas.apply_with_changes(&pixel_data, |point, _, _| {
+            if region.contains(point) {
+                overwritten += 1;
+            }
+        });
+        let crops = crop
+            .zip(before)
+            .map(|(crop, before)| (before, RgbImage::from_canvas(&canvas, crop)));
+
+        on_action(ModerationAction {
+            miliseconds_since_first_pixel: ms,
+            coordinate: pixel_data.coordinate,
+            bounds,
+            color: pixel_data.pixel_color,
+            user: pixel_data.user,
+            area,
+            overwritten,
+            crops,
+        })?;
+    }
+    Ok(())
+}
+
+/// Cropped snapshots written around every action.
+pub struct ModerationCrops {
+    /// The crops are written in here as `action-0001-before.png` and `action-0001-after.png`.
+    pub dir: String,
+    /// Pixels of context around the action.
+    pub margin: u32,
+    pub scale: u32,
+}
+
+/// A row of the moderation table.
+#[derive(Serialize)]
+struct ActionRecord {
+    /// Position of the action in time order, as in the crop names.
+    number: usize,
+    time: String,
+    time_ms: u32,
+    shape: &'static str,
+    /// Two opposite corners of the covered pixels in dataset coordinates, as accepted by
+    /// `--region`.
+    region: String,
+    /// Empty for rectangles.
+    radius: Option<i16>,
+    area: usize,
+    color: String,
+    /// Only counts the pixels inside the region.
+    overwritten: usize,
+    /// The user hash, or the user id if the dataset has no user table.
+    user: String,
+}
+
+/// Writes every rectangle and circle fill of the time window overlapping the region as a table in
+/// time order, and snapshots before and after every one of them if `crops` is given.
+pub fn write_moderation(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    window: TimeWindow,
+    region: CanvasBounds,
+    format: TableFormat,
+    output: &str,
+    crops: Option<&ModerationCrops>,
+) -> Result<(), Whatever> {
+    let (canvas, updates) =
+        keyframe::replay_from(data_path, keyframes_dir, window.start_ms, region)?;
+    let users = UserTable::load_for(data_path)?;
+    if let Some(crops) = crops {
+        fs::create_dir_all(&crops.dir).whatever_context("Failed to create crop directory")?;
+    }
+
+    let mut records = Vec::new();
+    find_actions(
+        canvas,
+        updates,
+        window,
+        region,
+        crops.map(|crops| crops.margin),
+        |action| {
+            let number = records.len() + 1;
+            if let (Some(crops), Some((before, after))) = (crops, &action.crops) {
+                before
+                    .scaled(crops.scale)
+                    .write_png(&format!("{}/action-{:04}-before.png", crops.dir, number))?;
+                after
+                    .scaled(crops.scale)
+                    .write_png(&format!("{}/action-{:04}-after.png", crops.dir, number))?;
+            }
+            let (shape, radius) = match action.coordinate {
+                Coordinate::Circle { radius, .. } => ("circle", Some(radius)),
+                _ => ("rectangle", None),
+            };
+            records.push(ActionRecord {
+                number,
+                time: to_rfc3339(action.miliseconds_since_first_pixel),
+                time_ms: action.miliseconds_since_first_pixel,
+                shape,
+                region: action.bounds.to_string(),
+                radius,
+                area: action.area,
+                color: hex(action.color.palette_index()),
+                overwritten: action.overwritten,
+                user: UserTable::name(users.as_ref(), action.user),
+            });
+            Ok(())
+        },
+    )?;
+    ensure_whatever!(
+        !records.is_empty(),
+        "No moderation actions in the region and time window"
+    );
+    write_records(&records, format, output)?;
+
+    println!(
+        "{} moderation actions covered {} pixels and overwrote {} of them",
+        records.len(),
+        records.iter().map(|record| record.area).sum::<usize>(),
+        records
+            .iter()
+            .map(|record| record.overwritten)
+            .sum::<usize>()
+    );
+    let mut largest: Vec<_> = records.iter().collect();
+    largest.sort_by_key(|record| Reverse(record.overwritten));
+    for record in largest.iter().take(10) {
+        println!(
+            "{:>5}. {} {} at {} in {}, overwrote {} of {} pixels",
+            record.number,
+            record.time,
+            record.shape,
+            record.region,
+            record.color,
+            record.overwritten,
+            record.area
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_find_actions() {
+        use super::find_actions;
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            canvas::CanvasState,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let update = |miliseconds_since_first_pixel, coordinate, r| {
+            Ok(PixelData {
+                miliseconds_since_first_pixel,
+                coordinate,
+                pixel_color: PixelColor { r, g: 0, b: 0 },
+                user: 7,
+            })
+        };
+        let pixels = vec![
+            update(0, Coordinate::Simple { x: 0, y: 0 }, 0),
+            update(
+                10,
+                Coordinate::Rectangle {
+                    x1: -1,
+                    y1: 1,
+                    x2: 1,
+                    y2: -1,
+                },
+                255,
+            ),
+            update(20, Coordinate::Simple { x: 0, y: 0 }, 0),
+            update(
+                30,
+                Coordinate::Circle {
+                    x: 0,
+                    y: 0,
+                    radius: 1,
+                },
+                0,
+            ),
+            update(
+                40,
+                Coordinate::Circle {
+                    x: 500,
+                    y: 0,
+                    radius: 1,
+                },
+                0,
+            ),
+            update(
+                50,
+                Coordinate::Rectangle {
+                    x1: 1,
+                    y1: 1,
+                    x2: 4,
+                    y2: 0,
+                },
+                0,
+            ),
+        ];
+        let region: CanvasBounds = "-2,2,2,-2".parse().unwrap();
+        let mut actions = Vec::new();
+        find_actions(
+            CanvasState::new(),
+            pixels.into_iter(),
+            TimeWindow::ALL,
+            region,
+            Some(1),
+            |action| {
+                actions.push(action);
+                Ok(())
+            },
+        )
+        .unwrap();
+
+        // The circle far away is outside of the region.
+        assert_eq!(actions.len(), 3);
+        // The 2x2 rectangle turns three white pixels and the black one red.
+        assert_eq!((actions[0].area, actions[0].overwritten), (4, 4));
+        assert_eq!(actions[0].bounds.to_string(), "-1,1,0,0");
+        // The circle covers five pixels and overwrites all of them but the black one.
+        assert_eq!((actions[1].area, actions[1].overwritten), (5, 4));
+        assert_eq!(actions[1].user, 7);
+        let (before, after) = actions[1].crops.as_ref().unwrap();
+        assert_eq!((before.width, before.height), (5, 5));
+        assert_eq!(before.get((2, 2)), [0, 0, 0]);
+        assert_eq!(before.get((2, 1)), [255, 0, 0]);
+        assert_eq!(after.get((2, 1)), [0, 0, 0]);
+        // The last pixel of the row is outside of the region, so it doesn't count as overwritten.
+        assert_eq!((actions[2].area, actions[2].overwritten), (3, 2));
+    }
+}
@@ -0,0 +1,421 @@
+use std::{fs::File, io::BufWriter};
+
+use rayon::prelude::*;
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{template::Template, TimeWindow};
+use crate::{
+    bounds::{BoundsTimeline, CanvasBounds},
+    canvas::{CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{to_rfc3339, PixelData},
+    keyframe,
+    metadata::DatasetMetadata,
+    palette::PaletteCanvas,
+};
+
+/// A position where enough pixels of the reference image matched the canvas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Match {
+    pub region: CanvasBounds,
+    pub matching: usize,
+}
+
+/// Summed-area table of the pixels that changed since the previous frame, so positions without
+/// changes can be skipped.
+pub struct ChangedPixels {
+    sums: Vec<u32>,
+}
+
+impl ChangedPixels {
+    /// Builds the table from a row-major flag for every canvas pixel.
+    pub fn new(changed: &[bool]) -> Self {
+        let width = CANVAS_WIDTH as usize + 1;
+        let mut sums = vec![0; width * (CANVAS_HEIGHT as usize + 1)];
+        for y in 0..CANVAS_HEIGHT as usize {
+            let mut row = 0;
+            for x in 0..CANVAS_WIDTH as usize {
+                row += changed[y * CANVAS_WIDTH as usize + x] as u32;
+                sums[(y + 1) * width + x + 1] = sums[y * width + x + 1] + row;
+            }
+        }
+        Self { sums }
+    }
+
+    pub fn any_in(&self, bounds: CanvasBounds) -> bool {
+        let width = CANVAS_WIDTH as usize + 1;
+        let sum = |x: u32, y: u32| self.sums[y as usize * width + x as usize];
+        sum(bounds.x2, bounds.y2) + sum(bounds.x1, bounds.y1)
+            > sum(bounds.x1, bounds.y2) + sum(bounds.x2, bounds.y1)
+    }
+}
+
+/// Returns every position in `area` where at least `min_matching` pixels of the template match
+/// the canvas. With `changed` only positions covering a changed pixel are checked.
+pub fn find_matches(
+    canvas: &PaletteCanvas,
+    template: &Template,
+    area: CanvasBounds,
+    changed: Option<&ChangedPixels>,
+    min_matching: usize,
+) -> Vec<Match> {
+    let (width, height) = (template.region.width(), template.region.height());
+    if width > area.width() || height > area.height() {
+        return Vec::new();
+    }
+    let pixels: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .zip(&template.colors)
+        .filter_map(|(offset, color)| color.map(|color| (offset, color)))
+        .collect();
+    // Giving up after this many mismatches skips most positions after a few pixels.
+    let allowed = pixels.len().saturating_sub(min_matching);
+
+    (area.y1..=area.y2 - height)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            let pixels = &pixels;
+            (area.x1..=area.x2 - width).filter_map(move |x| {
+                let region = CanvasBounds {
+                    x1: x,
+                    y1: y,
+                    x2: x + width,
+                    y2: y + height,
+                };
+                if changed.is_some_and(|changed| !changed.any_in(region)) {
+                    return None;
+                }
+                let mut mismatches = 0;
+                for &((dx, dy), color) in pixels {
+                    if canvas.get((x + dx, y + dy)) != color {
+                        mismatches += 1;
+                        if mismatches > allowed {
+                            return None;
+                        }
+                    }
+                }
+                Some(Match {
+                    region,
+                    matching: pixels.len() - mismatches,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Replays the updates on the canvas of the previous frame and returns the first update after
+/// which one of the candidates had at least `min_matching` pixels, together with that candidate.
+/// Candidates that already matched count from `previous_ms`.
+fn first_match(
+    mut canvas: PaletteCanvas,
+    previous_ms: u32,
+    updates: &[PixelData],
+    template: &Template,
+    candidates: &[Match],
+    min_matching: usize,
+) -> Option<(u32, Match)> {
+    let mut placed: Vec<_> = candidates
+        .iter()
+        .map(|candidate| {
+            let template = template.moved_to((candidate.region.x1, candidate.region.y1));
+            let matching = template.matching(&canvas);
+            (template, matching)
+        })
+        .collect();
+    let found = |placed: &[(Template, usize)]| {
+        placed
+            .iter()
+            .filter(|(_, matching)| *matching >= min_matching)
+            .max_by_key(|(_, matching)| *matching)
+            .map(|(template, matching)| Match {
+                region: template.region,
+                matching: *matching,
+            })
+    };
+    if let Some(found) = found(&placed) {
+        return Some((previous_ms, found));
+    }
+    for pixel_data in updates {
+        canvas.apply_with(pixel_data, |point, from, to| {
+            for (template, matching) in &mut placed {
+                if let Some(color) = template.get(point) {
+                    *matching = *matching + (to == color) as usize - (from == color) as usize;
+                }
+            }
+        });
+        if let Some(found) = found(&placed) {
+            return Some((pixel_data.miliseconds_since_first_pixel, found));
+        }
+    }
+    None
+}
+
+/// The earliest time and place the reference image was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sighting {
+    pub miliseconds_since_first_pixel: u32,
+    pub found: Match,
+    /// Positions matching in the first frame containing the image. Only the best
+    /// [`SearchOptions::max_candidates`] of them are replayed to find the exact time.
+    pub candidates: usize,
+}
+
+/// Replays the updates and searches the canvas every interval of the time window for the
+/// template, restricted to the search area and the opened canvas bounds. Once a frame contains it, the
+/// updates since the previous frame are replayed to find the exact time. Images that appear and
+/// disappear between two frames are missed.
+pub fn find_first_sighting(
+    mut canvas: PaletteCanvas,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    template: &Template,
+    canvas_bounds: &BoundsTimeline,
+    options: &SearchOptions,
+) -> Result<Option<Sighting>, Whatever> {
+    ensure_whatever!(
+        options.interval_ms > 0,
+        "Interval must be greater than zero"
+    );
+    ensure_whatever!(
+        options.threshold > 0.0 && options.threshold <= 1.0,
+        "Threshold must be greater than 0 and at most 1"
+    );
+    let (window, interval_ms) = (options.window, options.interval_ms);
+    let min_matching = (template.len() as f64 * options.threshold).ceil() as usize;
+    // Anything found in the first frame was there at the start of the window already.
+    let mut previous = (canvas.clone(), window.start_ms);
+    let mut pending: Vec<PixelData> = Vec::new();
+    let mut changed: Option<Vec<bool>> = None;
+    let mut frame_ms = window.start_ms as u64;
+
+    let search = |canvas: &PaletteCanvas,
+                  frame_ms: u32,
+                  previous: &mut (PaletteCanvas, u32),
+                  pending: &mut Vec<PixelData>,
+                  changed: &mut Option<Vec<bool>>| {
+        let opened = canvas_bounds.bounds_at(frame_ms);
+        // Nothing is searched while the area isn't opened yet, but the frame still moves on.
+        let mut matches = match options.area.intersection(&opened) {
+            Some(area) => {
+                let changed_pixels = changed.as_deref().map(ChangedPixels::new);
+                find_matches(
+                    canvas,
+                    template,
+                    area,
+                    changed_pixels.as_ref(),
+                    min_matching,
+                )
+            }
+            None => Vec::new(),
+        };
+        if !matches.is_empty() {
+            let candidates = matches.len();
+            matches.sort_by_key(|found| std::cmp::Reverse(found.matching));
+            matches.truncate(options.max_candidates.max(1));
+            return first_match(
+                previous.0.clone(),
+                previous.1,
+                pending,
+                template,
+                &matches,
+                min_matching,
+            )
+            .map(|(ms, found)| (ms, found, candidates));
+        }
+        *previous = (canvas.clone(), frame_ms);
+        pending.clear();
+        *changed = Some(vec![false; CANVAS_WIDTH as usize * CANVAS_HEIGHT as usize]);
+        None
+    };
+
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        while ms as u64 > frame_ms && frame_ms <= window.end_ms as u64 {
+            if let Some((ms, found, candidates)) = search(
+                &canvas,
+                frame_ms as u32,
+                &mut previous,
+                &mut pending,
+                &mut changed,
+            ) {
+                return Ok(Some(Sighting {
+                    miliseconds_since_first_pixel: ms,
+                    found,
+                    candidates,
+                }));
+            }
+            frame_ms += interval_ms as u64;
+        }
+        if frame_ms > window.end_ms as u64 {
+            return Ok(None);
+        }
+        canvas.apply_with(&pixel_data, |(x, y), from, to| {
+            if let Some(changed) = &mut changed {
+                if from != to {
+                    changed[(y * CANVAS_WIDTH + x) as usize] = true;
+                }
+            }
+        });
+        pending.push(pixel_data);
+    }
+    // The stream ended, so one last search covers the rest of the window.
+    let found = search(
+        &canvas,
+        frame_ms.min(window.end_ms as u64) as u32,
+        &mut previous,
+        &mut pending,
+        &mut changed,
+    );
+    Ok(found.map(|(ms, found, candidates)| Sighting {
+        miliseconds_since_first_pixel: ms,
+        found,
+        candidates,
+    }))
+}
+
+pub struct SearchOptions {
+    pub window: TimeWindow,
+    /// Part of the canvas to search.
+    pub area: CanvasBounds,
+    pub interval_ms: u32,
+    /// Share of the opaque pixels that have to match, from 0 to 1.
+    pub threshold: f64,
+    /// Matching positions of a frame that are replayed to find the exact time, the best matching
+    /// first. If more positions match, the earliest sighting may be among the ones left out.
+    pub max_candidates: usize,
+}
+
+/// The sighting, written as JSON.
+#[derive(Serialize)]
+struct SightingRecord {
+    time: String,
+    miliseconds_since_first_pixel: u32,
+    /// Two opposite corners in dataset coordinates, as accepted by `--region`.
+    region: String,
+    matching: usize,
+    pixels: usize,
+}
+
+/// Searches for the earliest time and place the reference image appeared, prints it and writes
+/// it as JSON to `output` if given.
+pub fn write_search(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    template: &Template,
+    options: &SearchOptions,
+    output: Option<&str>,
+) -> Result<(), Whatever> {
+    ensure_whatever!(!template.is_empty(), "Image has no opaque pixels");
+    let metadata = DatasetMetadata::load_or_default(data_path)?;
+    let (canvas, updates) = keyframe::replay_from(
+        data_path,
+        keyframes_dir,
+        options.window.start_ms,
+        options.area,
+    )?;
+    let sighting = find_first_sighting(
+        PaletteCanvas::from_canvas(&canvas),
+        updates,
+        template,
+        &metadata.canvas_bounds,
+        options,
+    )?;
+
+    let Some(sighting) = sighting else {
+        println!("Not found");
+        return Ok(());
+    };
+    let record = SightingRecord {
+        time: to_rfc3339(sighting.miliseconds_since_first_pixel),
+        miliseconds_since_first_pixel: sighting.miliseconds_since_first_pixel,
+        region: sighting.found.region.to_string(),
+        matching: sighting.found.matching,
+        pixels: template.len(),
+    };
+    println!(
+        "First seen at {} in {} with {} of {} pixels matching",
+        record.time, record.region, record.matching, record.pixels
+    );
+    if sighting.candidates > options.max_candidates {
+        println!(
+            "Only the best {} of {} matching positions were replayed, raise --max-candidates if \
+             it was seen earlier elsewhere",
+            options.max_candidates, sighting.candidates
+        );
+    }
+    if let Some(output) = output {
+        let file =
+            File::create(output).with_whatever_context(|_| format!("Failed to create {output}"))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &record)
+            .whatever_context("Failed to write sighting")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_find_first_sighting() {
+        use super::{find_first_sighting, SearchOptions};
+        use crate::{
+            analysis::{
+                template::{Anchor, Template},
+                TimeWindow,
+            },
+            bounds::{BoundsTimeline, CanvasBounds},
+            data::{Coordinate, PixelColor, PixelData},
+            palette::PaletteCanvas,
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, y| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x, y },
+            pixel_color: PixelColor { r: 0, g: 0, b: 0 },
+            user: 0,
+        };
+        // A black 2x2 square with one transparent corner.
+        let black = [0, 0, 0, 255];
+        let template = Template::from_rgba(
+            Anchor::TOP_LEFT,
+            (2, 2),
+            &[black, black, black, [0, 0, 0, 0]],
+        )
+        .unwrap();
+        let pixels = vec![
+            pixel(10, 100, 100),
+            pixel(20, 500, 500),
+            pixel(30, 501, 500),
+            pixel(150, 100, 99),
+            pixel(160, 101, 100),
+            pixel(300, 0, 0),
+        ];
+        let search = |threshold| {
+            let options = SearchOptions {
+                window: TimeWindow::ALL,
+                area: CanvasBounds::FULL,
+                interval_ms: 100,
+                threshold,
+                max_candidates: 64,
+            };
+            find_first_sighting(
+                PaletteCanvas::new(),
+                pixels.clone().into_iter().map(Ok),
+                &template,
+                &BoundsTimeline::default(),
+                &options,
+            )
+            .unwrap()
+        };
+
+        let sighting = search(1.0).unwrap();
+        assert_eq!(sighting.miliseconds_since_first_pixel, 160);
+        assert_eq!(
+            sighting.found.region,
+            "100,100,101,99".parse::<CanvasBounds>().unwrap()
+        );
+        // With one pixel of tolerance the pair placed first is enough.
+        let sighting = search(0.6).unwrap();
+        assert_eq!(sighting.miliseconds_since_first_pixel, 30);
+        assert_eq!(sighting.found.matching, 2);
+    }
+}
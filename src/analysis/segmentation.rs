@@ -0,0 +1,508 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, VecDeque},
+    fs::File,
+    io::BufWriter,
+};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::TimeWindow;
+use crate::{
+    bounds::CanvasBounds,
+    data::{to_rfc3339, to_utc, PixelData},
+    export::RgbImage,
+    keyframe,
+    palette::{PaletteCanvas, PALETTE},
+    ramp::ColorRamp,
+};
+
+/// Last change time of pixels that never changed.
+const NEVER: u32 = u32::MAX;
+
+pub struct SegmentationOptions {
+    pub window: TimeWindow,
+    pub region: CanvasBounds,
+    /// Event time between two segmentations of the canvas.
+    pub interval_ms: u32,
+    /// How long a pixel has to hold its color to be part of an artwork.
+    pub min_stable_ms: u32,
+    /// Neighbors last changed within this time of each other were built in the same burst.
+    pub burst_ms: u32,
+    /// Smaller segments are left out.
+    pub min_pixels: usize,
+    pub scale: u32,
+}
+
+/// Groups the pixels of the region that were stable at `now_ms` into connected segments of
+/// pixels built in the same burst. Returns the row-major indices of the pixels of every segment
+/// with at least `min_pixels`.
+pub fn find_segments(
+    region: CanvasBounds,
+    last_change: &[u32],
+    now_ms: u32,
+    options: &SegmentationOptions,
+) -> Vec<Vec<usize>> {
+    let width = region.width() as usize;
+    let stable = |index: usize| {
+        last_change[index] != NEVER
+            && now_ms.saturating_sub(last_change[index]) >= options.min_stable_ms
+    };
+    let mut visited = vec![false; last_change.len()];
+
+    let mut segments = Vec::new();
+    for start in 0..last_change.len() {
+        if visited[start] || !stable(start) {
+            continue;
+        }
+        visited[start] = true;
+        let mut queue = VecDeque::from([start]);
+        let mut pixels = Vec::new();
+        while let Some(index) = queue.pop_front() {
+            pixels.push(index);
+            let x = index % width;
+            let neighbors = [
+                (x > 0).then(|| index - 1),
+                (x + 1 < width).then_some(index + 1),
+                (index >= width).then(|| index - width),
+                (index + width < last_change.len()).then_some(index + width),
+            ];
+            for neighbor in neighbors.into_iter().flatten() {
+                if !visited[neighbor]
+                    && stable(neighbor)
+                    && last_change[neighbor].abs_diff(last_change[index]) <= options.burst_ms
+                {
+                    visited[neighbor] = true;
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+        if pixels.len() >= options.min_pixels {
+            segments.push(pixels);
+        }
+    }
+    segments
+}
+
+/// Returns the convex hull of the pixels of a segment in canvas coordinates, clockwise from the
+/// top left corner.
+pub fn outline(region: CanvasBounds, pixels: &[usize]) -> Vec<[u32; 2]> {
+    let width = region.width() as usize;
+    // The corners of the outermost pixels of every row span the same hull as all pixels.
+    let mut rows: BTreeMap<u32, (u32, u32)> = BTreeMap::new();
+    for &index in pixels {
+        let (x, y) = (
+            region.x1 + (index % width) as u32,
+            region.y1 + (index / width) as u32,
+        );
+        let row = rows.entry(y).or_insert((x, x));
+        *row = (row.0.min(x), row.1.max(x));
+    }
+    let mut points: Vec<[u32; 2]> = rows
+        .into_iter()
+        .flat_map(|(y, (left, right))| {
+            [[left, y], [left, y + 1], [right + 1, y], [right + 1, y + 1]]
+        })
+        .collect();
+    points.sort();
+    points.dedup();
+
+    let cross = |o: [u32; 2], a: [u32; 2], b: [u32; 2]| {
+        (a[0] as i64 - o[0] as i64) * (b[1] as i64 - o[1] as i64)
+            - (a[1] as i64 - o[1] as i64) * (b[0] as i64 - o[0] as i64)
+    };
+    let mut hull: Vec<[u32; 2]> = Vec::new();
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let start = hull.len();
+        for point in pass {
+            while hull.len() >= start + 2
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], point) <= 0
+            {
+                hull.pop();
+            }
+            hull.push(point);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// A likely artwork, followed through the segmentations it was found in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    /// Outline when the candidate was largest, in canvas coordinates.
+    pub outline: Vec<[u32; 2]>,
+    pub bounds: CanvasBounds,
+    /// Number of pixels when the candidate was largest.
+    pub pixels: usize,
+    /// When the last pixel of the first found segment changed, so the end of the burst that
+    /// built it.
+    pub built_ms: u32,
+    pub first_seen_ms: u32,
+    pub last_seen_ms: u32,
+}
+
+/// Candidates found in the region, with the canvas at the last segmentation.
+pub struct Detection {
+    pub region: CanvasBounds,
+    pub candidates: Vec<Candidate>,
+    /// Row-major index of the candidate every pixel belonged to at the last segmentation, plus
+    /// one. Zero for pixels outside of any candidate.
+    pub owners: Vec<u32>,
+    pub canvas: PaletteCanvas,
+    pub end_ms: u32,
+}
+
+impl Detection {
+    fn new(region: CanvasBounds) -> Self {
+        Self {
+            region,
+            candidates: Vec::new(),
+            owners: vec![0; region.area()],
+            canvas: PaletteCanvas::new(),
+            end_ms: 0,
+        }
+    }
+
+    /// Continues every candidate with the segment that mostly overlaps it, and starts new
+    /// candidates for the others.
+    fn update(&mut self, mut segments: Vec<Vec<usize>>, last_change: &[u32], now_ms: u32) {
+        let mut sizes: HashMap<u32, usize> = HashMap::new();
+        for &owner in self.owners.iter().filter(|&&owner| owner != 0) {
+            *sizes.entry(owner).or_default() += 1;
+        }
+        let mut owners = vec![0; self.owners.len()];
+        segments.sort_by_key(|pixels| Reverse(pixels.len()));
+        for pixels in segments {
+            let mut overlaps: HashMap<u32, usize> = HashMap::new();
+            for &index in &pixels {
+                if self.owners[index] != 0 {
+                    *overlaps.entry(self.owners[index]).or_default() += 1;
+                }
+            }
+            let continued = overlaps
+                .into_iter()
+                .filter(|&(owner, overlap)| {
+                    sizes.contains_key(&owner) && overlap * 2 >= pixels.len().min(sizes[&owner])
+                })
+                .max_by_key(|&(owner, overlap)| (overlap, Reverse(owner)))
+                .map(|(owner, _)| owner);
+            let owner = match continued {
+                Some(owner) => owner,
+                None => {
+                    self.candidates.push(Candidate {
+                        outline: Vec::new(),
+                        bounds: self.region,
+                        pixels: 0,
+                        built_ms: pixels
+                            .iter()
+                            .map(|&index| last_change[index])
+                            .max()
+                            .unwrap(),
+                        first_seen_ms: now_ms,
+                        last_seen_ms: now_ms,
+                    });
+                    self.candidates.len() as u32
+                }
+            };
+            // Every candidate continues in one segment at most.
+            sizes.remove(&owner);
+
+            let candidate = &mut self.candidates[owner as usize - 1];
+            candidate.last_seen_ms = now_ms;
+            if pixels.len() > candidate.pixels {
+                candidate.pixels = pixels.len();
+                candidate.outline = outline(self.region, &pixels);
+                candidate.bounds = CanvasBounds {
+                    x1: candidate
+                        .outline
+                        .iter()
+                        .map(|point| point[0])
+                        .min()
+                        .unwrap(),
+                    y1: candidate
+                        .outline
+                        .iter()
+                        .map(|point| point[1])
+                        .min()
+                        .unwrap(),
+                    x2: candidate
+                        .outline
+                        .iter()
+                        .map(|point| point[0])
+                        .max()
+                        .unwrap(),
+                    y2: candidate
+                        .outline
+                        .iter()
+                        .map(|point| point[1])
+                        .max()
+                        .unwrap(),
+                };
+            }
+            for index in pixels {
+                owners[index] = owner;
+            }
+        }
+        self.owners = owners;
+    }
+
+    /// Renders the canvas at the last segmentation with pixels outside of candidates dimmed, and
+    /// the bounding boxes of candidates in their color, or gray if they were gone by then.
+    pub fn render_overlay(&self) -> RgbImage {
+        let region = self.region;
+        let mut image = RgbImage::new(region.width(), region.height());
+        for y in region.y1..region.y2 {
+            for x in region.x1..region.x2 {
+                let color = PALETTE[self.canvas.get((x, y)) as usize];
+                let color = if self.owners[region.index_of((x, y)).unwrap()] == 0 {
+                    color.map(|c| c / 4 + 32)
+                } else {
+                    color
+                };
+                image.set((x - region.x1, y - region.y1), color);
+            }
+        }
+        for (index, candidate) in self.candidates.iter().enumerate() {
+            let color = if candidate.last_seen_ms == self.end_ms {
+                candidate_color(index)
+            } else {
+                [128; 3]
+            };
+            let bounds = candidate.bounds;
+            let (x1, y1) = (bounds.x1 - region.x1, bounds.y1 - region.y1);
+            let (x2, y2) = (bounds.x2 - region.x1 - 1, bounds.y2 - region.y1 - 1);
+            for x in x1..=x2 {
+                image.set((x, y1), color);
+                image.set((x, y2), color);
+            }
+            for y in y1..=y2 {
+                image.set((x1, y), color);
+                image.set((x2, y), color);
+            }
+        }
+        image
+    }
+}
+
+/// Spreads the colors of consecutive candidates over the ramp.
+fn candidate_color(index: usize) -> [u8; 3] {
+    ColorRamp::Viridis.sample((index as f32 * 0.618_034).fract())
+}
+
+/// Replays every update and segments the region at a fixed interval of event time during the
+/// window, and once more after the last update.
+pub fn detect_artworks(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    options: &SegmentationOptions,
+) -> Result<Detection, Whatever> {
+    ensure_whatever!(
+        options.interval_ms > 0,
+        "Interval must be greater than zero"
+    );
+    let region = options.region;
+    let mut detection = Detection::new(region);
+    let mut last_change = vec![NEVER; region.area()];
+    let mut sample_ms = options.window.start_ms as u64;
+    let mut sampled_ms = None;
+
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > options.window.end_ms {
+            break;
+        }
+        while ms as u64 > sample_ms {
+            let segments = find_segments(region, &last_change, sample_ms as u32, options);
+            detection.update(segments, &last_change, sample_ms as u32);
+            sampled_ms = Some(sample_ms as u32);
+            sample_ms += options.interval_ms as u64;
+        }
+        detection.end_ms = ms;
+        detection.canvas.apply_with(&pixel_data, |point, from, to| {
+            if from == to {
+                return;
+            }
+            if let Some(index) = region.index_of(point) {
+                last_change[index] = ms;
+            }
+        });
+    }
+    if sampled_ms != Some(detection.end_ms) {
+        let segments = find_segments(region, &last_change, detection.end_ms, options);
+        detection.update(segments, &last_change, detection.end_ms);
+    }
+    Ok(detection)
+}
+
+/// A candidate in the JSON output. The fields of Place Atlas entries come first, so the file
+/// loads as an atlas.
+#[derive(Serialize)]
+struct CandidateRecord {
+    id: usize,
+    name: String,
+    /// Outline in canvas coordinates.
+    path: Vec<[u32; 2]>,
+    /// Two opposite corners of the bounding box in dataset coordinates, as accepted by
+    /// `--region`.
+    region: String,
+    pixels: usize,
+    built: String,
+    built_ms: u32,
+    first_seen: String,
+    last_seen: String,
+    last_seen_ms: u32,
+    /// Time from the end of the building burst to the last segmentation the candidate was in.
+    lifetime_minutes: f64,
+}
+
+/// Writes the detected candidates as JSON and the overlay as PNG to `overlay_output` if given.
+pub fn write_detected_artworks(
+    data_path: &str,
+    options: &SegmentationOptions,
+    output: &str,
+    overlay_output: Option<&str>,
+) -> Result<(), Whatever> {
+    let updates = keyframe::updates_from(data_path, None, 0, options.region)?;
+    let detection = detect_artworks(updates, options)?;
+    ensure_whatever!(detection.end_ms > 0, "No updates in the time window");
+
+    if let Some(overlay_output) = overlay_output {
+        detection
+            .render_overlay()
+            .scaled(options.scale)
+            .write_png(overlay_output)?;
+    }
+    let records: Vec<_> = detection
+        .candidates
+        .iter()
+        .enumerate()
+        .map(|(index, candidate)| CandidateRecord {
+            id: index + 1,
+            name: format!("Candidate {}", index + 1),
+            path: candidate.outline.clone(),
+            region: candidate.bounds.to_string(),
+            pixels: candidate.pixels,
+            built: to_rfc3339(candidate.built_ms),
+            built_ms: candidate.built_ms,
+            first_seen: to_rfc3339(candidate.first_seen_ms),
+            last_seen: to_rfc3339(candidate.last_seen_ms),
+            last_seen_ms: candidate.last_seen_ms,
+            lifetime_minutes: (candidate.last_seen_ms - candidate.built_ms) as f64 / 60_000.0,
+        })
+        .collect();
+    let file =
+        File::create(output).with_whatever_context(|_| format!("Failed to create {output}"))?;
+    serde_json::to_writer_pretty(BufWriter::new(file), &records)
+        .with_whatever_context(|_| format!("Failed to write {output}"))?;
+
+    let alive = detection
+        .candidates
+        .iter()
+        .filter(|candidate| candidate.last_seen_ms == detection.end_ms)
+        .count();
+    println!(
+        "Found {} candidate artworks, {} of them still standing at {}",
+        records.len(),
+        alive,
+        to_utc(detection.end_ms)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_detect_artworks() {
+        use super::{detect_artworks, outline, SegmentationOptions};
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let options = SegmentationOptions {
+            window: TimeWindow::ALL,
+            region: "0,0,5,-1".parse().unwrap(),
+            interval_ms: 1000,
+            min_stable_ms: 1500,
+            burst_ms: 100,
+            min_pixels: 3,
+            scale: 1,
+        };
+        let pixel = |miliseconds_since_first_pixel, x, y, r| {
+            Ok(PixelData {
+                miliseconds_since_first_pixel,
+                coordinate: Coordinate::Simple { x, y },
+                pixel_color: PixelColor { r, g: 0, b: 0 },
+                user: 0,
+            })
+        };
+        // Two artworks side by side, built a second apart, and a lone pixel.
+        let mut pixels = Vec::new();
+        for (x, y) in [(0, 0), (1, 0), (0, -1), (1, -1)] {
+            pixels.push(pixel(100 + x as u32 * 10, x, y, 0));
+        }
+        for (x, y) in [(2, 0), (3, 0), (3, -1)] {
+            pixels.push(pixel(1100 + x as u32 * 10, x, y, 0));
+        }
+        pixels.push(pixel(1200, 5, 0, 0));
+        // The first artwork is overwritten in part, which leaves too few pixels.
+        pixels.push(pixel(3500, 0, 0, 255));
+        pixels.push(pixel(3500, 1, -1, 255));
+        pixels.push(pixel(4000, 5, -1, 0));
+
+        let detection = detect_artworks(pixels.into_iter(), &options).unwrap();
+        let candidates = &detection.candidates;
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].pixels, 4);
+        assert_eq!(
+            (
+                candidates[0].built_ms,
+                candidates[0].first_seen_ms,
+                candidates[0].last_seen_ms
+            ),
+            (110, 2000, 3000)
+        );
+        assert_eq!(
+            candidates[0].outline,
+            [[1500, 999], [1502, 999], [1502, 1001], [1500, 1001]]
+        );
+        assert_eq!(
+            candidates[0].bounds,
+            CanvasBounds {
+                x1: 1500,
+                y1: 999,
+                x2: 1502,
+                y2: 1001
+            }
+        );
+        assert_eq!(
+            (
+                candidates[1].built_ms,
+                candidates[1].first_seen_ms,
+                candidates[1].last_seen_ms
+            ),
+            (1130, 3000, 4000)
+        );
+        assert_eq!(
+            detection.owners.iter().filter(|&&owner| owner == 2).count(),
+            3
+        );
+
+        // An L shape is outlined by its hull.
+        let region = options.region;
+        let hull = outline(region, &[0, 1, 2, 6]);
+        assert_eq!(
+            hull,
+            [
+                [1500, 999],
+                [1503, 999],
+                [1503, 1000],
+                [1501, 1001],
+                [1500, 1001]
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+use snafu::{prelude::*, Whatever};
+
+use super::TimeWindow;
+use crate::{
+    bounds::CanvasBounds,
+    data::{to_utc, PixelData},
+    export::{write_npy, RgbImage},
+    keyframe,
+    palette::PaletteCanvas,
+    ramp::{normalize, ColorRamp},
+    tiles::Tiles,
+};
+
+/// How settled every pixel of a region is on the canvas at the end of a time window.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StabilityMap {
+    pub region: CanvasBounds,
+    /// End of the time window, or the last update of the dataset if it ends earlier. The held
+    /// times count up to it.
+    pub end_ms: u32,
+    /// Row-major time every pixel held its color at the end. Pixels that never changed count from
+    /// the start of the event.
+    pub held_ms: Vec<u32>,
+    /// Row-major number of times every pixel changed its color during the time window.
+    pub changes: Vec<u32>,
+}
+
+impl StabilityMap {
+    /// Renders how long every pixel held its final color, the longest with the hottest color.
+    pub fn render_held(&self, ramp: ColorRamp, log_scale: bool) -> RgbImage {
+        Self::render(self.region, &self.held_ms, ramp, log_scale)
+    }
+
+    /// Renders the number of changes of every pixel, the most changed with the hottest color.
+    pub fn render_changes(&self, ramp: ColorRamp, log_scale: bool) -> RgbImage {
+        Self::render(self.region, &self.changes, ramp, log_scale)
+    }
+
+    fn render(region: CanvasBounds, data: &[u32], ramp: ColorRamp, log_scale: bool) -> RgbImage {
+        let max = data.iter().copied().max().unwrap_or(0) as f64;
+        let values: Vec<f32> = data
+            .iter()
+            .map(|&value| normalize(value as f64, max, log_scale))
+            .collect();
+        ramp.render(region.width(), region.height(), &values)
+    }
+}
+
+/// Replays every update until the end of the time window and measures the stability of the
+/// region on the canvas at that time. Placing the color a pixel already has doesn't count as a
+/// change. Updates before the window are replayed too, so held times can start before it.
+///
+/// `dataset_end_ms` is the time of the last update of the dataset if `iter` may stop before it,
+/// like a tile query of the region. Otherwise the last update of `iter` is the end of the data.
+pub fn stability_map(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    region: CanvasBounds,
+    window: TimeWindow,
+    dataset_end_ms: Option<u32>,
+) -> Result<StabilityMap, Whatever> {
+    let mut canvas = PaletteCanvas::new();
+    let mut last_change = vec![0u32; region.area()];
+    let mut changes = vec![0u32; region.area()];
+    let mut end_ms = 0;
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        end_ms = ms;
+        canvas.apply_with(&pixel_data, |point, from, to| {
+            if from == to {
+                return;
+            }
+            if let Some(index) = region.index_of(point) {
+                last_change[index] = ms;
+                if ms >= window.start_ms {
+                    changes[index] += 1;
+                }
+            }
+        });
+    }
+    let end_ms = end_ms.max(dataset_end_ms.unwrap_or(0)).min(window.end_ms);
+    Ok(StabilityMap {
+        region,
+        end_ms,
+        held_ms: last_change.iter().map(|&ms| end_ms - ms).collect(),
+        changes,
+    })
+}
+
+pub struct StabilityOptions {
+    pub window: TimeWindow,
+    pub region: CanvasBounds,
+    pub ramp: ColorRamp,
+    pub log_scale: bool,
+    pub scale: u32,
+}
+
+/// Writes how long every pixel held its final color as PNG, and the number of changes of every
+/// pixel as PNG to `changes_output` if given. The raw values are written as `(height, width)`
+/// `.npy` arrays of `u32` to `raw_held_output` and `raw_changes_output` if given.
+pub fn write_stability_map(
+    data_path: &str,
+    options: &StabilityOptions,
+    output: &str,
+    changes_output: Option<&str>,
+    raw_held_output: Option<&str>,
+    raw_changes_output: Option<&str>,
+) -> Result<(), Whatever> {
+    // With tiles only the updates overlapping the region are read, so the end of the dataset
+    // comes from the tile index.
+    let dataset_end_ms = match Tiles::open_for(data_path)? {
+        Some(tiles) => Some(tiles.index().end_ms.whatever_context(
+            "Tiles were built without the end of the dataset, rebuild them with `tiles`",
+        )?),
+        None => None,
+    };
+    let updates = keyframe::updates_from(data_path, None, 0, options.region)?;
+    let map = stability_map(updates, options.region, options.window, dataset_end_ms)?;
+    ensure_whatever!(map.end_ms > 0, "No updates in the dataset");
+
+    map.render_held(options.ramp, options.log_scale)
+        .scaled(options.scale)
+        .write_png(output)?;
+    if let Some(changes_output) = changes_output {
+        map.render_changes(options.ramp, options.log_scale)
+            .scaled(options.scale)
+            .write_png(changes_output)?;
+    }
+    let shape = [
+        options.region.height() as usize,
+        options.region.width() as usize,
+    ];
+    if let Some(raw_held_output) = raw_held_output {
+        write_npy(raw_held_output, &shape, &map.held_ms)?;
+    }
+    if let Some(raw_changes_output) = raw_changes_output {
+        write_npy(raw_changes_output, &shape, &map.changes)?;
+    }
+
+    let hour = 3_600_000;
+    let last_hour = map.held_ms.iter().filter(|&&held| held < hour).count();
+    println!(
+        "Canvas at {}: {} of {} pixels changed in the last hour, most changed pixel: {}",
+        to_utc(map.end_ms),
+        last_hour,
+        map.held_ms.len(),
+        map.changes.iter().copied().max().unwrap_or(0)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_stability_map() {
+        use super::stability_map;
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, r| {
+            Ok(PixelData {
+                miliseconds_since_first_pixel,
+                coordinate: Coordinate::Simple { x, y: 0 },
+                pixel_color: PixelColor { r, g: 0, b: 0 },
+                user: 0,
+            })
+        };
+        let pixels = || {
+            vec![
+                pixel(100, 0, 0),
+                pixel(200, 1, 0),
+                pixel(300, 0, 255),
+                // Same color again, so the pixel doesn't change.
+                pixel(400, 1, 0),
+                pixel(1000, 5, 0),
+            ]
+        };
+        let region: CanvasBounds = "0,0,2,0".parse().unwrap();
+        let map = stability_map(pixels().into_iter(), region, TimeWindow::ALL, None).unwrap();
+
+        assert_eq!(map.end_ms, 1000);
+        assert_eq!(map.held_ms, vec![700, 800, 1000]);
+        assert_eq!(map.changes, vec![2, 1, 0]);
+
+        // A tile query of the region stops at its last update, before the end of the dataset.
+        let in_region = pixels().into_iter().take(4);
+        let map = stability_map(in_region, region, TimeWindow::ALL, Some(1000)).unwrap();
+        assert_eq!(map.end_ms, 1000);
+        assert_eq!(map.held_ms, vec![700, 800, 1000]);
+
+        // The canvas right before (0, 0) turned red, counting the changes from 150ms on.
+        let window = TimeWindow {
+            start_ms: 150,
+            end_ms: 299,
+        };
+        let map = stability_map(pixels().into_iter(), region, window, Some(1000)).unwrap();
+        assert_eq!(map.end_ms, 299);
+        assert_eq!(map.held_ms, vec![199, 99, 299]);
+        assert_eq!(map.changes, vec![0, 1, 0]);
+    }
+}
@@ -0,0 +1,403 @@
+use std::{fs::File, io::BufWriter, str::FromStr};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{to_canvas_coords, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{to_rfc3339, PixelData},
+    keyframe,
+    palette::{palette_index, PaletteCanvas},
+};
+
+/// Position of the top left pixel of a template, `x,y` in dataset coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    pub x: i16,
+    pub y: i16,
+}
+
+impl Anchor {
+    /// The top left corner of the canvas.
+    pub const TOP_LEFT: Anchor = Anchor { x: -1500, y: 999 };
+}
+
+impl FromStr for Anchor {
+    type Err = Box<dyn std::error::Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (x, y) = s.split_once(',').ok_or("Expected x,y")?;
+        Ok(Anchor {
+            x: x.trim().parse()?,
+            y: y.trim().parse()?,
+        })
+    }
+}
+
+/// The colors a community wanted in a region of the canvas.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    pub region: CanvasBounds,
+    /// Row-major palette index of every pixel of the region, `None` where any color will do.
+    pub colors: Vec<Option<u8>>,
+}
+
+impl Template {
+    /// Builds a template from row-major RGBA pixels. Mostly transparent pixels are left out and
+    /// colors outside of the palette are matched to the closest palette color.
+    pub fn from_rgba(
+        anchor: Anchor,
+        (width, height): (u32, u32),
+        pixels: &[[u8; 4]],
+    ) -> Result<Self, Whatever> {
+        let Some((x, y)) = to_canvas_coords((anchor.x, anchor.y)) else {
+            whatever!("Anchor is outside the canvas");
+        };
+        ensure_whatever!(
+            width > 0 && height > 0 && x + width <= CANVAS_WIDTH && y + height <= CANVAS_HEIGHT,
+            "Template of {width}x{height} doesn't fit on the canvas at the anchor"
+        );
+        let colors = pixels
+            .iter()
+            .map(|&[r, g, b, a]| (a >= 128).then(|| palette_index([r, g, b])))
+            .collect();
+        Ok(Self {
+            region: CanvasBounds {
+                x1: x,
+                y1: y,
+                x2: x + width,
+                y2: y + height,
+            },
+            colors,
+        })
+    }
+
+    /// Loads a template PNG placed with its top left pixel at the anchor.
+    pub fn load(path: &str, anchor: Anchor) -> Result<Self, Whatever> {
+        let file = File::open(path).with_whatever_context(|_| format!("Failed to open {path}"))?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder
+            .read_info()
+            .with_whatever_context(|_| format!("Failed to read {path}"))?;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buffer)
+            .with_whatever_context(|_| format!("Failed to decode {path}"))?;
+        let buffer = &buffer[..info.buffer_size()];
+        let pixels: Vec<[u8; 4]> = match info.color_type {
+            png::ColorType::Rgba => buffer
+                .chunks_exact(4)
+                .map(|p| [p[0], p[1], p[2], p[3]])
+                .collect(),
+            png::ColorType::Rgb => buffer
+                .chunks_exact(3)
+                .map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => buffer
+                .chunks_exact(2)
+                .map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            png::ColorType::Grayscale => buffer.iter().map(|&v| [v, v, v, 255]).collect(),
+            png::ColorType::Indexed => whatever!("Palette of {path} wasn't expanded"),
+        };
+        Self::from_rgba(anchor, (info.width, info.height), &pixels)
+    }
+
+    /// Returns the template placed with its top left pixel at the canvas point instead.
+    pub fn moved_to(&self, (x, y): (u32, u32)) -> Template {
+        Template {
+            region: CanvasBounds {
+                x1: x,
+                y1: y,
+                x2: x + self.region.width(),
+                y2: y + self.region.height(),
+            },
+            colors: self.colors.clone(),
+        }
+    }
+
+    /// Number of pixels with a wanted color.
+    pub fn len(&self) -> usize {
+        self.colors.iter().flatten().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the wanted color of a canvas pixel, or `None` if the template doesn't care.
+    pub fn get(&self, point: (u32, u32)) -> Option<u8> {
+        self.region
+            .index_of(point)
+            .and_then(|index| self.colors[index])
+    }
+
+    /// Counts the template pixels the canvas matches.
+    pub fn matching(&self, canvas: &PaletteCanvas) -> usize {
+        (self.region.y1..self.region.y2)
+            .flat_map(|y| (self.region.x1..self.region.x2).map(move |x| (x, y)))
+            .filter(|&point| self.get(point) == Some(canvas.get(point)))
+            .count()
+    }
+}
+
+/// A span during which a large part of the template was destroyed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Attack {
+    /// Last time before the attack the template was at its best.
+    pub start_ms: u32,
+    /// Time the template recovered, `None` if it never did.
+    pub end_ms: Option<u32>,
+    /// Matching pixels lost at the worst point of the attack.
+    pub pixels_lost: usize,
+}
+
+/// Number of matching template pixels over time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateProgress {
+    /// Matching pixels at every sample time.
+    pub samples: Vec<(u32, usize)>,
+    /// Time all template pixels first matched.
+    pub first_complete_ms: Option<u32>,
+    pub attacks: Vec<Attack>,
+}
+
+/// Replays the updates from `canvas` and samples the matching template pixels every
+/// `interval_ms` of the time window. An attack starts when the template drops `attack_pixels`
+/// below its best since the last attack, and ends once it is back within half of that.
+pub fn track_template(
+    mut canvas: PaletteCanvas,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    template: &Template,
+    window: TimeWindow,
+    interval_ms: u32,
+    attack_pixels: usize,
+) -> Result<TemplateProgress, Whatever> {
+    ensure_whatever!(interval_ms > 0, "Interval must be greater than zero");
+    let attack_pixels = attack_pixels.max(1);
+    let total = template.len();
+    let mut progress = TemplateProgress {
+        samples: Vec::new(),
+        first_complete_ms: None,
+        attacks: Vec::new(),
+    };
+
+    let mut matching = None;
+    let mut sample_ms = window.start_ms as u64;
+    let (mut best, mut best_ms) = (0, window.start_ms);
+    let mut attack: Option<Attack> = None;
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        if ms < window.start_ms {
+            canvas.apply_with(&pixel_data, |_, _, _| {});
+            continue;
+        }
+        let matching = matching.get_or_insert_with(|| {
+            let matching = template.matching(&canvas);
+            (best, best_ms) = (matching, window.start_ms);
+            matching
+        });
+        while ms as u64 > sample_ms {
+            progress.samples.push((sample_ms as u32, *matching));
+            sample_ms += interval_ms as u64;
+        }
+
+        canvas.apply_with(&pixel_data, |point, from, to| {
+            if let Some(color) = template.get(point) {
+                *matching = *matching + (to == color) as usize - (from == color) as usize;
+            }
+        });
+
+        if *matching == total && progress.first_complete_ms.is_none() {
+            progress.first_complete_ms = Some(ms);
+        }
+        match &mut attack {
+            Some(current) => {
+                current.pixels_lost = current.pixels_lost.max(best.saturating_sub(*matching));
+                if *matching + attack_pixels / 2 >= best {
+                    current.end_ms = Some(ms);
+                    progress.attacks.extend(attack.take());
+                    (best, best_ms) = (*matching, ms);
+                }
+            }
+            None if *matching >= best => (best, best_ms) = (*matching, ms),
+            None if *matching + attack_pixels <= best => {
+                attack = Some(Attack {
+                    start_ms: best_ms,
+                    end_ms: None,
+                    pixels_lost: best - *matching,
+                });
+            }
+            None => {}
+        }
+    }
+    progress.attacks.extend(attack);
+    let matching = matching.unwrap_or_else(|| template.matching(&canvas));
+    if sample_ms <= window.end_ms as u64 {
+        progress.samples.push((sample_ms as u32, matching));
+    }
+    Ok(progress)
+}
+
+pub struct TemplateOptions {
+    pub window: TimeWindow,
+    pub interval_ms: u32,
+    /// Share of the template pixels an attack has to destroy, in percent.
+    pub attack_percent: f64,
+}
+
+/// A row of the template progress table.
+#[derive(Serialize)]
+struct TemplateRecord {
+    time: String,
+    miliseconds_since_first_pixel: u32,
+    matching: usize,
+    percent: f64,
+}
+
+/// Summary of the template progress, written as JSON.
+#[derive(Serialize)]
+struct TemplateReport {
+    pixels: usize,
+    first_complete: Option<String>,
+    first_complete_ms: Option<u32>,
+    attacks: Vec<Attack>,
+}
+
+/// Writes the share of matching template pixels over time as a table, and the first completion
+/// and attacks as JSON to `report_output` if given.
+pub fn write_template_progress(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    template: &Template,
+    options: &TemplateOptions,
+    format: TableFormat,
+    output: &str,
+    report_output: Option<&str>,
+) -> Result<(), Whatever> {
+    ensure_whatever!(!template.is_empty(), "Template has no opaque pixels");
+    let (canvas, updates) = keyframe::replay_from(
+        data_path,
+        keyframes_dir,
+        options.window.start_ms,
+        template.region,
+    )?;
+    let attack_pixels = (template.len() as f64 * options.attack_percent / 100.0).ceil() as usize;
+    let progress = track_template(
+        PaletteCanvas::from_canvas(&canvas),
+        updates,
+        template,
+        options.window,
+        options.interval_ms,
+        attack_pixels,
+    )?;
+
+    let percent = |matching: usize| matching as f64 * 100.0 / template.len() as f64;
+    let records: Vec<_> = progress
+        .samples
+        .iter()
+        .map(|&(ms, matching)| TemplateRecord {
+            time: to_rfc3339(ms),
+            miliseconds_since_first_pixel: ms,
+            matching,
+            percent: percent(matching),
+        })
+        .collect();
+    write_records(&records, format, output)?;
+
+    match progress.first_complete_ms {
+        Some(ms) => println!("First complete at {}", to_rfc3339(ms)),
+        None => println!("Never complete"),
+    }
+    for attack in &progress.attacks {
+        println!(
+            "Attack from {} to {}: {} pixels ({:.1}%) lost",
+            to_rfc3339(attack.start_ms),
+            attack.end_ms.map_or("the end".to_string(), to_rfc3339),
+            attack.pixels_lost,
+            percent(attack.pixels_lost)
+        );
+    }
+    if let Some(report_output) = report_output {
+        let report = TemplateReport {
+            pixels: template.len(),
+            first_complete: progress.first_complete_ms.map(to_rfc3339),
+            first_complete_ms: progress.first_complete_ms,
+            attacks: progress.attacks,
+        };
+        let file = File::create(report_output)
+            .with_whatever_context(|_| format!("Failed to create {report_output}"))?;
+        serde_json::to_writer_pretty(BufWriter::new(file), &report)
+            .whatever_context("Failed to write report")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_track_template() {
+        use super::{track_template, Anchor, Template};
+        use crate::{
+            analysis::TimeWindow,
+            data::{Coordinate, PixelColor, PixelData},
+            palette::PaletteCanvas,
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, r| {
+            Ok(PixelData {
+                miliseconds_since_first_pixel,
+                coordinate: Coordinate::Simple { x, y: 0 },
+                pixel_color: PixelColor { r, g: 0, b: 0 },
+                user: 0,
+            })
+        };
+        // Four black pixels, the third one transparent.
+        let black = [0, 0, 0, 255];
+        let template = Template::from_rgba(
+            Anchor { x: 0, y: 0 },
+            (4, 1),
+            &[black, black, [255, 255, 255, 0], black],
+        )
+        .unwrap();
+        assert_eq!(template.len(), 3);
+
+        let pixels = vec![
+            pixel(10, 0, 0),
+            pixel(20, 1, 0),
+            pixel(25, 2, 255),
+            pixel(30, 3, 0),
+            pixel(40, 0, 255),
+            pixel(45, 1, 255),
+            pixel(50, 0, 0),
+            pixel(60, 1, 0),
+            pixel(70, 3, 255),
+        ];
+        let progress = track_template(
+            PaletteCanvas::new(),
+            pixels.into_iter(),
+            &template,
+            TimeWindow::ALL,
+            20,
+            2,
+        )
+        .unwrap();
+
+        assert_eq!(
+            progress.samples,
+            vec![(0, 0), (20, 2), (40, 2), (60, 3), (80, 2)]
+        );
+        assert_eq!(progress.first_complete_ms, Some(30));
+        assert_eq!(progress.attacks.len(), 1);
+        assert_eq!(progress.attacks[0].start_ms, 30);
+        assert_eq!(progress.attacks[0].end_ms, Some(50));
+        assert_eq!(progress.attacks[0].pixels_lost, 2);
+    }
+}
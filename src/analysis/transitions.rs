@@ -0,0 +1,242 @@
+use std::path::Path;
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    bounds::CanvasBounds,
+    data::PixelData,
+    export::RgbImage,
+    keyframe,
+    palette::{hex, PaletteCanvas, PALETTE},
+    ramp::{normalize, ColorRamp},
+};
+
+const COLORS: usize = PALETTE.len();
+
+/// Number of times a pixel in a region changed from one palette color to another. Placing the
+/// color a pixel already has isn't a transition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionMatrix {
+    pub region: CanvasBounds,
+    /// Row-major counts, with a row for every previous color and a column for every new color.
+    pub counts: Vec<u64>,
+}
+
+impl TransitionMatrix {
+    pub fn new(region: CanvasBounds) -> Self {
+        Self {
+            region,
+            counts: vec![0; COLORS * COLORS],
+        }
+    }
+
+    pub fn get(&self, from: u8, to: u8) -> u64 {
+        self.counts[from as usize * COLORS + to as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Renders the matrix with a cell for every transition, previous colors as rows and new
+    /// colors as columns. The first row and column show the palette colors.
+    pub fn render(&self, ramp: ColorRamp, log_scale: bool) -> RgbImage {
+        let max = self.counts.iter().copied().max().unwrap_or(0) as f64;
+        let size = COLORS as u32 + 1;
+        let mut image = RgbImage::new(size, size);
+        for (i, &color) in PALETTE.iter().enumerate() {
+            image.set((i as u32 + 1, 0), color);
+            image.set((0, i as u32 + 1), color);
+        }
+        for from in 0..COLORS as u8 {
+            for to in 0..COLORS as u8 {
+                let value = normalize(self.get(from, to) as f64, max, log_scale);
+                image.set((to as u32 + 1, from as u32 + 1), ramp.sample(value));
+            }
+        }
+        image
+    }
+}
+
+/// Replays the updates from `canvas` and counts the transitions during the time window in every
+/// region. Updates before the window only update the canvas.
+pub fn count_transitions(
+    mut canvas: PaletteCanvas,
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    window: TimeWindow,
+    regions: &[CanvasBounds],
+) -> Result<Vec<TransitionMatrix>, Whatever> {
+    let mut matrices: Vec<_> = regions.iter().copied().map(TransitionMatrix::new).collect();
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        let ms = pixel_data.miliseconds_since_first_pixel;
+        if ms > window.end_ms {
+            break;
+        }
+        let counted = window.contains(ms);
+        canvas.apply_with(&pixel_data, |point, from, to| {
+            if !counted || from == to {
+                return;
+            }
+            for matrix in &mut matrices {
+                if matrix.region.contains(point) {
+                    matrix.counts[from as usize * COLORS + to as usize] += 1;
+                }
+            }
+        });
+    }
+    Ok(matrices)
+}
+
+pub struct TransitionOptions {
+    pub window: TimeWindow,
+    /// A matrix is counted for every region.
+    pub regions: Vec<CanvasBounds>,
+    pub ramp: ColorRamp,
+    pub log_scale: bool,
+    pub scale: u32,
+}
+
+/// A row of the transition table.
+#[derive(Serialize)]
+struct TransitionRecord {
+    /// Position of the region in the given regions.
+    region: usize,
+    from: String,
+    to: String,
+    count: u64,
+}
+
+/// Returns the path of the image of region `index`, numbered if there is more than one region.
+fn image_path(image_output: &str, index: usize, regions: usize) -> String {
+    if regions == 1 {
+        return image_output.to_string();
+    }
+    let path = Path::new(image_output);
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("");
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("png");
+    path.with_file_name(format!("{stem}-{index}.{extension}"))
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Writes the transitions of every region as a table with a row per region and color pair, and
+/// the matrices as images to `image_output` if given. Images are numbered by region if there is
+/// more than one.
+pub fn write_transitions(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &TransitionOptions,
+    format: TableFormat,
+    output: &str,
+    image_output: Option<&str>,
+) -> Result<(), Whatever> {
+    ensure_whatever!(!options.regions.is_empty(), "No regions given");
+    // The updates have to cover every region.
+    let covering = options
+        .regions
+        .iter()
+        .copied()
+        .reduce(|a, b| a.union(&b))
+        .unwrap();
+    let (canvas, updates) =
+        keyframe::replay_from(data_path, keyframes_dir, options.window.start_ms, covering)?;
+    let matrices = count_transitions(
+        PaletteCanvas::from_canvas(&canvas),
+        updates,
+        options.window,
+        &options.regions,
+    )?;
+
+    let mut records = Vec::with_capacity(matrices.len() * COLORS * COLORS);
+    for (region, matrix) in matrices.iter().enumerate() {
+        for from in 0..COLORS as u8 {
+            for to in 0..COLORS as u8 {
+                records.push(TransitionRecord {
+                    region,
+                    from: hex(from),
+                    to: hex(to),
+                    count: matrix.get(from, to),
+                });
+            }
+        }
+    }
+    write_records(&records, format, output)?;
+
+    if let Some(image_output) = image_output {
+        for (index, matrix) in matrices.iter().enumerate() {
+            matrix
+                .render(options.ramp, options.log_scale)
+                .scaled(options.scale)
+                .write_png(&image_path(image_output, index, matrices.len()))?;
+        }
+    }
+    for (index, matrix) in matrices.iter().enumerate() {
+        println!("Region {}: {} transitions", index, matrix.total());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_count_transitions() {
+        use super::count_transitions;
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+            palette::{PaletteCanvas, PALETTE, WHITE},
+        };
+
+        let pixel = |miliseconds_since_first_pixel, x, color: u8| {
+            let [r, g, b] = PALETTE[color as usize];
+            Ok(PixelData {
+                miliseconds_since_first_pixel,
+                coordinate: Coordinate::Simple { x, y: 0 },
+                pixel_color: PixelColor { r, g, b },
+                user: 0,
+            })
+        };
+        let pixels = vec![
+            pixel(0, 0, 2),
+            pixel(10, 0, 3),
+            pixel(20, 0, 3),
+            pixel(30, 1, 2),
+            pixel(40, 0, WHITE),
+            pixel(50, 1, 3),
+        ];
+        let regions = [
+            "0,0,0,0".parse::<CanvasBounds>().unwrap(),
+            CanvasBounds::FULL,
+        ];
+        let window = TimeWindow {
+            start_ms: 10,
+            end_ms: 40,
+        };
+        let matrices =
+            count_transitions(PaletteCanvas::new(), pixels.into_iter(), window, &regions).unwrap();
+
+        assert_eq!(matrices[0].get(2, 3), 1);
+        assert_eq!(matrices[0].get(3, WHITE), 1);
+        assert_eq!(matrices[0].total(), 2);
+        assert_eq!(matrices[1].get(WHITE, 2), 1);
+        assert_eq!(matrices[1].total(), 3);
+    }
+
+    #[test]
+    fn test_image_path() {
+        use super::image_path;
+
+        assert_eq!(image_path("out/matrix.png", 0, 1), "out/matrix.png");
+        assert_eq!(image_path("out/matrix.png", 1, 2), "out/matrix-1.png");
+    }
+}
@@ -0,0 +1,302 @@
+use std::{cmp::Reverse, collections::BTreeMap};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use super::{write_records, TableFormat, TimeWindow};
+use crate::{
+    data::{to_rfc3339, Coordinate, PixelData},
+    palette::{hex, PALETTE},
+    parse::GzippedBinPixelDataReader,
+    user_table::UserTable,
+};
+
+/// A value of every update during a time window, grouped by user id in time order. Shape fills
+/// count once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UserUpdates<T> {
+    pub placements: Vec<u32>,
+    pub first_ms: Vec<u32>,
+    pub last_ms: Vec<u32>,
+    /// Start of the values of every user in `values`, and the end of the last.
+    offsets: Vec<u64>,
+    values: Vec<T>,
+}
+
+impl<T> UserUpdates<T> {
+    pub fn users(&self) -> usize {
+        self.placements.len()
+    }
+
+    /// Returns the values of the updates of a user.
+    pub fn of(&self, user: u32) -> &[T] {
+        let user = user as usize;
+        &self.values[self.offsets[user] as usize..self.offsets[user + 1] as usize]
+    }
+
+    /// Returns the number of users by their number of updates, leaving out users without any.
+    pub fn distribution(&self) -> BTreeMap<u32, u64> {
+        let mut distribution = BTreeMap::new();
+        for &placements in self.placements.iter().filter(|&&placements| placements > 0) {
+            *distribution.entry(placements).or_default() += 1;
+        }
+        distribution
+    }
+
+    /// Returns the users with updates, the most active first.
+    pub fn ranking(&self) -> Vec<u32> {
+        let mut users: Vec<u32> = (0..self.users() as u32)
+            .filter(|&user| self.placements[user as usize] > 0)
+            .collect();
+        users.sort_by_key(|&user| Reverse(self.placements[user as usize]));
+        users
+    }
+}
+
+impl UserUpdates<u8> {
+    /// Returns the most used palette colors of a user with their number of updates, the most
+    /// used first.
+    pub fn top_colors(&self, user: u32, count: usize) -> Vec<(u8, u32)> {
+        let mut counts = [0u32; PALETTE.len()];
+        for &color in self.of(user) {
+            counts[color as usize] += 1;
+        }
+        let mut colors: Vec<_> = (0..PALETTE.len() as u8)
+            .map(|color| (color, counts[color as usize]))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        colors.sort_by_key(|&(color, count)| (Reverse(count), color));
+        colors.truncate(count);
+        colors
+    }
+}
+
+/// Groups `value` of every update during the window by user. The updates are read once to count
+/// them and once more to fill in the values, so the values take no more memory than needed.
+pub fn group_by_user<T: Copy + Default, I: Iterator<Item = Result<PixelData, Whatever>>>(
+    mut updates: impl FnMut() -> Result<I, Whatever>,
+    window: TimeWindow,
+    value: impl Fn(&PixelData) -> T,
+) -> Result<UserUpdates<T>, Whatever> {
+    let for_each_update = |iter: I, f: &mut dyn FnMut(&PixelData)| -> Result<(), Whatever> {
+        for pixel_data in iter {
+            let pixel_data = pixel_data?;
+            let ms = pixel_data.miliseconds_since_first_pixel;
+            if ms > window.end_ms {
+                break;
+            }
+            if window.contains(ms) {
+                f(&pixel_data);
+            }
+        }
+        Ok(())
+    };
+
+    let (mut placements, mut first_ms, mut last_ms) = (Vec::new(), Vec::new(), Vec::new());
+    for_each_update(updates()?, &mut |pixel_data| {
+        let (user, ms) = (
+            pixel_data.user as usize,
+            pixel_data.miliseconds_since_first_pixel,
+        );
+        if user >= placements.len() {
+            placements.resize(user + 1, 0);
+            first_ms.resize(user + 1, u32::MAX);
+            last_ms.resize(user + 1, 0);
+        }
+        placements[user] += 1;
+        first_ms[user] = first_ms[user].min(ms);
+        last_ms[user] = last_ms[user].max(ms);
+    })?;
+
+    let mut offsets = Vec::with_capacity(placements.len() + 1);
+    let mut total = 0u64;
+    offsets.push(0);
+    for &count in &placements {
+        total += count as u64;
+        offsets.push(total);
+    }
+    let mut values = vec![T::default(); total as usize];
+    let mut cursors = offsets[..placements.len()].to_vec();
+    for_each_update(updates()?, &mut |pixel_data| {
+        let cursor = &mut cursors[pixel_data.user as usize];
+        values[*cursor as usize] = value(pixel_data);
+        *cursor += 1;
+    })?;
+
+    Ok(UserUpdates {
+        placements,
+        first_ms,
+        last_ms,
+        offsets,
+        values,
+    })
+}
+
+/// Whether the update is a user placing a pixel rather than a moderator fill. Errors are kept so
+/// they still end the read.
+pub fn is_placement(pixel_data: &Result<PixelData, Whatever>) -> bool {
+    !matches!(
+        pixel_data,
+        Ok(PixelData {
+            coordinate: Coordinate::Rectangle { .. } | Coordinate::Circle { .. },
+            ..
+        })
+    )
+}
+
+/// Finds a user by the hash or id in the user table next to the dataset, or by the id if there
+/// is no table.
+pub fn find_user(data_path: &str, key: &str) -> Result<u32, Whatever> {
+    let user = match UserTable::load_for(data_path)? {
+        Some(users) => users.find(key),
+        None => key.parse().ok(),
+    };
+    user.with_whatever_context(|| format!("No user {key}"))
+}
+
+pub struct UserStatsOptions {
+    pub window: TimeWindow,
+    /// Number of the most used colors listed for every user.
+    pub top_colors: usize,
+    /// Number of the most active users printed.
+    pub top_users: usize,
+}
+
+/// A row of the user table.
+#[derive(Serialize)]
+struct UserRecord {
+    /// The user hash, or the user id if the dataset has no user table.
+    user: String,
+    id: u32,
+    placements: u32,
+    first: String,
+    last: String,
+    active_minutes: f64,
+    /// The most used colors with their number of updates, like `#FF4500:12 #000000:3`.
+    top_colors: String,
+}
+
+/// A row of the placement count distribution.
+#[derive(Serialize)]
+struct DistributionRecord {
+    placements: u32,
+    users: u64,
+}
+
+/// Writes the updates, active time span and most used colors of every user as a table, the most
+/// active first, and the number of users by their number of updates to `distribution_output` if
+/// given.
+pub fn write_user_stats(
+    data_path: &str,
+    options: &UserStatsOptions,
+    format: TableFormat,
+    output: &str,
+    distribution_output: Option<&str>,
+) -> Result<(), Whatever> {
+    let activity = group_by_user(
+        || GzippedBinPixelDataReader::new(data_path),
+        options.window,
+        |pixel_data| pixel_data.pixel_color.palette_index(),
+    )?;
+    let users = UserTable::load_for(data_path)?;
+    let ranking = activity.ranking();
+    ensure_whatever!(!ranking.is_empty(), "No updates in the time window");
+
+    let records: Vec<_> = ranking
+        .iter()
+        .map(|&user| {
+            let index = user as usize;
+            UserRecord {
+                user: UserTable::name(users.as_ref(), user),
+                id: user,
+                placements: activity.placements[index],
+                first: to_rfc3339(activity.first_ms[index]),
+                last: to_rfc3339(activity.last_ms[index]),
+                active_minutes: (activity.last_ms[index] - activity.first_ms[index]) as f64
+                    / 60_000.0,
+                top_colors: activity
+                    .top_colors(user, options.top_colors)
+                    .iter()
+                    .map(|&(color, count)| format!("{}:{}", hex(color), count))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            }
+        })
+        .collect();
+    write_records(&records, format, output)?;
+    if let Some(distribution_output) = distribution_output {
+        let distribution: Vec<_> = activity
+            .distribution()
+            .into_iter()
+            .map(|(placements, users)| DistributionRecord { placements, users })
+            .collect();
+        write_records(&distribution, format, distribution_output)?;
+    }
+
+    let total: u64 = records.iter().map(|record| record.placements as u64).sum();
+    println!(
+        "{} users placed {} updates, {:.1} on average",
+        records.len(),
+        total,
+        total as f64 / records.len() as f64
+    );
+    for (rank, record) in records.iter().take(options.top_users).enumerate() {
+        println!(
+            "{:>3}. {} with {} updates over {:.0} minutes",
+            rank + 1,
+            record.user,
+            record.placements,
+            record.active_minutes
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_group_by_user() {
+        use super::group_by_user;
+        use crate::{
+            analysis::TimeWindow,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let pixel = |miliseconds_since_first_pixel, user, r| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x: 0, y: 0 },
+            pixel_color: PixelColor { r, g: 0, b: 0 },
+            user,
+        };
+        let pixels = vec![
+            pixel(0, 2, 0),
+            pixel(10, 0, 255),
+            pixel(20, 2, 255),
+            pixel(30, 2, 0),
+            pixel(40, 2, 0),
+            pixel(50, 0, 0),
+        ];
+        let window = TimeWindow {
+            start_ms: 10,
+            end_ms: 40,
+        };
+        let activity = group_by_user(
+            || Ok(pixels.clone().into_iter().map(Ok)),
+            window,
+            |pixel_data| pixel_data.pixel_color.palette_index(),
+        )
+        .unwrap();
+
+        assert_eq!(activity.placements, [1, 0, 3]);
+        assert_eq!((activity.first_ms[2], activity.last_ms[2]), (20, 40));
+        assert_eq!(activity.of(2), [2, 27, 27]);
+        assert_eq!(activity.ranking(), [2, 0]);
+        // Black is palette index 27 and red is closest to #FF4500 at index 2.
+        assert_eq!(activity.top_colors(2, 5), [(27, 2), (2, 1)]);
+        assert_eq!(activity.top_colors(2, 1), [(27, 2)]);
+        assert_eq!(
+            activity.distribution().into_iter().collect::<Vec<_>>(),
+            [(1, 1), (3, 1)]
+        );
+    }
+}
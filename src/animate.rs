@@ -0,0 +1,205 @@
+use std::{borrow::Cow, fs::File, io::BufWriter};
+
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    bounds::CanvasBounds,
+    export::RgbImage,
+    palette::{palette_index, PALETTE},
+    timelapse::{for_each_frame, FrameRange},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AnimationFormat {
+    /// GIF using the r/place palette as its global color table.
+    Gif,
+    /// Animated PNG.
+    Apng,
+}
+
+pub struct AnimationOptions {
+    pub range: FrameRange,
+    pub region: CanvasBounds,
+    pub scale: u32,
+    pub format: AnimationFormat,
+    /// Display time of one frame of the range.
+    pub frame_delay_ms: u32,
+}
+
+/// A distinct frame and the number of consecutive frames of the range it stands for.
+struct AnimationFrame {
+    image: RgbImage,
+    repeats: u32,
+}
+
+/// Writes an animation of the region over the time range. Frames where nothing in the region
+/// changed are merged into the previous frame. All frames are kept in memory until they are
+/// encoded, so this is meant for small regions.
+///
+/// Returns the number of frames written.
+pub fn write_animation(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &AnimationOptions,
+    output: &str,
+) -> Result<usize, Whatever> {
+    let mut frames: Vec<AnimationFrame> = Vec::new();
+    for_each_frame(
+        data_path,
+        keyframes_dir,
+        options.range,
+        options.region,
+        |_, canvas| {
+            let image = RgbImage::from_canvas(canvas, options.region);
+            match frames.last_mut() {
+                Some(last) if last.image == image => last.repeats += 1,
+                _ => frames.push(AnimationFrame { image, repeats: 1 }),
+            }
+            Ok(())
+        },
+    )?;
+    ensure_whatever!(!frames.is_empty(), "No frames in the time range");
+
+    let file =
+        File::create(output).with_whatever_context(|_| format!("Failed to create {output}"))?;
+    let writer = BufWriter::new(file);
+    let scale = options.scale.max(1);
+    match options.format {
+        AnimationFormat::Gif => write_gif(writer, &frames, scale, options.frame_delay_ms)?,
+        AnimationFormat::Apng => write_apng(writer, &frames, scale, options.frame_delay_ms)?,
+    }
+    Ok(frames.len())
+}
+
+/// Returns the smallest rectangle `(left, top, width, height)` containing every pixel that
+/// differs between the two frames.
+fn changed_rect(previous: &[u8], current: &[u8], width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let (mut x1, mut y1, mut x2, mut y2) = (width, height, 0, 0);
+    for y in 0..height {
+        for x in 0..width {
+            let index = (y * width + x) as usize;
+            if previous[index] != current[index] {
+                (x1, y1) = (x1.min(x), y1.min(y));
+                (x2, y2) = (x2.max(x + 1), y2.max(y + 1));
+            }
+        }
+    }
+    if x1 >= x2 {
+        // Different colors can map to the same palette index.
+        return (0, 0, 1, 1);
+    }
+    (x1, y1, x2 - x1, y2 - y1)
+}
+
+fn write_gif(
+    writer: BufWriter<File>,
+    frames: &[AnimationFrame],
+    scale: u32,
+    frame_delay_ms: u32,
+) -> Result<(), Whatever> {
+    let (width, height) = (
+        frames[0].image.width * scale,
+        frames[0].image.height * scale,
+    );
+    ensure_whatever!(
+        width <= u16::MAX as u32 && height <= u16::MAX as u32,
+        "Animation is too large for GIF"
+    );
+    let palette: Vec<u8> = PALETTE.iter().flatten().copied().collect();
+    let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &palette)
+        .whatever_context("Failed to write GIF header")?;
+    encoder
+        .set_repeat(gif::Repeat::Infinite)
+        .whatever_context("Failed to write GIF header")?;
+
+    let mut previous: Option<Vec<u8>> = None;
+    for frame in frames {
+        let indices: Vec<u8> = frame
+            .image
+            .scaled(scale)
+            .pixels
+            .chunks_exact(3)
+            .map(|rgb| palette_index([rgb[0], rgb[1], rgb[2]]))
+            .collect();
+
+        // Only the changed part is encoded, the rest is kept from the previous frame.
+        let (left, top, rect_width, rect_height) = match &previous {
+            Some(previous) => changed_rect(previous, &indices, width, height),
+            None => (0, 0, width, height),
+        };
+        let buffer: Vec<u8> = (top..top + rect_height)
+            .flat_map(|y| {
+                let start = (y * width + left) as usize;
+                indices[start..start + rect_width as usize].iter().copied()
+            })
+            .collect();
+
+        let delay = (frame.repeats * frame_delay_ms / 10).min(u16::MAX as u32) as u16;
+        encoder
+            .write_frame(&gif::Frame {
+                left: left as u16,
+                top: top as u16,
+                width: rect_width as u16,
+                height: rect_height as u16,
+                delay,
+                dispose: gif::DisposalMethod::Keep,
+                buffer: Cow::Owned(buffer),
+                ..Default::default()
+            })
+            .whatever_context("Failed to write GIF frame")?;
+        previous = Some(indices);
+    }
+    Ok(())
+}
+
+fn write_apng(
+    writer: BufWriter<File>,
+    frames: &[AnimationFrame],
+    scale: u32,
+    frame_delay_ms: u32,
+) -> Result<(), Whatever> {
+    let (width, height) = (
+        frames[0].image.width * scale,
+        frames[0].image.height * scale,
+    );
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .set_animated(frames.len() as u32, 0)
+        .whatever_context("Failed to set up APNG")?;
+    let mut writer = encoder
+        .write_header()
+        .whatever_context("Failed to write PNG header")?;
+
+    for frame in frames {
+        let delay_ms = frame.repeats * frame_delay_ms;
+        let (numerator, denominator) = match u16::try_from(delay_ms) {
+            Ok(delay_ms) => (delay_ms, 1000),
+            Err(_) => ((delay_ms / 1000).min(u16::MAX as u32) as u16, 1),
+        };
+        writer
+            .set_frame_delay(numerator, denominator)
+            .whatever_context("Failed to set frame delay")?;
+        writer
+            .write_image_data(&frame.image.scaled(scale).pixels)
+            .whatever_context("Failed to write APNG frame")?;
+    }
+    writer.finish().whatever_context("Failed to finish APNG")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_changed_rect() {
+        use super::changed_rect;
+
+        let previous = vec![0; 12];
+        let mut current = previous.clone();
+        current[5] = 1;
+        current[10] = 1;
+        // 4x3 frame, changes at (1, 1) and (2, 2)
+        assert_eq!(changed_rect(&previous, &current, 4, 3), (1, 1, 2, 2));
+        assert_eq!(changed_rect(&previous, &previous, 4, 3), (0, 0, 1, 1));
+    }
+}
@@ -0,0 +1,223 @@
+use std::{collections::BTreeMap, fs::File, io::BufReader};
+
+use serde::Deserialize;
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{CANVAS_HEIGHT, CANVAS_WIDTH},
+};
+
+/// Width and height of the cells artworks are indexed by for looking up points.
+const GRID_SIZE: u32 = 100;
+
+/// An entry of a Place Atlas JSON file.
+#[derive(Deserialize)]
+struct AtlasEntry {
+    id: serde_json::Value,
+    name: String,
+    path: AtlasPath,
+}
+
+/// The outline of an entry, either a single polygon or one for every period of the event it
+/// existed in.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AtlasPath {
+    Points(Vec<[f64; 2]>),
+    Periods(BTreeMap<String, Vec<[f64; 2]>>),
+}
+
+/// Returns the last period of a key like `1-20, 56, T`, where `T` is the final canvas.
+fn last_period(key: &str) -> u32 {
+    key.split(',')
+        .map(|part| match part.trim() {
+            "T" => u32::MAX,
+            part => part
+                .rsplit('-')
+                .next()
+                .and_then(|end| end.trim().parse().ok())
+                .unwrap_or(0),
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// An artwork outlined by a polygon in canvas coordinates, with the origin in the top left
+/// corner like in the atlas files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Artwork {
+    pub id: String,
+    pub name: String,
+    pub polygon: Vec<[f64; 2]>,
+    /// Pixels touched by the polygon, clipped to the canvas.
+    pub bounds: CanvasBounds,
+}
+
+impl Artwork {
+    /// Returns `None` if the polygon lies outside of the canvas.
+    pub fn new(id: String, name: String, polygon: Vec<[f64; 2]>) -> Option<Self> {
+        let (mut min, mut max) = ([f64::MAX; 2], [f64::MIN; 2]);
+        for point in &polygon {
+            for axis in 0..2 {
+                min[axis] = min[axis].min(point[axis]);
+                max[axis] = max[axis].max(point[axis]);
+            }
+        }
+        let clamp = |value: f64, end: u32| value.max(0.0).min(end as f64) as u32;
+        let bounds = CanvasBounds {
+            x1: clamp(min[0].floor(), CANVAS_WIDTH),
+            y1: clamp(min[1].floor(), CANVAS_HEIGHT),
+            x2: clamp(max[0].ceil(), CANVAS_WIDTH),
+            y2: clamp(max[1].ceil(), CANVAS_HEIGHT),
+        };
+        (polygon.len() >= 3 && bounds.x1 < bounds.x2 && bounds.y1 < bounds.y2).then_some(Self {
+            id,
+            name,
+            polygon,
+            bounds,
+        })
+    }
+
+    /// Whether the center of the pixel lies inside of the polygon.
+    pub fn contains(&self, (x, y): (u32, u32)) -> bool {
+        if !self.bounds.contains((x, y)) {
+            return false;
+        }
+        let (px, py) = (x as f64 + 0.5, y as f64 + 0.5);
+        let mut inside = false;
+        let mut previous = self.polygon[self.polygon.len() - 1];
+        for &point in &self.polygon {
+            let ([x1, y1], [x2, y2]) = (previous, point);
+            if (y1 > py) != (y2 > py) && px < x1 + (py - y1) * (x2 - x1) / (y2 - y1) {
+                inside = !inside;
+            }
+            previous = point;
+        }
+        inside
+    }
+
+    /// Returns every pixel inside of the polygon.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let bounds = self.bounds;
+        (bounds.y1..bounds.y2)
+            .flat_map(move |y| (bounds.x1..bounds.x2).map(move |x| (x, y)))
+            .filter(|&point| self.contains(point))
+    }
+}
+
+/// Artworks from a Place Atlas JSON file.
+pub struct Atlas {
+    pub artworks: Vec<Artwork>,
+    /// Artworks overlapping every grid cell.
+    grid: Vec<Vec<usize>>,
+}
+
+impl Atlas {
+    pub fn new(artworks: Vec<Artwork>) -> Self {
+        let columns = CANVAS_WIDTH.div_ceil(GRID_SIZE);
+        let mut grid = vec![Vec::new(); (columns * CANVAS_HEIGHT.div_ceil(GRID_SIZE)) as usize];
+        for (index, artwork) in artworks.iter().enumerate() {
+            let bounds = artwork.bounds;
+            for y in bounds.y1 / GRID_SIZE..=(bounds.y2 - 1) / GRID_SIZE {
+                for x in bounds.x1 / GRID_SIZE..=(bounds.x2 - 1) / GRID_SIZE {
+                    grid[(y * columns + x) as usize].push(index);
+                }
+            }
+        }
+        Self { artworks, grid }
+    }
+
+    /// Loads an atlas file. Entries with an outline for every period use the latest one, and
+    /// entries outside of the canvas are left out.
+    pub fn load(path: &str) -> Result<Self, Whatever> {
+        let file = File::open(path).with_whatever_context(|_| format!("Failed to open {path}"))?;
+        let entries: Vec<AtlasEntry> = serde_json::from_reader(BufReader::new(file))
+            .with_whatever_context(|_| format!("Failed to parse {path}"))?;
+        let artworks = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let polygon = match entry.path {
+                    AtlasPath::Points(points) => points,
+                    AtlasPath::Periods(periods) => {
+                        periods
+                            .into_iter()
+                            .max_by_key(|(key, _)| last_period(key))?
+                            .1
+                    }
+                };
+                let id = match entry.id {
+                    serde_json::Value::String(id) => id,
+                    id => id.to_string(),
+                };
+                Artwork::new(id, entry.name, polygon)
+            })
+            .collect();
+        Ok(Self::new(artworks))
+    }
+
+    /// Finds an artwork by its id, or by its name ignoring case.
+    pub fn find(&self, key: &str) -> Option<&Artwork> {
+        self.artworks
+            .iter()
+            .find(|artwork| artwork.id == key)
+            .or_else(|| {
+                self.artworks
+                    .iter()
+                    .find(|artwork| artwork.name.eq_ignore_ascii_case(key))
+            })
+    }
+
+    /// Returns the smallest artwork containing the pixel.
+    pub fn artwork_at(&self, (x, y): (u32, u32)) -> Option<&Artwork> {
+        if x >= CANVAS_WIDTH || y >= CANVAS_HEIGHT {
+            return None;
+        }
+        let columns = CANVAS_WIDTH.div_ceil(GRID_SIZE);
+        self.grid[(y / GRID_SIZE * columns + x / GRID_SIZE) as usize]
+            .iter()
+            .map(|&index| &self.artworks[index])
+            .filter(|artwork| artwork.contains((x, y)))
+            .min_by_key(|artwork| artwork.bounds.area())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_artwork_at() {
+        use super::{last_period, Artwork, Atlas};
+
+        assert_eq!(last_period("1-20, 56"), 56);
+        assert_eq!(last_period("109-165, T"), u32::MAX);
+
+        let square = |id: &str, size: f64| {
+            Artwork::new(
+                id.to_string(),
+                id.to_uppercase(),
+                vec![
+                    [10.0, 10.0],
+                    [10.0 + size, 10.0],
+                    [10.0 + size, 10.0 + size],
+                    [10.0, 10.0 + size],
+                ],
+            )
+            .unwrap()
+        };
+        let triangle = Artwork::new(
+            "c".to_string(),
+            "C".to_string(),
+            vec![[200.0, 0.0], [300.0, 0.0], [200.0, 100.0]],
+        )
+        .unwrap();
+        let atlas = Atlas::new(vec![square("a", 200.0), square("b", 5.0), triangle]);
+
+        assert_eq!(atlas.artwork_at((12, 12)).unwrap().id, "b");
+        assert_eq!(atlas.artwork_at((150, 150)).unwrap().id, "a");
+        assert_eq!(atlas.artwork_at((250, 20)).unwrap().id, "c");
+        assert!(atlas.artwork_at((290, 90)).is_none());
+        assert!(atlas.artwork_at((5, 5)).is_none());
+        assert_eq!(atlas.find("b").unwrap().name, "B");
+        assert_eq!(atlas.find("C").unwrap().pixels().count(), 4950);
+    }
+}
@@ -0,0 +1,296 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    canvas::{to_canvas_coords, to_dataset_coords, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{Coordinate, PixelData},
+};
+
+/// A rectangle on the canvas in canvas coordinates. `x2` and `y2` are exclusive.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone, Copy)]
+pub struct CanvasBounds {
+    pub x1: u32,
+    pub y1: u32,
+    pub x2: u32,
+    pub y2: u32,
+}
+
+impl CanvasBounds {
+    pub const FULL: CanvasBounds = CanvasBounds {
+        x1: 0,
+        y1: 0,
+        x2: CANVAS_WIDTH,
+        y2: CANVAS_HEIGHT,
+    };
+
+    pub fn width(&self) -> u32 {
+        self.x2 - self.x1
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y2 - self.y1
+    }
+
+    pub fn contains(&self, (x, y): (u32, u32)) -> bool {
+        x >= self.x1 && x < self.x2 && y >= self.y1 && y < self.y2
+    }
+
+    /// Returns the row-major index of the point within the bounds, or `None` if it lies outside.
+    pub fn index_of(&self, (x, y): (u32, u32)) -> Option<usize> {
+        self.contains((x, y))
+            .then(|| (y - self.y1) as usize * self.width() as usize + (x - self.x1) as usize)
+    }
+
+    /// Returns the smallest bounds containing both.
+    pub fn union(&self, other: &CanvasBounds) -> CanvasBounds {
+        CanvasBounds {
+            x1: self.x1.min(other.x1),
+            y1: self.y1.min(other.y1),
+            x2: self.x2.max(other.x2),
+            y2: self.y2.max(other.y2),
+        }
+    }
+
+    pub fn intersects(&self, other: &CanvasBounds) -> bool {
+        self.x1 < other.x2 && other.x1 < self.x2 && self.y1 < other.y2 && other.y1 < self.y2
+    }
+
+    /// Returns the overlap of both, or `None` if they don't overlap.
+    pub fn intersection(&self, other: &CanvasBounds) -> Option<CanvasBounds> {
+        self.intersects(other).then(|| CanvasBounds {
+            x1: self.x1.max(other.x1),
+            y1: self.y1.max(other.y1),
+            x2: self.x2.min(other.x2),
+            y2: self.y2.min(other.y2),
+        })
+    }
+
+    pub fn area(&self) -> usize {
+        self.width() as usize * self.height() as usize
+    }
+
+    /// Returns the smallest bounds aligned to a grid of `snap` pixels that contain both `self`
+    /// and the given point.
+    fn expand_snapped(&self, (x, y): (u32, u32), snap: u32) -> CanvasBounds {
+        CanvasBounds {
+            x1: self.x1.min(x / snap * snap),
+            y1: self.y1.min(y / snap * snap),
+            x2: self.x2.max(((x / snap + 1) * snap).min(CANVAS_WIDTH)),
+            y2: self.y2.max(((y / snap + 1) * snap).min(CANVAS_HEIGHT)),
+        }
+    }
+
+    /// Returns the bounds as `[u1, v1, u2, v2]` texture coordinates.
+    pub fn to_uv(&self) -> [f32; 4] {
+        [
+            self.x1 as f32 / CANVAS_WIDTH as f32,
+            self.y1 as f32 / CANVAS_HEIGHT as f32,
+            self.x2 as f32 / CANVAS_WIDTH as f32,
+            self.y2 as f32 / CANVAS_HEIGHT as f32,
+        ]
+    }
+}
+
+impl FromStr for CanvasBounds {
+    type Err = Box<dyn std::error::Error>;
+
+    /// Parses two opposite corners `x1,y1,x2,y2` in dataset coordinates, both inclusive.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts = s
+            .split(',')
+            .map(|part| part.trim().parse::<i16>())
+            .collect::<Result<Vec<_>, _>>()?;
+        let [x1, y1, x2, y2] = parts[..] else {
+            return Err("Expected x1,y1,x2,y2".into());
+        };
+        let (x1, y1) = to_canvas_coords((x1, y1)).ok_or("First corner is outside the canvas")?;
+        let (x2, y2) = to_canvas_coords((x2, y2)).ok_or("Second corner is outside the canvas")?;
+        Ok(CanvasBounds {
+            x1: x1.min(x2),
+            y1: y1.min(y2),
+            x2: x1.max(x2) + 1,
+            y2: y1.max(y2) + 1,
+        })
+    }
+}
+
+impl fmt::Display for CanvasBounds {
+    /// Formats the bounds the way they are parsed, as two inclusive corners in dataset
+    /// coordinates.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (x1, y1) = to_dataset_coords((self.x1, self.y1));
+        let (x2, y2) = to_dataset_coords((self.x2 - 1, self.y2 - 1));
+        write!(f, "{x1},{y1},{x2},{y2}")
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct BoundsStage {
+    pub miliseconds_since_first_pixel: u32,
+    pub bounds: CanvasBounds,
+}
+
+/// The active area of the canvas over the event. The canvas grew in stages, so every stage
+/// holds the bounds that were open from its timestamp until the next stage.
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+pub struct BoundsTimeline {
+    pub stages: Vec<BoundsStage>,
+}
+
+impl Default for BoundsTimeline {
+    fn default() -> Self {
+        Self {
+            stages: vec![BoundsStage {
+                miliseconds_since_first_pixel: 0,
+                bounds: CanvasBounds::FULL,
+            }],
+        }
+    }
+}
+
+impl BoundsTimeline {
+    /// Detects the stages from the pixel updates. Only simple pixel placements are considered,
+    /// since users could only place pixels inside the opened area. Whenever a pixel lands
+    /// outside of the current bounds, the bounds are grown to the next multiple of `snap`.
+    ///
+    /// An opened area doesn't fill up at once, so growth within `merge_window_ms` of a stage is
+    /// merged into that stage.
+    pub fn detect(
+        iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+        snap: u32,
+        merge_window_ms: u32,
+    ) -> Result<Self, Whatever> {
+        ensure_whatever!(snap > 0, "Snap must be greater than zero");
+
+        let mut stages: Vec<BoundsStage> = Vec::new();
+        for pixel_data in iter {
+            let pixel_data = pixel_data?;
+            let Coordinate::Simple { x, y } = pixel_data.coordinate else {
+                continue;
+            };
+            let Some(point) = to_canvas_coords((x, y)) else {
+                continue;
+            };
+
+            let bounds = match stages.last() {
+                Some(stage) if stage.bounds.contains(point) => continue,
+                Some(stage) => stage.bounds.expand_snapped(point, snap),
+                None => CanvasBounds {
+                    x1: CANVAS_WIDTH,
+                    y1: CANVAS_HEIGHT,
+                    x2: 0,
+                    y2: 0,
+                }
+                .expand_snapped(point, snap),
+            };
+
+            match stages.last_mut() {
+                Some(stage)
+                    if pixel_data.miliseconds_since_first_pixel
+                        <= stage
+                            .miliseconds_since_first_pixel
+                            .saturating_add(merge_window_ms) =>
+                {
+                    stage.bounds = bounds;
+                }
+                _ => stages.push(BoundsStage {
+                    miliseconds_since_first_pixel: pixel_data.miliseconds_since_first_pixel,
+                    bounds,
+                }),
+            }
+        }
+
+        match stages.first_mut() {
+            // The first stage is open from the very beginning.
+            Some(first) => first.miliseconds_since_first_pixel = 0,
+            None => return Ok(Self::default()),
+        }
+        Ok(Self { stages })
+    }
+
+    /// Returns the bounds that were active at the given time.
+    pub fn bounds_at(&self, miliseconds_since_first_pixel: u32) -> CanvasBounds {
+        let index = self.stages.partition_point(|stage| {
+            stage.miliseconds_since_first_pixel <= miliseconds_since_first_pixel
+        });
+        self.stages
+            .get(index.saturating_sub(1))
+            .map(|stage| stage.bounds)
+            .unwrap_or(CanvasBounds::FULL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoundsTimeline, CanvasBounds};
+    use crate::data::{Coordinate, PixelColor, PixelData};
+
+    fn pixel(miliseconds_since_first_pixel: u32, x: i16, y: i16) -> PixelData {
+        PixelData {
+            miliseconds_since_first_pixel,
+            coordinate: Coordinate::Simple { x, y },
+            pixel_color: PixelColor { r: 0, g: 0, b: 0 },
+            user: 0,
+        }
+    }
+
+    #[test]
+    fn test_parse_bounds() {
+        let bounds: CanvasBounds = "-10,10,9,-9".parse().unwrap();
+        assert_eq!(
+            bounds,
+            CanvasBounds {
+                x1: 1490,
+                y1: 989,
+                x2: 1510,
+                y2: 1009
+            }
+        );
+        assert_eq!((bounds.width(), bounds.height()), (20, 20));
+        assert_eq!(bounds.to_string(), "-10,10,9,-9");
+        "0,0,1500,0".parse::<CanvasBounds>().unwrap_err();
+    }
+
+    #[test]
+    fn test_detect_bounds() {
+        let pixels = vec![
+            pixel(10, 0, 0),
+            pixel(20, -500, 499),
+            pixel(30, 499, -500),
+            pixel(3_600_000, 600, 0),
+            pixel(7_200_000, -1500, -1000),
+        ];
+        let timeline = BoundsTimeline::detect(pixels.into_iter().map(Ok), 500, 60_000).unwrap();
+
+        assert_eq!(timeline.stages.len(), 3);
+        assert_eq!(
+            timeline.bounds_at(0),
+            CanvasBounds {
+                x1: 1000,
+                y1: 500,
+                x2: 2000,
+                y2: 1500
+            }
+        );
+        assert_eq!(
+            timeline.bounds_at(3_600_001),
+            CanvasBounds {
+                x1: 1000,
+                y1: 500,
+                x2: 2500,
+                y2: 1500
+            }
+        );
+        assert_eq!(
+            timeline.bounds_at(u32::MAX),
+            CanvasBounds {
+                x1: 0,
+                y1: 500,
+                x2: 2500,
+                y2: 2000
+            }
+        );
+    }
+}
@@ -0,0 +1,330 @@
+use std::ops::Range;
+
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    bounds::CanvasBounds,
+    data::{Coordinate, PixelColor, PixelData},
+};
+
+pub const CANVAS_WIDTH: u32 = 3000;
+pub const CANVAS_HEIGHT: u32 = 2000;
+
+/// Converts a dataset coordinate to a canvas coordinate.
+///
+/// In the dataset the origin is in the center of the canvas and y grows upwards.
+/// On the canvas the origin is in the top left corner.
+/// Coordinate: min: (-1500, -1000), max: (1499, 999)
+/// Canvas: min: (0, 0), max: (2999, 1999)
+pub fn to_canvas_coords((x, y): (i16, i16)) -> Option<(u32, u32)> {
+    let x = x as i32 + CANVAS_WIDTH as i32 / 2;
+    let y = -(y as i32) - 1 + CANVAS_HEIGHT as i32 / 2;
+    if x >= 0 && x < CANVAS_WIDTH as i32 && y >= 0 && y < CANVAS_HEIGHT as i32 {
+        Some((x as u32, y as u32))
+    } else {
+        None
+    }
+}
+
+/// Converts a canvas coordinate back to a dataset coordinate.
+pub fn to_dataset_coords((x, y): (u32, u32)) -> (i16, i16) {
+    (
+        (x as i32 - CANVAS_WIDTH as i32 / 2) as i16,
+        (CANVAS_HEIGHT as i32 / 2 - 1 - y as i32) as i16,
+    )
+}
+
+/// Calls `f` with every canvas pixel covered by the coordinate, clipped to the canvas.
+/// Rectangles span between their corners in canvas coordinates excluding the larger ones, in
+/// whichever order the corners come, and circles include their border. The player fills the same
+/// pixels, see `rectangle_ranges`.
+pub fn for_each_covered_pixel(coordinate: &Coordinate, mut f: impl FnMut((u32, u32))) {
+    match *coordinate {
+        Coordinate::Simple { x, y } => {
+            if let Some(point) = to_canvas_coords((x, y)) {
+                f(point);
+            }
+        }
+        Coordinate::Rectangle { x1, y1, x2, y2 } => {
+            let (xs, ys) = rectangle_ranges((x1, y1), (x2, y2));
+            for y in ys {
+                for x in xs.clone() {
+                    f((x, y));
+                }
+            }
+        }
+        Coordinate::Circle { x, y, radius } => {
+            let Some((x, y)) = to_canvas_coords((x, y)) else {
+                return;
+            };
+            let radius = radius.max(0) as u32;
+            for j in y.saturating_sub(radius)..=(y + radius).min(CANVAS_HEIGHT - 1) {
+                for i in x.saturating_sub(radius)..=(x + radius).min(CANVAS_WIDTH - 1) {
+                    let (dx, dy) = (i as i64 - x as i64, j as i64 - y as i64);
+                    if dx * dx + dy * dy <= radius as i64 * radius as i64 {
+                        f((i, j));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Returns whether the coordinate covers the canvas pixel, by the same rules as
+/// [`for_each_covered_pixel`] but without visiting the whole shape.
+pub fn covers(coordinate: &Coordinate, (px, py): (u32, u32)) -> bool {
+    match *coordinate {
+        Coordinate::Simple { x, y } => to_canvas_coords((x, y)) == Some((px, py)),
+        Coordinate::Rectangle { x1, y1, x2, y2 } => {
+            let (xs, ys) = rectangle_ranges((x1, y1), (x2, y2));
+            xs.contains(&px) && ys.contains(&py)
+        }
+        Coordinate::Circle { x, y, radius } => {
+            let Some((x, y)) = to_canvas_coords((x, y)) else {
+                return false;
+            };
+            let radius = radius.max(0) as i64;
+            let (dx, dy) = (px as i64 - x as i64, py as i64 - y as i64);
+            px < CANVAS_WIDTH && py < CANVAS_HEIGHT && dx * dx + dy * dy <= radius * radius
+        }
+    }
+}
+
+/// Returns the smallest bounds containing every pixel covered by the coordinate, or `None` if it
+/// doesn't cover any pixel of the canvas.
+pub fn covered_bounds(coordinate: &Coordinate) -> Option<CanvasBounds> {
+    let (xs, ys) = match *coordinate {
+        Coordinate::Simple { x, y } => {
+            let (x, y) = to_canvas_coords((x, y))?;
+            (x..x + 1, y..y + 1)
+        }
+        Coordinate::Rectangle { x1, y1, x2, y2 } => rectangle_ranges((x1, y1), (x2, y2)),
+        Coordinate::Circle { x, y, radius } => {
+            let (x, y) = to_canvas_coords((x, y))?;
+            let radius = radius.max(0) as u32;
+            (
+                x.saturating_sub(radius)..(x + radius + 1).min(CANVAS_WIDTH),
+                y.saturating_sub(radius)..(y + radius + 1).min(CANVAS_HEIGHT),
+            )
+        }
+    };
+    (!xs.is_empty() && !ys.is_empty()).then_some(CanvasBounds {
+        x1: xs.start,
+        y1: ys.start,
+        x2: xs.end,
+        y2: ys.end,
+    })
+}
+
+/// Canvas columns and rows covered by a rectangle between two dataset corners, clipped to the
+/// canvas. The corners may come in any order. The player uploads rectangles to the compute shader
+/// as these ranges, so both cover the same pixels.
+pub(crate) fn rectangle_ranges(
+    (x1, y1): (i16, i16),
+    (x2, y2): (i16, i16),
+) -> (Range<u32>, Range<u32>) {
+    let clamp = |(x, y): (i16, i16)| {
+        (
+            (x as i32 + CANVAS_WIDTH as i32 / 2).clamp(0, CANVAS_WIDTH as i32) as u32,
+            (-(y as i32) - 1 + CANVAS_HEIGHT as i32 / 2).clamp(0, CANVAS_HEIGHT as i32) as u32,
+        )
+    };
+    let ((x1, y1), (x2, y2)) = (clamp((x1, y1)), clamp((x2, y2)));
+    (x1.min(x2)..x1.max(x2), y1.min(y2)..y1.max(y2))
+}
+
+/// The canvas replayed on the CPU, as row-major RGB bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanvasState {
+    pixels: Vec<u8>,
+}
+
+impl Default for CanvasState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CanvasState {
+    /// Creates a white canvas, like the one at the start of the event.
+    pub fn new() -> Self {
+        Self {
+            pixels: vec![255; CANVAS_WIDTH as usize * CANVAS_HEIGHT as usize * 3],
+        }
+    }
+
+    pub fn from_bytes(pixels: Vec<u8>) -> Result<Self, Whatever> {
+        ensure_whatever!(
+            pixels.len() == CANVAS_WIDTH as usize * CANVAS_HEIGHT as usize * 3,
+            "Canvas has {} bytes instead of {}x{} RGB pixels",
+            pixels.len(),
+            CANVAS_WIDTH,
+            CANVAS_HEIGHT
+        );
+        Ok(Self { pixels })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn get(&self, (x, y): (u32, u32)) -> [u8; 3] {
+        let index = (y as usize * CANVAS_WIDTH as usize + x as usize) * 3;
+        [
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        ]
+    }
+
+    pub fn set(&mut self, (x, y): (u32, u32), color: &PixelColor) {
+        let index = (y as usize * CANVAS_WIDTH as usize + x as usize) * 3;
+        self.pixels[index..index + 3].copy_from_slice(&[color.r, color.g, color.b]);
+    }
+
+    pub fn apply(&mut self, pixel_data: &PixelData) {
+        for_each_covered_pixel(&pixel_data.coordinate, |point| {
+            self.set(point, &pixel_data.pixel_color)
+        });
+    }
+
+    /// Applies the update and calls `on_change` with the point, the previous color and the new
+    /// color of every covered pixel whose color actually changed.
+    pub fn apply_with_changes(
+        &mut self,
+        pixel_data: &PixelData,
+        mut on_change: impl FnMut((u32, u32), [u8; 3], [u8; 3]),
+    ) {
+        let color = &pixel_data.pixel_color;
+        let new = [color.r, color.g, color.b];
+        for_each_covered_pixel(&pixel_data.coordinate, |point| {
+            let previous = self.get(point);
+            if previous != new {
+                self.set(point, color);
+                on_change(point, previous, new);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_to_canvas_coords() {
+        use super::{to_canvas_coords, to_dataset_coords};
+
+        assert_eq!(to_canvas_coords((-1500, 999)), Some((0, 0)));
+        assert_eq!(to_canvas_coords((1499, -1000)), Some((2999, 1999)));
+        assert_eq!(to_canvas_coords((0, 0)), Some((1500, 999)));
+        assert_eq!(to_canvas_coords((1500, 0)), None);
+        assert_eq!(to_canvas_coords((0, 1000)), None);
+        assert_eq!(to_dataset_coords((0, 0)), (-1500, 999));
+        assert_eq!(to_dataset_coords((2999, 1999)), (1499, -1000));
+    }
+
+    #[test]
+    fn test_covers() {
+        use super::{covers, for_each_covered_pixel};
+        use crate::data::Coordinate;
+
+        let coordinates = [
+            Coordinate::Simple { x: 0, y: 0 },
+            Coordinate::Rectangle {
+                x1: 3,
+                y1: -2,
+                x2: -1,
+                y2: 1,
+            },
+            Coordinate::Circle {
+                x: -1499,
+                y: 998,
+                radius: 2,
+            },
+        ];
+        for coordinate in &coordinates {
+            let mut covered = Vec::new();
+            for_each_covered_pixel(coordinate, |point| covered.push(point));
+            for y in 0..10 {
+                for x in 0..10 {
+                    let point = (x, y);
+                    assert_eq!(covers(coordinate, point), covered.contains(&point));
+                }
+            }
+            for y in 990..1010 {
+                for x in 1490..1510 {
+                    let point = (x, y);
+                    assert_eq!(covers(coordinate, point), covered.contains(&point));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_covered_bounds() {
+        use super::covered_bounds;
+        use crate::{bounds::CanvasBounds, data::Coordinate};
+
+        let bounds = |x1, y1, x2, y2| Some(CanvasBounds { x1, y1, x2, y2 });
+        assert_eq!(
+            covered_bounds(&Coordinate::Simple { x: 0, y: 0 }),
+            bounds(1500, 999, 1501, 1000)
+        );
+        assert_eq!(
+            covered_bounds(&Coordinate::Circle {
+                x: -1500,
+                y: 999,
+                radius: 2
+            }),
+            bounds(0, 0, 3, 3)
+        );
+        assert_eq!(
+            covered_bounds(&Coordinate::Rectangle {
+                x1: 2,
+                y1: 0,
+                x2: 0,
+                y2: 2
+            }),
+            bounds(1500, 997, 1502, 999)
+        );
+        assert_eq!(covered_bounds(&Coordinate::Simple { x: 1500, y: 0 }), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        use super::CanvasState;
+        use crate::data::{Coordinate, PixelColor, PixelData};
+
+        let black = PixelColor { r: 0, g: 0, b: 0 };
+        let mut canvas = CanvasState::new();
+        canvas.apply(&PixelData {
+            miliseconds_since_first_pixel: 0,
+            coordinate: Coordinate::Rectangle {
+                x1: 0,
+                y1: 0,
+                x2: 2,
+                y2: 2,
+            },
+            pixel_color: black.clone(),
+            user: 0,
+        });
+        canvas.apply(&PixelData {
+            miliseconds_since_first_pixel: 0,
+            coordinate: Coordinate::Circle {
+                x: -1400,
+                y: 0,
+                radius: 1,
+            },
+            pixel_color: black,
+            user: 0,
+        });
+
+        // The rectangle covers (1500, 997) to (1501, 998).
+        assert_eq!(canvas.get((1500, 998)), [0, 0, 0]);
+        assert_eq!(canvas.get((1501, 997)), [0, 0, 0]);
+        assert_eq!(canvas.get((1502, 998)), [255, 255, 255]);
+        assert_eq!(canvas.get((1500, 999)), [255, 255, 255]);
+        assert_eq!(canvas.get((100, 998)), [0, 0, 0]);
+        assert_eq!(canvas.get((101, 999)), [0, 0, 0]);
+        assert_eq!(canvas.get((101, 1000)), [255, 255, 255]);
+    }
+}
@@ -1,8 +1,28 @@
 use std::str::FromStr;
 
 use bincode::{Decode, Encode};
+use chrono::{DateTime, Duration, SecondsFormat, TimeZone, Utc};
+use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
+lazy_static! {
+    /// Time of the first pixel placement of the event.
+    pub static ref FIRST_PIXEL_TIME: DateTime<Utc> =
+        DateTime::parse_from_rfc3339("2023-07-20 13:00:26.088Z")
+            .unwrap()
+            .with_timezone(&Utc);
+}
+
+/// Converts event time to UTC.
+pub fn to_utc(miliseconds_since_first_pixel: u32) -> DateTime<Utc> {
+    *FIRST_PIXEL_TIME + Duration::milliseconds(miliseconds_since_first_pixel as i64)
+}
+
+/// Formats event time as an RFC 3339 UTC timestamp with milliseconds, for machine readable output.
+pub fn to_rfc3339(miliseconds_since_first_pixel: u32) -> String {
+    to_utc(miliseconds_since_first_pixel).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
 #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Encode, Decode, Clone)]
 pub enum Coordinate {
     Simple { x: i16, y: i16 },
@@ -32,6 +52,9 @@ pub struct PixelData {
     pub miliseconds_since_first_pixel: u32,
     pub coordinate: Coordinate,
     pub pixel_color: PixelColor,
+    /// Id of the user who placed the update, in order of their first update. The hash of every
+    /// id is in the [`UserTable`](crate::user_table::UserTable) next to the dataset.
+    pub user: u32,
 }
 
 impl FromStr for Coordinate {
@@ -114,6 +137,35 @@ impl FromStr for PixelColor {
     }
 }
 
+/// A point in time during the event.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+pub struct EventTime {
+    pub miliseconds_since_first_pixel: u32,
+}
+
+impl FromStr for EventTime {
+    type Err = Box<dyn std::error::Error>;
+
+    /// Parses milliseconds since the Unix epoch, or a UTC timestamp such as
+    /// `2023-07-21T12:00:00Z` or `2023-07-21 12:00:00 UTC` like in the CSV files.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let time = match s.parse::<i64>() {
+            Ok(miliseconds_since_epoch) => Utc
+                .timestamp_millis_opt(miliseconds_since_epoch)
+                .single()
+                .ok_or("Timestamp out of range")?,
+            Err(_) => DateTime::parse_from_rfc3339(&s.replace(" UTC", "Z"))?.with_timezone(&Utc),
+        };
+        let miliseconds_since_first_pixel = (time - *FIRST_PIXEL_TIME)
+            .num_milliseconds()
+            .try_into()
+            .map_err(|_| "Time is outside of the event")?;
+        Ok(EventTime {
+            miliseconds_since_first_pixel,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -149,4 +201,25 @@ mod tests {
         let coordinate = Coordinate::from_str("424,336,425,337,3").unwrap_err();
         assert_eq!(coordinate.to_string(), "Unknown coordinate format");
     }
+
+    #[test]
+    fn test_parse_event_time() {
+        use super::EventTime;
+        use std::str::FromStr;
+
+        let time = EventTime::from_str("2023-07-20 13:00:27.088 UTC").unwrap();
+        assert_eq!(time.miliseconds_since_first_pixel, 1000);
+
+        let time = EventTime::from_str("2023-07-20T14:00:26.088Z").unwrap();
+        assert_eq!(time.miliseconds_since_first_pixel, 3_600_000);
+
+        let time = EventTime::from_str("1689858027088").unwrap();
+        assert_eq!(time.miliseconds_since_first_pixel, 1000);
+        assert_eq!(
+            super::to_utc(1000).to_rfc3339(),
+            "2023-07-20T13:00:27.088+00:00"
+        );
+
+        EventTime::from_str("2023-07-20 13:00:00 UTC").unwrap_err();
+    }
 }
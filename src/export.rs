@@ -0,0 +1,226 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    bounds::CanvasBounds,
+    canvas::CanvasState,
+    data::{to_utc, EventTime},
+    keyframe,
+};
+
+/// An RGB image with row-major pixels.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RgbImage {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl RgbImage {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![0; width as usize * height as usize * 3],
+        }
+    }
+
+    /// Crops the region out of the canvas.
+    pub fn from_canvas(canvas: &CanvasState, region: CanvasBounds) -> Self {
+        let mut image = Self::new(region.width(), region.height());
+        for y in region.y1..region.y2 {
+            for x in region.x1..region.x2 {
+                image.set((x - region.x1, y - region.y1), canvas.get((x, y)));
+            }
+        }
+        image
+    }
+
+    pub fn get(&self, (x, y): (u32, u32)) -> [u8; 3] {
+        let index = (y as usize * self.width as usize + x as usize) * 3;
+        [
+            self.pixels[index],
+            self.pixels[index + 1],
+            self.pixels[index + 2],
+        ]
+    }
+
+    pub fn set(&mut self, (x, y): (u32, u32), color: [u8; 3]) {
+        let index = (y as usize * self.width as usize + x as usize) * 3;
+        self.pixels[index..index + 3].copy_from_slice(&color);
+    }
+
+    /// Upscales the image by an integer factor with nearest neighbor sampling, so every canvas
+    /// pixel stays a sharp square.
+    pub fn scaled(&self, scale: u32) -> Self {
+        if scale <= 1 {
+            return self.clone();
+        }
+        let mut image = Self::new(self.width * scale, self.height * scale);
+        for y in 0..image.height {
+            for x in 0..image.width {
+                image.set((x, y), self.get((x / scale, y / scale)));
+            }
+        }
+        image
+    }
+
+    pub fn write_png(&self, path: &str) -> Result<(), Whatever> {
+        let file =
+            File::create(path).with_whatever_context(|_| format!("Failed to create {path}"))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .whatever_context("Failed to write PNG header")?;
+        writer
+            .write_image_data(&self.pixels)
+            .whatever_context("Failed to write PNG data")
+    }
+}
+
+/// Element types that can be written to `.npy` files.
+pub trait NpyElement: Copy {
+    /// NumPy type description, such as `<u4`.
+    const DESCR: &'static str;
+
+    fn write_le(self, writer: &mut impl Write) -> io::Result<()>;
+}
+
+macro_rules! impl_npy_element {
+    ($($ty:ty => $descr:literal),*) => {
+        $(
+            impl NpyElement for $ty {
+                const DESCR: &'static str = $descr;
+
+                fn write_le(self, writer: &mut impl Write) -> io::Result<()> {
+                    writer.write_all(&self.to_le_bytes())
+                }
+            }
+        )*
+    };
+}
+
+impl_npy_element!(u8 => "|u1", u16 => "<u2", u32 => "<u4", u64 => "<u8", f32 => "<f4");
+
+/// Writes a row-major array as a `.npy` file, which notebooks can load with `numpy.load`.
+pub fn write_npy<T: NpyElement>(path: &str, shape: &[usize], data: &[T]) -> Result<(), Whatever> {
+    ensure_whatever!(
+        shape.iter().product::<usize>() == data.len(),
+        "Shape {:?} doesn't match {} elements",
+        shape,
+        data.len()
+    );
+    let shape = match shape {
+        [len] => format!("({len},)"),
+        _ => format!(
+            "({})",
+            shape
+                .iter()
+                .map(|len| len.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+        T::DESCR,
+        shape
+    );
+    // The magic string, version and header length take 10 bytes, and the data has to start at
+    // a multiple of 64 bytes.
+    let padding = 63 - (10 + header.len()) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let file = File::create(path).with_whatever_context(|_| format!("Failed to create {path}"))?;
+    let mut writer = BufWriter::new(file);
+    (|| {
+        writer.write_all(b"\x93NUMPY\x01\x00")?;
+        writer.write_all(&(header.len() as u16).to_le_bytes())?;
+        writer.write_all(header.as_bytes())?;
+        for &value in data {
+            value.write_le(&mut writer)?;
+        }
+        writer.flush()
+    })()
+    .with_whatever_context(|_| format!("Failed to write {path}"))
+}
+
+/// Writes the canvas at the given time as PNG, without needing a GPU.
+pub fn write_snapshot(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    time: EventTime,
+    region: CanvasBounds,
+    scale: u32,
+    output: &str,
+) -> Result<(), Whatever> {
+    let canvas = keyframe::canvas_at(
+        data_path,
+        keyframes_dir,
+        time.miliseconds_since_first_pixel,
+        region,
+    )?;
+    RgbImage::from_canvas(&canvas, region)
+        .scaled(scale)
+        .write_png(output)?;
+    println!(
+        "Wrote the canvas at {} to {}",
+        to_utc(time.miliseconds_since_first_pixel),
+        output
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_crop_and_scale() {
+        use super::RgbImage;
+        use crate::{
+            bounds::CanvasBounds,
+            canvas::CanvasState,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let mut canvas = CanvasState::new();
+        canvas.apply(&PixelData {
+            miliseconds_since_first_pixel: 0,
+            coordinate: Coordinate::Simple { x: 0, y: 0 },
+            pixel_color: PixelColor { r: 1, g: 2, b: 3 },
+            user: 0,
+        });
+        let image = RgbImage::from_canvas(&canvas, "0,0,1,1".parse::<CanvasBounds>().unwrap());
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.get((0, 1)), [1, 2, 3]);
+        assert_eq!(image.get((1, 1)), [255, 255, 255]);
+
+        let image = image.scaled(3);
+        assert_eq!((image.width, image.height), (6, 6));
+        assert_eq!(image.get((2, 3)), [1, 2, 3]);
+        assert_eq!(image.get((3, 3)), [255, 255, 255]);
+    }
+
+    #[test]
+    fn test_write_npy() {
+        let path = std::env::temp_dir().join(format!("rplace-{}.npy", std::process::id()));
+        let path = path.to_str().unwrap();
+        super::write_npy(path, &[2, 3], &[1u32, 2, 3, 4, 5, 6]).unwrap();
+
+        let bytes = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '<u4', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert_eq!(bytes.len(), 10 + header_len + 6 * 4);
+        assert_eq!(&bytes[10 + header_len..10 + header_len + 4], &[1, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,233 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+};
+
+use serde::Serialize;
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    canvas::{covers, to_canvas_coords},
+    data::{to_rfc3339, to_utc, Coordinate, PixelColor, PixelData},
+    history_index::HistoryIndex,
+    parse::GzippedBinPixelDataReader,
+    user_table::UserTable,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateShape {
+    Pixel,
+    /// Rectangle fill by a moderator.
+    Rectangle,
+    /// Circle fill by a moderator.
+    Circle,
+}
+
+impl UpdateShape {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            UpdateShape::Pixel => "pixel",
+            UpdateShape::Rectangle => "rectangle",
+            UpdateShape::Circle => "circle",
+        }
+    }
+}
+
+impl From<&Coordinate> for UpdateShape {
+    fn from(coordinate: &Coordinate) -> Self {
+        match coordinate {
+            Coordinate::Simple { .. } => UpdateShape::Pixel,
+            Coordinate::Rectangle { .. } => UpdateShape::Rectangle,
+            Coordinate::Circle { .. } => UpdateShape::Circle,
+        }
+    }
+}
+
+/// An update that covered a pixel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub miliseconds_since_first_pixel: u32,
+    pub pixel_color: PixelColor,
+    pub shape: UpdateShape,
+    pub user: u32,
+}
+
+impl From<&PixelData> for HistoryEntry {
+    fn from(pixel_data: &PixelData) -> Self {
+        Self {
+            miliseconds_since_first_pixel: pixel_data.miliseconds_since_first_pixel,
+            pixel_color: pixel_data.pixel_color.clone(),
+            shape: (&pixel_data.coordinate).into(),
+            user: pixel_data.user,
+        }
+    }
+}
+
+/// Returns every update that covered the canvas pixel, in time order.
+pub fn pixel_history(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    point: (u32, u32),
+) -> Result<Vec<HistoryEntry>, Whatever> {
+    let mut history = Vec::new();
+    for pixel_data in iter {
+        let pixel_data = pixel_data?;
+        if covers(&pixel_data.coordinate, point) {
+            history.push(HistoryEntry::from(&pixel_data));
+        }
+    }
+    Ok(history)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum HistoryFormat {
+    /// Aligned columns for reading in a terminal.
+    Table,
+    Json,
+}
+
+/// A history entry as written to JSON.
+#[derive(Serialize)]
+struct HistoryRecord {
+    time: String,
+    miliseconds_since_first_pixel: u32,
+    color: String,
+    shape: UpdateShape,
+    /// The user hash, or the user id if the dataset has no user table.
+    user: String,
+}
+
+impl HistoryRecord {
+    fn new(entry: &HistoryEntry, users: Option<&UserTable>) -> Self {
+        let PixelColor { r, g, b } = entry.pixel_color;
+        Self {
+            time: to_rfc3339(entry.miliseconds_since_first_pixel),
+            miliseconds_since_first_pixel: entry.miliseconds_since_first_pixel,
+            color: format!("#{r:02X}{g:02X}{b:02X}"),
+            shape: entry.shape,
+            user: UserTable::name(users, entry.user),
+        }
+    }
+}
+
+fn write_table(writer: &mut impl Write, records: &[HistoryRecord]) -> io::Result<()> {
+    writeln!(
+        writer,
+        "{:<27}  {:<7}  {:<9}  user",
+        "time", "color", "shape"
+    )?;
+    for record in records {
+        let time = to_utc(record.miliseconds_since_first_pixel);
+        writeln!(
+            writer,
+            "{:<27}  {:<7}  {:<9}  {}",
+            time.format("%Y-%m-%d %H:%M:%S%.3f UTC").to_string(),
+            record.color,
+            record.shape.as_str(),
+            record.user
+        )?;
+    }
+    writer.flush()
+}
+
+/// Writes the history of the pixel at the dataset coordinate to the output, or to stdout if no
+/// output is given. Uses the history index of the dataset if it has been built.
+pub fn write_history(
+    data_path: &str,
+    (x, y): (i16, i16),
+    format: HistoryFormat,
+    output: Option<&str>,
+) -> Result<(), Whatever> {
+    let point = to_canvas_coords((x, y))
+        .with_whatever_context(|| format!("({x}, {y}) is outside of the canvas"))?;
+    let history = match HistoryIndex::open_for(data_path)? {
+        Some(mut index) => index.pixel(point)?,
+        None => pixel_history(GzippedBinPixelDataReader::new(data_path)?, point)?,
+    };
+    let users = UserTable::load_for(data_path)?;
+    let records: Vec<_> = history
+        .iter()
+        .map(|entry| HistoryRecord::new(entry, users.as_ref()))
+        .collect();
+
+    let mut writer: Box<dyn Write> = match output {
+        Some(output) => {
+            let file = File::create(output)
+                .with_whatever_context(|_| format!("Failed to create {output}"))?;
+            Box::new(BufWriter::new(file))
+        }
+        None => Box::new(BufWriter::new(io::stdout().lock())),
+    };
+    match format {
+        HistoryFormat::Table => {
+            write_table(&mut writer, &records).whatever_context("Failed to write history")?
+        }
+        HistoryFormat::Json => {
+            serde_json::to_writer_pretty(&mut writer, &records)
+                .whatever_context("Failed to write history")?;
+            writeln!(writer)
+                .and_then(|_| writer.flush())
+                .whatever_context("Failed to write history")?;
+        }
+    }
+    if let Some(output) = output {
+        println!(
+            "Wrote {} updates of ({}, {}) to {}",
+            history.len(),
+            x,
+            y,
+            output
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_pixel_history() {
+        use super::{pixel_history, UpdateShape};
+        use crate::data::{Coordinate, PixelColor, PixelData};
+
+        let update = |ms, coordinate| {
+            Ok(PixelData {
+                miliseconds_since_first_pixel: ms,
+                coordinate,
+                pixel_color: PixelColor { r: 0, g: 0, b: 0 },
+                user: 0,
+            })
+        };
+        let updates = vec![
+            update(100, Coordinate::Simple { x: 0, y: 0 }),
+            update(200, Coordinate::Simple { x: 1, y: 0 }),
+            update(
+                300,
+                Coordinate::Rectangle {
+                    x1: -5,
+                    y1: 5,
+                    x2: 5,
+                    y2: -5,
+                },
+            ),
+            update(
+                400,
+                Coordinate::Circle {
+                    x: 3,
+                    y: 0,
+                    radius: 2,
+                },
+            ),
+        ];
+
+        // (0, 0) in dataset coordinates
+        let history = pixel_history(updates.into_iter(), (1500, 999)).unwrap();
+        let history: Vec<_> = history
+            .iter()
+            .map(|entry| (entry.miliseconds_since_first_pixel, entry.shape))
+            .collect();
+        assert_eq!(
+            history,
+            vec![(100, UpdateShape::Pixel), (300, UpdateShape::Rectangle)]
+        );
+    }
+}
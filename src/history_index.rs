@@ -0,0 +1,371 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    bounds::CanvasBounds,
+    canvas::{for_each_covered_pixel, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{PixelColor, PixelData},
+    history::{HistoryEntry, UpdateShape},
+    parse::GzippedBinPixelDataReader,
+};
+
+const MAGIC: &[u8; 4] = b"RPHI";
+const VERSION: u32 = 2;
+const HEADER_LEN: u64 = 16;
+const RECORD_LEN: usize = 12;
+const CELLS: usize = CANVAS_WIDTH as usize * CANVAS_HEIGHT as usize;
+
+/// A canvas pixel and every update that covered it.
+pub type PointHistory = ((u32, u32), Vec<HistoryEntry>);
+
+/// Every update of every pixel laid out pixel-major, so the history of a pixel is a single read
+/// instead of a scan of the whole stream. The file is uncompressed and contains:
+///
+/// - The magic `RPHI`, the version, and the canvas width and height as `u32`.
+/// - `width * height + 1` offsets as `u64`: the first record of every row-major canvas pixel,
+///   followed by the number of records.
+/// - 12 byte records of the time as `u32`, the RGB color, the [`UpdateShape`] and the user id as
+///   `u32`, sorted by time for every pixel.
+///
+/// All numbers are little-endian. Shape fills get a record for every pixel they covered.
+pub struct HistoryIndex {
+    file: File,
+}
+
+fn encode_record(entry: &HistoryEntry) -> [u8; RECORD_LEN] {
+    let [a, b, c, d] = entry.miliseconds_since_first_pixel.to_le_bytes();
+    let PixelColor { r, g, b: blue } = entry.pixel_color;
+    let shape = match entry.shape {
+        UpdateShape::Pixel => 0,
+        UpdateShape::Rectangle => 1,
+        UpdateShape::Circle => 2,
+    };
+    let [e, f, h, i] = entry.user.to_le_bytes();
+    [a, b, c, d, r, g, blue, shape, e, f, h, i]
+}
+
+fn decode_record(bytes: &[u8]) -> Result<HistoryEntry, Whatever> {
+    let shape = match bytes[7] {
+        0 => UpdateShape::Pixel,
+        1 => UpdateShape::Rectangle,
+        2 => UpdateShape::Circle,
+        tag => whatever!("Unknown shape {} in history index", tag),
+    };
+    Ok(HistoryEntry {
+        miliseconds_since_first_pixel: u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        pixel_color: PixelColor {
+            r: bytes[4],
+            g: bytes[5],
+            b: bytes[6],
+        },
+        shape,
+        user: u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+    })
+}
+
+impl HistoryIndex {
+    pub fn path_for(data_path: &str) -> String {
+        format!("{data_path}.history.bin")
+    }
+
+    pub fn open(path: &str) -> Result<Self, Whatever> {
+        let mut file =
+            File::open(path).with_whatever_context(|_| format!("Failed to open {path}"))?;
+        let mut header = [0; HEADER_LEN as usize];
+        file.read_exact(&mut header)
+            .whatever_context("Failed to read history index header")?;
+        let field = |i: usize| u32::from_le_bytes(header[i * 4..i * 4 + 4].try_into().unwrap());
+        ensure_whatever!(&header[..4] == MAGIC, "{} is not a history index", path);
+        ensure_whatever!(
+            field(1) == VERSION,
+            "History index version {} is not supported, rebuild it",
+            field(1)
+        );
+        ensure_whatever!(
+            (field(2), field(3)) == (CANVAS_WIDTH, CANVAS_HEIGHT),
+            "History index is for a {}x{} canvas",
+            field(2),
+            field(3)
+        );
+        Ok(Self { file })
+    }
+
+    /// Opens the index next to the dataset if it has been built.
+    pub fn open_for(data_path: &str) -> Result<Option<Self>, Whatever> {
+        let path = Self::path_for(data_path);
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+        Self::open(&path).map(Some)
+    }
+
+    /// Reads the offsets of `len + 1` consecutive cells starting at `cell`.
+    fn offsets(&mut self, cell: usize, len: usize) -> Result<Vec<u64>, Whatever> {
+        let mut bytes = vec![0; (len + 1) * 8];
+        self.file
+            .seek(SeekFrom::Start(HEADER_LEN + cell as u64 * 8))
+            .and_then(|_| self.file.read_exact(&mut bytes))
+            .whatever_context("Failed to read history index offsets")?;
+        Ok(bytes
+            .chunks_exact(8)
+            .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+            .collect())
+    }
+
+    /// Reads the records `start..end`.
+    fn records(&mut self, start: u64, end: u64) -> Result<Vec<HistoryEntry>, Whatever> {
+        let records_start = HEADER_LEN + (CELLS as u64 + 1) * 8;
+        let mut bytes = vec![0; (end - start) as usize * RECORD_LEN];
+        self.file
+            .seek(SeekFrom::Start(records_start + start * RECORD_LEN as u64))
+            .and_then(|_| self.file.read_exact(&mut bytes))
+            .whatever_context("Failed to read history index records")?;
+        bytes.chunks_exact(RECORD_LEN).map(decode_record).collect()
+    }
+
+    /// Returns every update that covered the canvas pixel, in time order.
+    pub fn pixel(&mut self, (x, y): (u32, u32)) -> Result<Vec<HistoryEntry>, Whatever> {
+        let offsets = self.offsets((y * CANVAS_WIDTH + x) as usize, 1)?;
+        self.records(offsets[0], offsets[1])
+    }
+
+    /// Returns the history of every pixel of the region in row-major order. Every row of the
+    /// region is read at once.
+    pub fn region(&mut self, region: CanvasBounds) -> Result<Vec<PointHistory>, Whatever> {
+        let mut histories = Vec::with_capacity(region.area());
+        for y in region.y1..region.y2 {
+            let cell = (y * CANVAS_WIDTH + region.x1) as usize;
+            let offsets = self.offsets(cell, region.width() as usize)?;
+            let mut records = self.records(offsets[0], offsets[offsets.len() - 1])?;
+            // Split the row from the end so every split is cheap.
+            let mut row = Vec::with_capacity(region.width() as usize);
+            for (i, x) in (region.x1..region.x2).enumerate().rev() {
+                let start = (offsets[i] - offsets[0]) as usize;
+                row.push(((x, y), records.split_off(start)));
+            }
+            histories.extend(row.into_iter().rev());
+        }
+        Ok(histories)
+    }
+}
+
+/// Builds the history index from the time-ordered updates of the dataset. The canvas is split
+/// into bands of rows whose records take at most `max_band_bytes` (or a single row), and the
+/// dataset is read once to count the records and once per band to fill them in.
+///
+/// Returns the number of records written.
+pub fn write_history_index(
+    data_path: &str,
+    output: &str,
+    max_band_bytes: u64,
+) -> Result<u64, Whatever> {
+    let mut counts = vec![0u32; CELLS];
+    for pixel_data in GzippedBinPixelDataReader::new(data_path)? {
+        for_each_covered_pixel(&pixel_data?.coordinate, |(x, y)| {
+            counts[(y * CANVAS_WIDTH + x) as usize] += 1;
+        });
+    }
+    let mut offsets = Vec::with_capacity(CELLS + 1);
+    let mut total = 0u64;
+    offsets.push(0);
+    for &count in &counts {
+        total += count as u64;
+        offsets.push(total);
+    }
+    drop(counts);
+
+    let row_offsets: Vec<u64> = offsets
+        .iter()
+        .step_by(CANVAS_WIDTH as usize)
+        .copied()
+        .collect();
+    let bands = row_bands(&row_offsets, max_band_bytes / RECORD_LEN as u64);
+    println!(
+        "Indexing {} records in {} bands of rows",
+        total,
+        bands.len()
+    );
+
+    let file =
+        File::create(output).with_whatever_context(|_| format!("Failed to create {output}"))?;
+    let mut writer = BufWriter::new(file);
+    (|| -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        for field in [VERSION, CANVAS_WIDTH, CANVAS_HEIGHT] {
+            writer.write_all(&field.to_le_bytes())?;
+        }
+        for offset in &offsets {
+            writer.write_all(&offset.to_le_bytes())?;
+        }
+        Ok(())
+    })()
+    .whatever_context("Failed to write history index header")?;
+
+    for (i, &(y1, y2)) in bands.iter().enumerate() {
+        let first_cell = (y1 * CANVAS_WIDTH) as usize;
+        let last_cell = (y2 * CANVAS_WIDTH) as usize;
+        let band_start = offsets[first_cell];
+        let mut records = vec![0u8; (offsets[last_cell] - band_start) as usize * RECORD_LEN];
+        let mut cursors = offsets[first_cell..last_cell].to_vec();
+
+        for pixel_data in GzippedBinPixelDataReader::new(data_path)? {
+            let pixel_data: PixelData = pixel_data?;
+            let record = encode_record(&HistoryEntry::from(&pixel_data));
+            for_each_covered_pixel(&pixel_data.coordinate, |(x, y)| {
+                if (y1..y2).contains(&y) {
+                    let cursor = &mut cursors[(y * CANVAS_WIDTH + x) as usize - first_cell];
+                    let start = (*cursor - band_start) as usize * RECORD_LEN;
+                    records[start..start + RECORD_LEN].copy_from_slice(&record);
+                    *cursor += 1;
+                }
+            });
+        }
+        writer
+            .write_all(&records)
+            .whatever_context("Failed to write history index records")?;
+        println!("Indexed rows {}..{} ({}/{})", y1, y2, i + 1, bands.len());
+    }
+    writer
+        .flush()
+        .whatever_context("Failed to flush history index")?;
+    Ok(total)
+}
+
+/// Splits rows into consecutive ranges with at most `max_records` records each, unless a single
+/// row has more. `row_offsets` has the first record of every row, followed by the number of
+/// records.
+fn row_bands(row_offsets: &[u64], max_records: u64) -> Vec<(u32, u32)> {
+    let rows = row_offsets.len() as u32 - 1;
+    let mut bands = Vec::new();
+    let mut y1 = 0;
+    while y1 < rows {
+        let mut y2 = y1 + 1;
+        while y2 < rows && row_offsets[y2 as usize + 1] - row_offsets[y1 as usize] <= max_records {
+            y2 += 1;
+        }
+        bands.push((y1, y2));
+        y1 = y2;
+    }
+    bands
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_row_bands() {
+        use super::row_bands;
+
+        // Rows with 2, 5, 1, 1 and 4 records
+        let row_offsets = [0, 2, 7, 8, 9, 13];
+        assert_eq!(
+            row_bands(&row_offsets, 4),
+            vec![(0, 1), (1, 2), (2, 4), (4, 5)]
+        );
+        assert_eq!(row_bands(&row_offsets, 8), vec![(0, 3), (3, 5)]);
+        assert_eq!(row_bands(&row_offsets, 100), vec![(0, 5)]);
+    }
+
+    #[test]
+    fn test_records_roundtrip() {
+        use super::{decode_record, encode_record};
+        use crate::{
+            data::PixelColor,
+            history::{HistoryEntry, UpdateShape},
+        };
+
+        let entry = HistoryEntry {
+            miliseconds_since_first_pixel: 123_456_789,
+            pixel_color: PixelColor { r: 1, g: 2, b: 3 },
+            shape: UpdateShape::Circle,
+            user: 9_876_543,
+        };
+        assert_eq!(decode_record(&encode_record(&entry)).unwrap(), entry);
+    }
+
+    #[test]
+    fn test_index_matches_history() {
+        use std::io::Write;
+
+        use super::{write_history_index, HistoryIndex, RECORD_LEN};
+        use crate::{
+            canvas::to_canvas_coords,
+            data::{Coordinate, PixelColor, PixelData},
+            history::pixel_history,
+            parse::write_stream_header,
+        };
+
+        let coordinates = [
+            Coordinate::Simple { x: 0, y: 0 },
+            Coordinate::Rectangle {
+                x1: -2,
+                y1: 2,
+                x2: 2,
+                y2: -1,
+            },
+            Coordinate::Simple { x: 1, y: 1 },
+            Coordinate::Circle {
+                x: 0,
+                y: 0,
+                radius: 2,
+            },
+            Coordinate::Simple { x: 0, y: -2 },
+            Coordinate::Simple { x: 3, y: 3 },
+            Coordinate::Rectangle {
+                x1: 1,
+                y1: 0,
+                x2: 3,
+                y2: -3,
+            },
+        ];
+        let pixels: Vec<_> = coordinates
+            .into_iter()
+            .enumerate()
+            .map(|(i, coordinate)| PixelData {
+                miliseconds_since_first_pixel: i as u32 * 10,
+                coordinate,
+                pixel_color: PixelColor {
+                    r: i as u8,
+                    g: 0,
+                    b: 0,
+                },
+                user: i as u32 % 3,
+            })
+            .collect();
+
+        let dir = std::env::temp_dir();
+        let data_path = dir.join(format!("rplace-history-{}.bin", std::process::id()));
+        let data_path = data_path.to_str().unwrap();
+        let file = std::fs::File::create(data_path).unwrap();
+        let mut writer = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+        write_stream_header(&mut writer).unwrap();
+        for pixel_data in &pixels {
+            bincode::encode_into_std_write(pixel_data, &mut writer, bincode::config::standard())
+                .unwrap();
+        }
+        writer.finish().unwrap().flush().unwrap();
+
+        // Bands of at most 4 records split the covered rows into several bands.
+        let index_path = HistoryIndex::path_for(data_path);
+        let total = write_history_index(data_path, &index_path, 4 * RECORD_LEN as u64).unwrap();
+        let mut index = HistoryIndex::open(&index_path).unwrap();
+
+        let mut records = 0;
+        let (cx, cy) = to_canvas_coords((0, 0)).unwrap();
+        for y in cy - 5..=cy + 5 {
+            for x in cx - 5..=cx + 5 {
+                let expected = pixel_history(pixels.iter().cloned().map(Ok), (x, y)).unwrap();
+                assert_eq!(index.pixel((x, y)).unwrap(), expected, "at {x},{y}");
+                records += expected.len() as u64;
+            }
+        }
+        assert_eq!(records, total);
+
+        std::fs::remove_file(data_path).unwrap();
+        std::fs::remove_file(index_path).unwrap();
+    }
+}
@@ -0,0 +1,391 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Write},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    analysis::TimeWindow,
+    bounds::CanvasBounds,
+    canvas::CanvasState,
+    data::PixelData,
+    parse::{write_stream_header, GzippedBinPixelDataReader, STREAM_VERSION},
+    tiles::Tiles,
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct KeyframeEntry {
+    pub miliseconds_since_first_pixel: u32,
+    /// Number of pixel updates in the stream before this keyframe.
+    pub stream_offset: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyframeIndex {
+    /// [`STREAM_VERSION`] of the deltas. Missing from indices built before it was recorded.
+    #[serde(default)]
+    pub version: u32,
+    pub interval_ms: u32,
+    pub keyframes: Vec<KeyframeEntry>,
+}
+
+/// Canvas snapshots taken at a fixed interval of event time, stored in a directory as:
+///
+/// - `index.json`: the [`KeyframeIndex`].
+/// - `keyframe-NNNNN.bin`: gzipped RGB bytes of the canvas with every update before the keyframe.
+/// - `delta-NNNNN.bin`: the updates from the keyframe until the next one, in the same format as
+///   `pixels.bin` including its header, so seeking doesn't have to decompress the stream from the
+///   start.
+pub struct Keyframes {
+    dir: String,
+    index: KeyframeIndex,
+}
+
+fn keyframe_path(dir: &str, index: usize) -> String {
+    format!("{dir}/keyframe-{index:05}.bin")
+}
+
+fn delta_path(dir: &str, index: usize) -> String {
+    format!("{dir}/delta-{index:05}.bin")
+}
+
+fn index_path(dir: &str) -> String {
+    format!("{dir}/index.json")
+}
+
+type DeltaWriter = GzEncoder<BufWriter<File>>;
+
+fn create_gz(path: &str) -> Result<DeltaWriter, Whatever> {
+    let file = File::create(path).with_whatever_context(|_| format!("Failed to create {path}"))?;
+    Ok(GzEncoder::new(BufWriter::new(file), Compression::fast()))
+}
+
+fn finish_gz(writer: DeltaWriter) -> Result<(), Whatever> {
+    writer
+        .finish()
+        .whatever_context("Failed to finish gzip stream")?
+        .flush()
+        .whatever_context("Failed to flush file")
+}
+
+/// Writes the snapshot of keyframe `index` and opens its delta file.
+fn start_keyframe(dir: &str, index: usize, canvas: &CanvasState) -> Result<DeltaWriter, Whatever> {
+    let mut snapshot = create_gz(&keyframe_path(dir, index))?;
+    snapshot
+        .write_all(canvas.as_bytes())
+        .whatever_context("Failed to write keyframe")?;
+    finish_gz(snapshot)?;
+
+    let mut delta = create_gz(&delta_path(dir, index))?;
+    write_stream_header(&mut delta).whatever_context("Failed to write delta header")?;
+    Ok(delta)
+}
+
+/// Replays the pixel updates and writes a keyframe every `interval_ms` of event time to `dir`.
+pub fn write_keyframes(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    dir: &str,
+    interval_ms: u32,
+) -> Result<KeyframeIndex, Whatever> {
+    ensure_whatever!(
+        interval_ms > 0,
+        "Keyframe interval must be greater than zero"
+    );
+    fs::create_dir_all(dir).whatever_context("Failed to create keyframe directory")?;
+    let bincode_config = bincode::config::standard();
+
+    let mut canvas = CanvasState::new();
+    let mut keyframes = vec![KeyframeEntry {
+        miliseconds_since_first_pixel: 0,
+        stream_offset: 0,
+    }];
+    let mut delta = start_keyframe(dir, 0, &canvas)?;
+    let mut next_keyframe_ms = interval_ms as u64;
+
+    for (offset, pixel_data) in iter.enumerate() {
+        let pixel_data = pixel_data?;
+        while pixel_data.miliseconds_since_first_pixel as u64 >= next_keyframe_ms {
+            println!("Keyframe at {}ms", next_keyframe_ms);
+            finish_gz(delta)?;
+            delta = start_keyframe(dir, keyframes.len(), &canvas)?;
+            keyframes.push(KeyframeEntry {
+                miliseconds_since_first_pixel: next_keyframe_ms as u32,
+                stream_offset: offset as u64,
+            });
+            next_keyframe_ms += interval_ms as u64;
+        }
+
+        bincode::encode_into_std_write(&pixel_data, &mut delta, bincode_config)
+            .whatever_context("Failed to write pixel data")?;
+        canvas.apply(&pixel_data);
+    }
+    finish_gz(delta)?;
+
+    let index = KeyframeIndex {
+        version: STREAM_VERSION,
+        interval_ms,
+        keyframes,
+    };
+    let file = File::create(index_path(dir)).whatever_context("Failed to create index")?;
+    serde_json::to_writer_pretty(file, &index).whatever_context("Failed to write index")?;
+    Ok(index)
+}
+
+impl Keyframes {
+    pub fn open(dir: &str) -> Result<Self, Whatever> {
+        let file = File::open(index_path(dir)).whatever_context("Failed to open keyframe index")?;
+        let index: KeyframeIndex =
+            serde_json::from_reader(BufReader::new(file)).whatever_context("Invalid index")?;
+        ensure_whatever!(
+            index.version == STREAM_VERSION,
+            "Keyframes in {dir} were built by an older version, rebuild them with `keyframes`"
+        );
+        ensure_whatever!(!index.keyframes.is_empty(), "Keyframe index is empty");
+        Ok(Self {
+            dir: dir.to_string(),
+            index,
+        })
+    }
+
+    pub fn index(&self) -> &KeyframeIndex {
+        &self.index
+    }
+
+    fn load_snapshot(&self, index: usize) -> Result<CanvasState, Whatever> {
+        let file = File::open(keyframe_path(&self.dir, index))
+            .whatever_context("Failed to open keyframe")?;
+        let mut pixels = Vec::new();
+        GzDecoder::new(BufReader::new(file))
+            .read_to_end(&mut pixels)
+            .whatever_context("Failed to read keyframe")?;
+        CanvasState::from_bytes(pixels)
+    }
+
+    /// Returns the index of the nearest keyframe at or before the given time.
+    fn keyframe_before(&self, miliseconds_since_first_pixel: u32) -> usize {
+        self.index
+            .keyframes
+            .partition_point(|keyframe| {
+                keyframe.miliseconds_since_first_pixel <= miliseconds_since_first_pixel
+            })
+            .saturating_sub(1)
+    }
+
+    /// Loads the nearest keyframe at or before the given time. Returns its canvas and its time.
+    pub fn snapshot_before(
+        &self,
+        miliseconds_since_first_pixel: u32,
+    ) -> Result<(CanvasState, u32), Whatever> {
+        let index = self.keyframe_before(miliseconds_since_first_pixel);
+        let canvas = self.load_snapshot(index)?;
+        Ok((
+            canvas,
+            self.index.keyframes[index].miliseconds_since_first_pixel,
+        ))
+    }
+
+    /// Returns the pixel updates from the nearest keyframe at or before the given time until the
+    /// end of the stream.
+    pub fn deltas_from(&self, miliseconds_since_first_pixel: u32) -> DeltaReader {
+        DeltaReader {
+            dir: self.dir.clone(),
+            next: self.keyframe_before(miliseconds_since_first_pixel),
+            len: self.index.keyframes.len(),
+            current: None,
+        }
+    }
+
+    /// Loads the nearest keyframe at or before the given time. Returns its canvas and the pixel
+    /// updates from the keyframe until the end of the stream.
+    pub fn seek(
+        &self,
+        miliseconds_since_first_pixel: u32,
+    ) -> Result<(CanvasState, DeltaReader), Whatever> {
+        let canvas = self.load_snapshot(self.keyframe_before(miliseconds_since_first_pixel))?;
+        Ok((canvas, self.deltas_from(miliseconds_since_first_pixel)))
+    }
+
+    /// Rebuilds the canvas with every update up to and including the given time.
+    pub fn canvas_at(&self, miliseconds_since_first_pixel: u32) -> Result<CanvasState, Whatever> {
+        let (mut canvas, deltas) = self.seek(miliseconds_since_first_pixel)?;
+        for pixel_data in deltas {
+            let pixel_data = pixel_data?;
+            if pixel_data.miliseconds_since_first_pixel > miliseconds_since_first_pixel {
+                break;
+            }
+            canvas.apply(&pixel_data);
+        }
+        Ok(canvas)
+    }
+}
+
+/// Pixel updates from any source, such as `pixels.bin` or keyframe deltas.
+pub type PixelDataIter = Box<dyn Iterator<Item = Result<PixelData, Whatever>>>;
+
+/// Returns a canvas at or before the given time and the updates following it, from the keyframes
+/// in `keyframes_dir` if given, otherwise a blank canvas and the dataset from the start.
+///
+/// If the dataset has tiles, only the updates whose bounding box overlaps `region` are read, so
+/// the canvas is only correct inside of it. Tiles built from another version of the dataset are
+/// an error, see [`Tiles::open_for`].
+pub fn replay_from(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    miliseconds_since_first_pixel: u32,
+    region: CanvasBounds,
+) -> Result<(CanvasState, PixelDataIter), Whatever> {
+    if let Some(tiles) = Tiles::open_for(data_path)? {
+        let (canvas, start_ms) = match keyframes_dir {
+            Some(dir) => Keyframes::open(dir)?.snapshot_before(miliseconds_since_first_pixel)?,
+            None => (CanvasState::new(), 0),
+        };
+        let window = TimeWindow {
+            start_ms,
+            ..TimeWindow::ALL
+        };
+        return Ok((canvas, Box::new(tiles.query(region, window)?)));
+    }
+    match keyframes_dir {
+        Some(dir) => {
+            let (canvas, deltas) = Keyframes::open(dir)?.seek(miliseconds_since_first_pixel)?;
+            Ok((canvas, Box::new(deltas)))
+        }
+        None => Ok((
+            CanvasState::new(),
+            Box::new(GzippedBinPixelDataReader::new(data_path)?),
+        )),
+    }
+}
+
+/// Returns the updates from a keyframe at or before the given time if `keyframes_dir` is given,
+/// otherwise the dataset from the start. Meant for analyses that skip everything before a time.
+///
+/// If the dataset has tiles, only the updates from the given time whose bounding box overlaps
+/// `region` are read.
+pub fn updates_from(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    miliseconds_since_first_pixel: u32,
+    region: CanvasBounds,
+) -> Result<PixelDataIter, Whatever> {
+    if let Some(tiles) = Tiles::open_for(data_path)? {
+        let window = TimeWindow {
+            start_ms: miliseconds_since_first_pixel,
+            ..TimeWindow::ALL
+        };
+        return Ok(Box::new(tiles.query(region, window)?));
+    }
+    match keyframes_dir {
+        Some(dir) => Ok(Box::new(
+            Keyframes::open(dir)?.deltas_from(miliseconds_since_first_pixel),
+        )),
+        None => Ok(Box::new(GzippedBinPixelDataReader::new(data_path)?)),
+    }
+}
+
+/// Rebuilds the canvas with every update up to and including the given time, from the keyframes
+/// in `keyframes_dir` if given, otherwise by replaying the dataset from the start. Only `region`
+/// is guaranteed to be correct, see [`replay_from`].
+pub fn canvas_at(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    miliseconds_since_first_pixel: u32,
+    region: CanvasBounds,
+) -> Result<CanvasState, Whatever> {
+    let (mut canvas, updates) = replay_from(
+        data_path,
+        keyframes_dir,
+        miliseconds_since_first_pixel,
+        region,
+    )?;
+    for pixel_data in updates {
+        let pixel_data = pixel_data?;
+        if pixel_data.miliseconds_since_first_pixel > miliseconds_since_first_pixel {
+            break;
+        }
+        canvas.apply(&pixel_data);
+    }
+    Ok(canvas)
+}
+
+/// Reads the pixel updates of consecutive delta files.
+pub struct DeltaReader {
+    dir: String,
+    next: usize,
+    len: usize,
+    current: Option<GzippedBinPixelDataReader>,
+}
+
+impl Iterator for DeltaReader {
+    type Item = Result<PixelData, Whatever>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(pixel_data) = self.current.as_mut().and_then(|reader| reader.next()) {
+                return Some(pixel_data);
+            }
+            if self.next >= self.len {
+                return None;
+            }
+            let path = delta_path(&self.dir, self.next);
+            self.next += 1;
+            match GzippedBinPixelDataReader::new(&path) {
+                Ok(reader) => self.current = Some(reader),
+                Err(e) => {
+                    self.next = self.len;
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_keyframes_roundtrip() {
+        use super::{write_keyframes, Keyframes};
+        use crate::{
+            canvas::CanvasState,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let pixels: Vec<_> = (0..100u32)
+            .map(|i| PixelData {
+                miliseconds_since_first_pixel: i * 7,
+                coordinate: Coordinate::Simple {
+                    x: (i % 10) as i16,
+                    y: (i / 10) as i16,
+                },
+                pixel_color: PixelColor {
+                    r: i as u8,
+                    g: 0,
+                    b: 0,
+                },
+                user: i % 3,
+            })
+            .collect();
+
+        let dir = std::env::temp_dir().join(format!("rplace-keyframes-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let index = write_keyframes(pixels.iter().cloned().map(Ok), dir, 100).unwrap();
+        assert_eq!(index.keyframes.len(), 7);
+        assert_eq!(index.keyframes[1].stream_offset, 15);
+
+        let keyframes = Keyframes::open(dir).unwrap();
+        for ms in [0, 99, 100, 350, 693, 10000] {
+            let mut expected = CanvasState::new();
+            pixels
+                .iter()
+                .filter(|pixel_data| pixel_data.miliseconds_since_first_pixel <= ms)
+                .for_each(|pixel_data| expected.apply(pixel_data));
+            assert!(keyframes.canvas_at(ms).unwrap() == expected, "at {ms}ms");
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
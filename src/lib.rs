@@ -1,10 +1,35 @@
-use renderer::App;
+use std::fs::File;
 
-use crate::{data::Coordinate, parse::GzippedBinPixelDataReader};
+use renderer::{App, PlayerOptions};
+use snafu::{prelude::*, Whatever};
 
+use crate::{
+    analysis::TimeWindow,
+    atlas::Atlas,
+    bounds::{BoundsTimeline, CanvasBounds},
+    data::Coordinate,
+    metadata::DatasetMetadata,
+    parse::GzippedBinPixelDataReader,
+};
+
+pub mod analysis;
+pub mod animate;
+pub mod atlas;
+pub mod bounds;
+pub mod canvas;
 pub mod data;
+pub mod export;
+pub mod history;
+pub mod history_index;
+pub mod keyframe;
+pub mod metadata;
+pub mod palette;
 pub mod parse;
+pub mod ramp;
 mod renderer;
+pub mod tiles;
+pub mod timelapse;
+pub mod user_table;
 
 pub fn get_max_min_coord() {
     let iter = GzippedBinPixelDataReader::new("pixels.bin").unwrap();
@@ -32,65 +57,14 @@ pub fn get_max_min_coord() {
     println!("min: {:?}, max: {:?}", min, max);
 }
 
+/// Counts the canvas pixels no update ever covered. Covered pixels follow
+/// [`canvas::for_each_covered_pixel`] like the player, so circles include their border.
 pub fn find_never_updated_pixels() {
     let iter = GzippedBinPixelDataReader::new("pixels.bin").unwrap();
-    let mut pixels = vec![false; 3000 * 2000];
+    let counts =
+        analysis::heatmap::count_updates(iter, TimeWindow::ALL, CanvasBounds::FULL).unwrap();
 
-    for (index, pixel_data) in iter.enumerate() {
-        if index % 100000 == 0 {
-            println!("{}", index);
-        }
-        let pixel_data = pixel_data.unwrap();
-        fn convert((x, y): (i16, i16)) -> Option<(usize, usize)> {
-            let x = x as i32 + 1500;
-            let y = -y as i32 - 1 + 1000;
-            if x >= 0 && x < 3000 && y >= 0 && y < 2000 {
-                Some((x as usize, y as usize))
-            } else {
-                None
-            }
-        }
-        match pixel_data.coordinate {
-            Coordinate::Simple { x, y } => {
-                if let Some((x, y)) = convert((x, y)) {
-                    pixels[y * 3000 + x] = true;
-                }
-            }
-            Coordinate::Circle { x, y, radius } => {
-                if let Some((x, y)) = convert((x, y)) {
-                    let (min_i, max_i) = (
-                        x.saturating_sub(radius as usize),
-                        (x + radius as usize).min(2999),
-                    );
-                    let (min_j, max_j) = (
-                        y.saturating_sub(radius as usize),
-                        (y + radius as usize).min(1999),
-                    );
-
-                    for i in min_i..=max_i {
-                        for j in min_j..=max_j {
-                            if (i as i32 - x as i32).pow(2) + (j as i32 - y as i32).pow(2)
-                                < radius.pow(2) as i32
-                            {
-                                pixels[j * 3000 + i] = true;
-                            }
-                        }
-                    }
-                }
-            }
-            Coordinate::Rectangle { x1, y1, x2, y2 } => {
-                if let (Some((x1, y1)), Some((x2, y2))) = (convert((x1, y1)), convert((x2, y2))) {
-                    for i in x1..x2 {
-                        for j in y1..y2 {
-                            pixels[j * 3000 + i] = true;
-                        }
-                    }
-                }
-            }
-        }
-    }
-
-    let never_updated = pixels.iter().filter(|&&x| !x).count();
+    let never_updated = counts.counts.iter().filter(|&&count| count == 0).count();
 
     println!("Never updated: {}", never_updated);
 }
@@ -115,8 +89,67 @@ pub fn print_quad_circle() {
     }
 }
 
-pub fn run() {
+/// Writes the dataset metadata. The canvas bounds timeline is read from `bounds_config` if given,
+/// otherwise it is detected from the pixel updates.
+pub fn write_metadata(
+    data_path: &str,
+    bounds_config: Option<&str>,
+    snap: u32,
+    merge_window_ms: u32,
+) -> Result<(), Whatever> {
+    let canvas_bounds = match bounds_config {
+        Some(path) => {
+            let file = File::open(path).whatever_context("Failed to open bounds config")?;
+            serde_json::from_reader(file).whatever_context("Failed to parse bounds config")?
+        }
+        None => BoundsTimeline::detect(
+            GzippedBinPixelDataReader::new(data_path)?,
+            snap,
+            merge_window_ms,
+        )?,
+    };
+    for stage in &canvas_bounds.stages {
+        println!(
+            "{}ms: {:?} ({}x{})",
+            stage.miliseconds_since_first_pixel,
+            stage.bounds,
+            stage.bounds.width(),
+            stage.bounds.height()
+        );
+    }
+
+    let mut metadata = DatasetMetadata::load_or_default(data_path)?;
+    metadata.canvas_bounds = canvas_bounds;
+    metadata.save(data_path)
+}
+
+pub fn run(
+    data_path: &str,
+    playback_speed: u32,
+    follow_bounds: bool,
+    max_age_ms: u32,
+    atlas_path: Option<&str>,
+) -> Result<(), Whatever> {
+    let metadata = DatasetMetadata::load_or_default(data_path)?;
+    let atlas = atlas_path.map(Atlas::load).transpose()?;
     let mut app = App::new();
 
-    app.run(GzippedBinPixelDataReader::new("pixels.bin").unwrap(), 10000);
+    app.run(
+        GzippedBinPixelDataReader::new(data_path)?,
+        PlayerOptions {
+            playback_speed,
+            canvas_bounds: metadata.canvas_bounds,
+            follow_bounds,
+            max_age_ms,
+            // Events saved before the end of their series was kept end the timeline themselves.
+            timeline_end_ms: metadata.activity_end_ms.or(metadata
+                .activity_events
+                .iter()
+                .map(|event| event.end_ms)
+                .max()),
+            events: metadata.activity_events,
+            atlas,
+        },
+    );
+    Ok(())
 }
@@ -1,12 +1,1336 @@
-// use dotenvy::dotenv;
+use std::{error::Error, str::FromStr};
 
-fn main() {
+use clap::{Args, Parser, Subcommand};
+use rplace_2023::{
+    analysis::{
+        activity::{self, ActivityOptions, DetectionOptions},
+        age::{self, AgeMapOptions},
+        artworks::{self, ArtworkStatsOptions},
+        battles::{self, BattleFrames, BattleOptions},
+        colors,
+        cooldown::{self, CooldownOptions},
+        diff::{self, DiffOptions},
+        factions::{self, FactionOptions},
+        griefing::{self, GriefingOptions},
+        heatmap::{self, HeatmapOptions},
+        moderation::{self, ModerationCrops},
+        search::{self, SearchOptions},
+        segmentation::{self, SegmentationOptions},
+        stability::{self, StabilityOptions},
+        template::{self, Anchor, Template, TemplateOptions},
+        transitions::{self, TransitionOptions},
+        users::{self, UserStatsOptions},
+        TableFormat, TimeWindow,
+    },
+    animate::{self, AnimationFormat, AnimationOptions},
+    atlas::Atlas,
+    bounds::CanvasBounds,
+    data::EventTime,
+    export,
+    history::{self, HistoryFormat},
+    history_index::{self, HistoryIndex},
+    keyframe,
+    parse::GzippedBinPixelDataReader,
+    ramp::ColorRamp,
+    tiles::{self, SourceFingerprint, Tiles},
+    timelapse::{self, FrameRange, TimelapseFormat, TimelapseOptions},
+};
+use snafu::{whatever, OptionExt, Whatever};
+
+#[derive(Parser)]
+#[command(
+    about = "r/place 2023 player and tools",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    /// Path to the gzipped pixel updates.
+    #[arg(long, global = true, default_value = "pixels.bin")]
+    data: String,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    play: PlayArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Play the pixel updates in a window. This is the default command.
+    Play(PlayArgs),
+    /// Convert the gzipped CSV files of the official dataset to the pixel updates at `--data`,
+    /// with the user table next to them.
+    Convert {
+        /// Directory with the 53 `2023_place_canvas_history-*.csv.gzip` files.
+        dir: String,
+    },
+    /// Write the dataset metadata, such as the canvas expansion timeline.
+    Meta {
+        /// JSON file with the canvas bounds timeline. Detected from the data if not given.
+        #[arg(long)]
+        bounds_config: Option<String>,
+        /// Grid in pixels the detected bounds are aligned to.
+        #[arg(long, default_value_t = 500)]
+        snap: u32,
+        /// Expansions within this many milliseconds are merged into one stage.
+        #[arg(long, default_value_t = 600_000)]
+        merge_window_ms: u32,
+    },
+    /// Write canvas snapshots at a fixed interval of event time for fast seeking.
+    Keyframes {
+        /// Directory to write the keyframes to.
+        #[arg(long, default_value = "keyframes")]
+        output: String,
+        /// Event time between two keyframes.
+        #[arg(long, default_value_t = 30)]
+        interval_minutes: u32,
+    },
+    /// Split the updates into tiles and time buckets next to the dataset, so region exports and
+    /// analyses only read the updates they need.
+    Tiles {
+        /// Width and height of a tile in pixels.
+        #[arg(long, default_value_t = 100)]
+        tile_size: u32,
+        /// Event time covered by a block of a tile.
+        #[arg(long, default_value_t = 60)]
+        bucket_minutes: u32,
+    },
+    /// Write the canvas at a given time as PNG.
+    Snapshot {
+        /// Milliseconds since the Unix epoch or a UTC timestamp.
+        #[arg(long, value_parser = parse::<EventTime>)]
+        time: EventTime,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Write frames of the canvas at a fixed interval of event time.
+    Timelapse {
+        #[command(flatten)]
+        range: RangeArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(long, value_enum, default_value_t = TimelapseFormat::Png)]
+        format: TimelapseFormat,
+        /// Frame rate written to the Y4M header.
+        #[arg(long, default_value_t = 30)]
+        fps: u32,
+        /// Output directory for PNG frames, or file for Y4M (`-` for stdout).
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Write an animated GIF or APNG of a small region.
+    Animate {
+        #[command(flatten)]
+        range: RangeArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(long, value_enum, default_value_t = AnimationFormat::Gif)]
+        format: AnimationFormat,
+        /// Display time of one frame.
+        #[arg(long, default_value_t = 100)]
+        frame_delay_ms: u32,
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Render the number of updates of every pixel as a heatmap.
+    Heatmap {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(long, value_enum, default_value_t = ColorRamp::Inferno)]
+        ramp: ColorRamp,
+        /// Scale the counts logarithmically.
+        #[arg(long)]
+        log: bool,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the raw counts as a `.npy` array of `u32` with shape (height, width).
+        #[arg(long)]
+        raw: Option<String>,
+    },
+    /// Count the placements and pixels of every palette color over time.
+    Colors {
+        #[command(flatten)]
+        window: WindowArgs,
+        /// Event time covered by a row of the table.
+        #[arg(long, default_value_t = 60)]
+        bucket_minutes: u32,
+        /// Two opposite corners `x1,y1,x2,y2` in dataset coordinates. Only its pixels and the
+        /// updates overlapping it are counted. Defaults to the whole canvas.
+        #[arg(long, value_parser = parse::<CanvasBounds>)]
+        region: Option<CanvasBounds>,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Measure the update rate over time and detect spikes and lulls.
+    Activity {
+        #[command(flatten)]
+        window: WindowArgs,
+        /// Event time covered by a row of the table.
+        #[arg(long, default_value_t = 10)]
+        resolution_seconds: u32,
+        /// Event time on every side of a row that its usual rate is measured over.
+        #[arg(long, default_value_t = 30)]
+        baseline_minutes: u32,
+        /// Rows with more than this times their usual rate are spikes.
+        #[arg(long, default_value_t = 3.0)]
+        spike_factor: f64,
+        /// Rows with less than this times their usual rate are lulls.
+        #[arg(long, default_value_t = 0.25)]
+        lull_factor: f64,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the detected events as JSON.
+        #[arg(long)]
+        events: Option<String>,
+        /// Store the events in the dataset metadata, so the player marks them on its timeline and
+        /// names the one playing in its window title.
+        #[arg(long)]
+        save_events: bool,
+    },
+    /// Count how often pixels changed from one palette color to another.
+    Transitions {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Count a separate matrix for this region, given as two opposite corners `x1,y1,x2,y2`
+        /// in dataset coordinates. Can be repeated. Defaults to the whole canvas.
+        #[arg(long = "region", value_parser = parse::<CanvasBounds>)]
+        regions: Vec<CanvasBounds>,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also render the matrices as PNG, numbered by region if there are several.
+        #[arg(long)]
+        image: Option<String>,
+        #[arg(long, value_enum, default_value_t = ColorRamp::Inferno)]
+        ramp: ColorRamp,
+        /// Scale the counts logarithmically.
+        #[arg(long)]
+        log: bool,
+        /// Size of a matrix cell in the image.
+        #[arg(long, default_value_t = 16)]
+        scale: u32,
+    },
+    /// Find the regions where colors kept overwriting each other, ranked by overwrites.
+    Battles {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Width and height of a grid cell in pixels.
+        #[arg(long, default_value_t = 50)]
+        cell_size: u32,
+        /// Event time overwrites are counted over.
+        #[arg(long, default_value_t = 10)]
+        bin_minutes: u32,
+        /// Overwrites a cell needs in one bin to be contested.
+        #[arg(long, default_value_t = 100)]
+        min_overwrites: u32,
+        /// Number of battles to keep.
+        #[arg(long, default_value_t = 20)]
+        top: usize,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also write a timelapse of every battle as PNG frames into numbered directories here.
+        #[arg(long)]
+        frames: Option<String>,
+        /// Event time between two frames of a battle timelapse.
+        #[arg(long, default_value_t = 10)]
+        frame_interval_seconds: u32,
+        /// Integer upscaling factor of the battle timelapses.
+        #[arg(long, default_value_t = 4)]
+        scale: u32,
+    },
+    /// Color every pixel by how long it held its color at the end of the time window, the final
+    /// canvas by default. Color changes are counted during the window.
+    Stability {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[arg(long, value_enum, default_value_t = ColorRamp::Inferno)]
+        ramp: ColorRamp,
+        /// Scale the durations and change counts logarithmically.
+        #[arg(long)]
+        log: bool,
+        #[arg(long, short)]
+        output: String,
+        /// Also render the number of color changes of every pixel as PNG.
+        #[arg(long)]
+        changes: Option<String>,
+        /// Also write the durations in milliseconds as a `.npy` array of `u32` with shape
+        /// (height, width).
+        #[arg(long)]
+        raw_held: Option<String>,
+        /// Also write the change counts as a `.npy` array of `u32` with shape (height, width).
+        #[arg(long)]
+        raw_changes: Option<String>,
+    },
+    /// Track how much of a template image matched the canvas over time.
+    Template {
+        /// PNG with the wanted colors. Transparent pixels can have any color.
+        template: String,
+        /// Position `x,y` of the top left pixel of the template in dataset coordinates.
+        #[arg(long, value_parser = parse::<Anchor>)]
+        anchor: Anchor,
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Event time between two rows of the table.
+        #[arg(long, default_value_t = 60)]
+        interval_seconds: u32,
+        /// Share of the template in percent that has to be destroyed to count as an attack.
+        #[arg(long, default_value_t = 5.0)]
+        attack_percent: f64,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the first completion and the attacks as JSON.
+        #[arg(long)]
+        report: Option<String>,
+    },
+    /// Find the earliest time and place a reference image appeared on the canvas.
+    Search {
+        /// PNG to search for. Transparent pixels can have any color.
+        image: String,
+        /// Share of the opaque pixels that have to match, from 0 to 1.
+        #[arg(long, default_value_t = 0.9)]
+        threshold: f64,
+        /// Part of the canvas to search, as two opposite corners `x1,y1,x2,y2` in dataset
+        /// coordinates. Defaults to the whole canvas.
+        #[arg(long, value_parser = parse::<CanvasBounds>)]
+        region: Option<CanvasBounds>,
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Event time between two searched frames. The exact time is found by replaying the
+        /// updates between the frames.
+        #[arg(long, default_value_t = 10)]
+        interval_minutes: u32,
+        /// Matching positions of the first frame containing the image that are replayed to find
+        /// the exact time, the best matching first. Images with little detail can match in more
+        /// places, and the earliest sighting may be among the ones left out.
+        #[arg(long, default_value_t = 64)]
+        max_candidates: usize,
+        /// Also write the sighting as JSON.
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Compare the canvas at two times and count the changed pixels by color.
+    Diff {
+        /// Milliseconds since the Unix epoch or a UTC timestamp.
+        #[arg(long, value_parser = parse::<EventTime>)]
+        from: EventTime,
+        /// Milliseconds since the Unix epoch or a UTC timestamp.
+        #[arg(long, value_parser = parse::<EventTime>)]
+        to: EventTime,
+        /// Count the changes of this region separately, given as two opposite corners
+        /// `x1,y1,x2,y2` in dataset coordinates. Can be repeated. Defaults to the whole canvas.
+        #[arg(long = "region", value_parser = parse::<CanvasBounds>)]
+        regions: Vec<CanvasBounds>,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the canvas at the end with unchanged pixels dimmed as PNG, covering all
+        /// regions.
+        #[arg(long)]
+        image: Option<String>,
+        /// Integer upscaling factor of the image.
+        #[arg(long, default_value_t = 1)]
+        scale: u32,
+    },
+    /// Write the pixels, placements, overwrites and survival of every artwork of a Place Atlas
+    /// file as a table.
+    ArtworkStats {
+        /// Place Atlas JSON file with artwork outlines in canvas coordinates.
+        atlas: String,
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Percentage of the pixels that have to have their final color for an artwork to count
+        /// as settled.
+        #[arg(long, default_value_t = 90.0)]
+        settle_percent: f64,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Write a timelapse of the bounding box of an artwork from a Place Atlas file.
+    ArtworkTimelapse {
+        /// Place Atlas JSON file with artwork outlines in canvas coordinates.
+        atlas: String,
+        /// Id or name of the artwork.
+        #[arg(long)]
+        artwork: String,
+        #[command(flatten)]
+        range: RangeArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Integer upscaling factor.
+        #[arg(long, default_value_t = 1)]
+        scale: u32,
+        #[arg(long, value_enum, default_value_t = TimelapseFormat::Png)]
+        format: TimelapseFormat,
+        /// Frame rate written to the Y4M header.
+        #[arg(long, default_value_t = 30)]
+        fps: u32,
+        /// Output directory for PNG frames, or file for Y4M (`-` for stdout).
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Detect likely artworks as connected regions that were built in bursts and stayed stable,
+    /// and write their outlines and lifetimes as JSON, which loads as an atlas.
+    DetectArtworks {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        export: ExportArgs,
+        /// Event time between two segmentations of the canvas.
+        #[arg(long, default_value_t = 60)]
+        interval_minutes: u32,
+        /// How long a pixel has to hold its color to be part of an artwork.
+        #[arg(long, default_value_t = 60)]
+        min_stable_minutes: u32,
+        /// Neighbors last changed within this many minutes of each other were built together.
+        #[arg(long, default_value_t = 30)]
+        burst_minutes: u32,
+        /// Leave out smaller candidates.
+        #[arg(long, default_value_t = 100)]
+        min_pixels: usize,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the canvas with the candidates outlined as PNG.
+        #[arg(long)]
+        overlay: Option<String>,
+    },
+    /// Write the updates, active time span and most used colors of every user, the most active
+    /// first, and optionally the updates of one user as heatmap.
+    Users {
+        #[command(flatten)]
+        window: WindowArgs,
+        /// Number of the most used colors listed for every user.
+        #[arg(long, default_value_t = 3)]
+        top_colors: usize,
+        /// Number of the most active users printed.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the number of users by their number of updates as a table.
+        #[arg(long)]
+        distribution: Option<String>,
+        /// Hash or id of the user whose updates are written with `--heatmap`.
+        #[arg(long, requires = "heatmap")]
+        user: Option<String>,
+        /// Write a heatmap of the updates of `--user` as PNG.
+        #[arg(long, requires = "user")]
+        heatmap: Option<String>,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        #[arg(long, value_enum, default_value_t = ColorRamp::Inferno)]
+        ramp: ColorRamp,
+        /// Scale the heatmap counts logarithmically.
+        #[arg(long)]
+        log: bool,
+    },
+    /// Rank users by how likely they placed with a script: faster than the cooldown, at regular
+    /// intervals or with little variety in their timing.
+    Cooldown {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[arg(long, default_value_t = 300)]
+        cooldown_seconds: u32,
+        /// Width of the interval bins the timing entropy is measured with.
+        #[arg(long, default_value_t = 1000)]
+        bin_ms: u32,
+        /// Leave out users with fewer placements.
+        #[arg(long, default_value_t = 20)]
+        min_placements: usize,
+        /// Flag users whose intervals vary less than this share of their mean as regular.
+        #[arg(long, default_value_t = 0.1)]
+        max_variation: f64,
+        /// Flag users with a lower normalized timing entropy as low entropy.
+        #[arg(long, default_value_t = 0.5)]
+        max_entropy: f64,
+        /// Number of the highest scoring users printed.
+        #[arg(long, default_value_t = 10)]
+        top: usize,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+    },
+    /// Cluster users into factions by the tiles they placed in during the same time bins, and
+    /// write the faction of every user and a heatmap of every faction's placements.
+    Factions {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Width and height of a tile in pixels.
+        #[arg(long, default_value_t = 50)]
+        tile_size: u32,
+        /// Event time placements in the same tile count together over.
+        #[arg(long, default_value_t = 60)]
+        bin_minutes: u32,
+        #[arg(long, default_value_t = 8)]
+        factions: usize,
+        /// Leave out users with fewer placements.
+        #[arg(long, default_value_t = 10)]
+        min_placements: u32,
+        #[arg(long, default_value_t = 30)]
+        max_iterations: usize,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Directory to write the footprint of every faction to as `faction-01.png` and so on.
+        #[arg(long)]
+        footprints: String,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[arg(long, value_enum, default_value_t = ColorRamp::Inferno)]
+        ramp: ColorRamp,
+        /// Scale the footprint counts logarithmically.
+        #[arg(long)]
+        log: bool,
+    },
+    /// Find damage to stable parts of the canvas and how quickly it was reverted, grouped into
+    /// events with their location, time, size and revert latency.
+    Griefing {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Two opposite corners `x1,y1,x2,y2` in dataset coordinates. Defaults to the whole canvas.
+        #[arg(long, value_parser = parse::<CanvasBounds>)]
+        region: Option<CanvasBounds>,
+        /// Time a pixel has to hold its color for a change of it to count as damage.
+        #[arg(long, default_value_t = 30)]
+        min_stable_minutes: u32,
+        /// Time a damaged pixel has to get its previous color back in to count as reverted.
+        #[arg(long, default_value_t = 600)]
+        revert_seconds: u32,
+        /// Width and height of the grid cells damage is grouped in.
+        #[arg(long, default_value_t = 10)]
+        cell_size: u32,
+        /// Longest pause between damage in the same or neighboring cells of one event.
+        #[arg(long, default_value_t = 120)]
+        gap_seconds: u32,
+        /// Leave out events with fewer damaged pixels.
+        #[arg(long, default_value_t = 5)]
+        min_pixels: usize,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+    },
+    /// List every rectangle and circle fill of the admins and moderators with the pixels it
+    /// overwrote, and optionally snapshots before and after every one.
+    Moderation {
+        #[command(flatten)]
+        window: WindowArgs,
+        #[command(flatten)]
+        source: SourceArgs,
+        /// Two opposite corners `x1,y1,x2,y2` in dataset coordinates. Only fills overlapping it
+        /// are listed, and only their pixels inside it count as overwritten. Defaults to the whole
+        /// canvas.
+        #[arg(long, value_parser = parse::<CanvasBounds>)]
+        region: Option<CanvasBounds>,
+        #[arg(long, value_enum, default_value_t = TableFormat::Csv)]
+        format: TableFormat,
+        #[arg(long, short)]
+        output: String,
+        /// Also write crops of the canvas before and after every fill as PNG here.
+        #[arg(long)]
+        crops: Option<String>,
+        /// Pixels of context around every crop.
+        #[arg(long, default_value_t = 20)]
+        margin: u32,
+        /// Integer upscaling factor of the crops.
+        #[arg(long, default_value_t = 4)]
+        scale: u32,
+    },
+    /// List every update that covered a pixel, including moderator fills.
+    History {
+        /// X in dataset coordinates.
+        #[arg(allow_negative_numbers = true)]
+        x: i16,
+        /// Y in dataset coordinates.
+        #[arg(allow_negative_numbers = true)]
+        y: i16,
+        #[arg(long, value_enum, default_value_t = HistoryFormat::Table)]
+        format: HistoryFormat,
+        /// File to write to instead of stdout.
+        #[arg(long, short)]
+        output: Option<String>,
+    },
+    /// Build the pixel-major history index next to the dataset, which makes `history` fast.
+    HistoryIndex {
+        /// Memory for the records of one band of rows. The dataset is read once per band.
+        #[arg(long, default_value_t = 1024)]
+        memory_mb: u64,
+    },
+    /// Color every pixel by how long ago it last changed at a given time.
+    Age {
+        /// Milliseconds since the Unix epoch or a UTC timestamp.
+        #[arg(long, value_parser = parse::<EventTime>)]
+        time: EventTime,
+        #[command(flatten)]
+        export: ExportArgs,
+        #[arg(long, value_enum, default_value_t = ColorRamp::Inferno)]
+        ramp: ColorRamp,
+        /// Scale the ages logarithmically.
+        #[arg(long)]
+        log: bool,
+        /// Ages at or above this are drawn with the coldest color. Defaults to the oldest pixel.
+        #[arg(long)]
+        max_age_minutes: Option<u32>,
+        #[arg(long, short)]
+        output: String,
+        /// Also write the ages in milliseconds as a `.npy` array of `u32` with shape
+        /// (height, width).
+        #[arg(long)]
+        raw: Option<String>,
+    },
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    /// Two opposite corners `x1,y1,x2,y2` in dataset coordinates. Defaults to the whole canvas.
+    #[arg(long, value_parser = parse::<CanvasBounds>)]
+    region: Option<CanvasBounds>,
+    /// Integer upscaling factor.
+    #[arg(long, default_value_t = 1)]
+    scale: u32,
+}
+
+#[derive(Args)]
+struct SourceArgs {
+    /// Keyframe directory to seek with instead of replaying from the start.
+    #[arg(long)]
+    keyframes: Option<String>,
+}
+
+#[derive(Args)]
+struct WindowArgs {
+    /// Start time, as milliseconds since the Unix epoch or a UTC timestamp. Defaults to the start
+    /// of the event.
+    #[arg(long, value_parser = parse::<EventTime>)]
+    start: Option<EventTime>,
+    /// End time, inclusive. Defaults to the end of the data.
+    #[arg(long, value_parser = parse::<EventTime>)]
+    end: Option<EventTime>,
+}
+
+impl WindowArgs {
+    fn time_window(&self) -> TimeWindow {
+        TimeWindow {
+            start_ms: self
+                .start
+                .map_or(0, |time| time.miliseconds_since_first_pixel),
+            end_ms: self
+                .end
+                .map_or(u32::MAX, |time| time.miliseconds_since_first_pixel),
+        }
+    }
+}
+
+#[derive(Args)]
+struct RangeArgs {
+    #[command(flatten)]
+    window: WindowArgs,
+    /// Event time between two frames.
+    #[arg(long, default_value_t = 60)]
+    interval_seconds: u32,
+}
+
+impl RangeArgs {
+    fn frame_range(&self) -> Result<FrameRange, Whatever> {
+        let window = self.window.time_window();
+        Ok(FrameRange {
+            start_ms: window.start_ms,
+            end_ms: self.window.end.map(|_| window.end_ms),
+            interval_ms: seconds_to_ms(self.interval_seconds)?,
+        })
+    }
+}
+
+#[derive(Args)]
+struct PlayArgs {
+    /// Playback speed relative to real time.
+    #[arg(long, default_value_t = 10000)]
+    speed: u32,
+    /// Frame the view to the opened area of the canvas as it grows.
+    #[arg(long)]
+    follow_bounds: bool,
+    /// Age at which pixels get the coldest color in the age view, toggled with `A`.
+    #[arg(long, default_value_t = 60)]
+    max_age_minutes: u32,
+    /// Place Atlas JSON file. The artwork under the cursor is outlined and named next to the
+    /// cursor.
+    #[arg(long)]
+    atlas: Option<String>,
+}
+
+fn parse<T: FromStr<Err = Box<dyn Error>>>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|e: Box<dyn Error>| e.to_string())
+}
+
+/// Converts a duration option in minutes to milliseconds of event time.
+fn minutes_to_ms(minutes: u32) -> Result<u32, Whatever> {
+    minutes
+        .checked_mul(60_000)
+        .with_whatever_context(|| format!("{minutes} minutes are too long"))
+}
+
+/// Converts a duration option in seconds to milliseconds of event time.
+fn seconds_to_ms(seconds: u32) -> Result<u32, Whatever> {
+    seconds
+        .checked_mul(1000)
+        .with_whatever_context(|| format!("{seconds} seconds are too long"))
+}
+
+fn main() -> Result<(), Whatever> {
     env_logger::init();
-    // dotenv().ok();
-    // let instant = std::time::Instant::now();
-    // get_max_min_coord();
-    // println!("Time: {:?}", instant.elapsed());
-    rplace_2023::run();
-    // rplace_2023::find_never_updated_pixels();
-    // rplace_2023::print_quad_circle();
+    let cli = Cli::parse();
+
+    match cli.command.unwrap_or(Command::Play(cli.play)) {
+        Command::Play(args) => rplace_2023::run(
+            &cli.data,
+            args.speed,
+            args.follow_bounds,
+            minutes_to_ms(args.max_age_minutes)?,
+            args.atlas.as_deref(),
+        ),
+        Command::Convert { dir } => rplace_2023::parse::parse_and_write_to_bin(&dir, &cli.data),
+        Command::Meta {
+            bounds_config,
+            snap,
+            merge_window_ms,
+        } => {
+            rplace_2023::write_metadata(&cli.data, bounds_config.as_deref(), snap, merge_window_ms)
+        }
+        Command::Keyframes {
+            output,
+            interval_minutes,
+        } => {
+            let index = keyframe::write_keyframes(
+                GzippedBinPixelDataReader::new(&cli.data)?,
+                &output,
+                minutes_to_ms(interval_minutes)?,
+            )?;
+            println!("Wrote {} keyframes", index.keyframes.len());
+            Ok(())
+        }
+        Command::Snapshot {
+            time,
+            export,
+            source,
+            output,
+        } => export::write_snapshot(
+            &cli.data,
+            source.keyframes.as_deref(),
+            time,
+            export.region.unwrap_or(CanvasBounds::FULL),
+            export.scale,
+            &output,
+        ),
+        Command::Timelapse {
+            range,
+            export,
+            source,
+            format,
+            fps,
+            output,
+        } => {
+            let options = TimelapseOptions {
+                range: range.frame_range()?,
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                scale: export.scale,
+                format,
+                fps,
+            };
+            let frames = timelapse::write_timelapse(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                &output,
+            )?;
+            eprintln!("Wrote {frames} frames");
+            Ok(())
+        }
+        Command::Animate {
+            range,
+            export,
+            source,
+            format,
+            frame_delay_ms,
+            output,
+        } => {
+            let options = AnimationOptions {
+                range: range.frame_range()?,
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                scale: export.scale,
+                format,
+                frame_delay_ms,
+            };
+            let frames = animate::write_animation(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                &output,
+            )?;
+            println!("Wrote {frames} distinct frames");
+            Ok(())
+        }
+        Command::Heatmap {
+            window,
+            export,
+            source,
+            ramp,
+            log,
+            output,
+            raw,
+        } => {
+            let options = HeatmapOptions {
+                window: window.time_window(),
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                ramp,
+                log_scale: log,
+                scale: export.scale,
+                user: None,
+            };
+            heatmap::write_heatmap(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                &output,
+                raw.as_deref(),
+            )
+        }
+        Command::Age {
+            time,
+            export,
+            ramp,
+            log,
+            max_age_minutes,
+            output,
+            raw,
+        } => {
+            let options = AgeMapOptions {
+                time,
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                ramp,
+                log_scale: log,
+                max_age_ms: max_age_minutes.map(minutes_to_ms).transpose()?,
+                scale: export.scale,
+            };
+            age::write_age_map(&cli.data, &options, &output, raw.as_deref())
+        }
+        Command::History {
+            x,
+            y,
+            format,
+            output,
+        } => history::write_history(&cli.data, (x, y), format, output.as_deref()),
+        Command::HistoryIndex { memory_mb } => {
+            let output = HistoryIndex::path_for(&cli.data);
+            let records =
+                history_index::write_history_index(&cli.data, &output, memory_mb * 1024 * 1024)?;
+            println!("Wrote {records} records to {output}");
+            Ok(())
+        }
+        Command::Tiles {
+            tile_size,
+            bucket_minutes,
+        } => {
+            let output = Tiles::path_for(&cli.data);
+            let index = tiles::write_tiles(
+                GzippedBinPixelDataReader::new(&cli.data)?,
+                SourceFingerprint::of(&cli.data)?,
+                &output,
+                tile_size,
+                minutes_to_ms(bucket_minutes)?,
+            )?;
+            println!("Wrote {} tiles to {}", index.tiles.len(), output);
+            Ok(())
+        }
+        Command::Colors {
+            window,
+            bucket_minutes,
+            region,
+            format,
+            output,
+        } => colors::write_color_usage(
+            &cli.data,
+            window.time_window(),
+            minutes_to_ms(bucket_minutes)?,
+            region.unwrap_or(CanvasBounds::FULL),
+            format,
+            &output,
+        ),
+        Command::Transitions {
+            window,
+            source,
+            regions,
+            format,
+            output,
+            image,
+            ramp,
+            log,
+            scale,
+        } => {
+            let options = TransitionOptions {
+                window: window.time_window(),
+                regions: if regions.is_empty() {
+                    vec![CanvasBounds::FULL]
+                } else {
+                    regions
+                },
+                ramp,
+                log_scale: log,
+                scale,
+            };
+            transitions::write_transitions(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                format,
+                &output,
+                image.as_deref(),
+            )
+        }
+        Command::Activity {
+            window,
+            resolution_seconds,
+            baseline_minutes,
+            spike_factor,
+            lull_factor,
+            format,
+            output,
+            events,
+            save_events,
+        } => {
+            let options = ActivityOptions {
+                window: window.time_window(),
+                resolution_ms: seconds_to_ms(resolution_seconds)?,
+                detection: DetectionOptions {
+                    baseline_radius: (baseline_minutes as u64 * 60
+                        / resolution_seconds.max(1) as u64)
+                        as usize,
+                    spike_factor,
+                    lull_factor,
+                },
+            };
+            activity::write_activity(
+                &cli.data,
+                &options,
+                format,
+                &output,
+                events.as_deref(),
+                save_events,
+            )
+        }
+        Command::Battles {
+            window,
+            source,
+            cell_size,
+            bin_minutes,
+            min_overwrites,
+            top,
+            format,
+            output,
+            frames,
+            frame_interval_seconds,
+            scale,
+        } => {
+            let options = BattleOptions {
+                window: window.time_window(),
+                cell_size,
+                bin_ms: minutes_to_ms(bin_minutes)?,
+                min_overwrites,
+                top,
+            };
+            let interval_ms = seconds_to_ms(frame_interval_seconds)?;
+            let frames = frames.map(|dir| BattleFrames {
+                dir,
+                interval_ms,
+                scale,
+            });
+            battles::write_battles(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                format,
+                &output,
+                frames.as_ref(),
+            )
+        }
+        Command::Stability {
+            window,
+            export,
+            ramp,
+            log,
+            output,
+            changes,
+            raw_held,
+            raw_changes,
+        } => {
+            let options = StabilityOptions {
+                window: window.time_window(),
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                ramp,
+                log_scale: log,
+                scale: export.scale,
+            };
+            stability::write_stability_map(
+                &cli.data,
+                &options,
+                &output,
+                changes.as_deref(),
+                raw_held.as_deref(),
+                raw_changes.as_deref(),
+            )
+        }
+        Command::Template {
+            template,
+            anchor,
+            window,
+            source,
+            interval_seconds,
+            attack_percent,
+            format,
+            output,
+            report,
+        } => {
+            let template = Template::load(&template, anchor)?;
+            let options = TemplateOptions {
+                window: window.time_window(),
+                interval_ms: seconds_to_ms(interval_seconds)?,
+                attack_percent,
+            };
+            template::write_template_progress(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &template,
+                &options,
+                format,
+                &output,
+                report.as_deref(),
+            )
+        }
+        Command::Search {
+            image,
+            threshold,
+            region,
+            window,
+            source,
+            interval_minutes,
+            max_candidates,
+            output,
+        } => {
+            let template = Template::load(&image, Anchor::TOP_LEFT)?;
+            let options = SearchOptions {
+                window: window.time_window(),
+                area: region.unwrap_or(CanvasBounds::FULL),
+                interval_ms: minutes_to_ms(interval_minutes)?,
+                threshold,
+                max_candidates,
+            };
+            search::write_search(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &template,
+                &options,
+                output.as_deref(),
+            )
+        }
+        Command::Diff {
+            from,
+            to,
+            regions,
+            source,
+            format,
+            output,
+            image,
+            scale,
+        } => {
+            let options = DiffOptions {
+                from,
+                to,
+                regions: if regions.is_empty() {
+                    vec![CanvasBounds::FULL]
+                } else {
+                    regions
+                },
+                scale,
+            };
+            diff::write_diff(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                format,
+                &output,
+                image.as_deref(),
+            )
+        }
+        Command::ArtworkStats {
+            atlas,
+            window,
+            source,
+            settle_percent,
+            format,
+            output,
+        } => {
+            let options = ArtworkStatsOptions {
+                window: window.time_window(),
+                settle_share: settle_percent / 100.0,
+            };
+            artworks::write_artwork_stats(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &Atlas::load(&atlas)?,
+                &options,
+                format,
+                &output,
+            )
+        }
+        Command::ArtworkTimelapse {
+            atlas,
+            artwork,
+            range,
+            source,
+            scale,
+            format,
+            fps,
+            output,
+        } => {
+            let atlas = Atlas::load(&atlas)?;
+            let Some(artwork) = atlas.find(&artwork) else {
+                whatever!("No artwork {artwork:?} in the atlas");
+            };
+            let options = TimelapseOptions {
+                range: range.frame_range()?,
+                region: artwork.bounds,
+                scale,
+                format,
+                fps,
+            };
+            let frames = timelapse::write_timelapse(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                &output,
+            )?;
+            eprintln!("Wrote {frames} frames of {}", artwork.name);
+            Ok(())
+        }
+        Command::DetectArtworks {
+            window,
+            export,
+            interval_minutes,
+            min_stable_minutes,
+            burst_minutes,
+            min_pixels,
+            output,
+            overlay,
+        } => {
+            let options = SegmentationOptions {
+                window: window.time_window(),
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                interval_ms: minutes_to_ms(interval_minutes)?,
+                min_stable_ms: minutes_to_ms(min_stable_minutes)?,
+                burst_ms: minutes_to_ms(burst_minutes)?,
+                min_pixels,
+                scale: export.scale,
+            };
+            segmentation::write_detected_artworks(&cli.data, &options, &output, overlay.as_deref())
+        }
+        Command::Users {
+            window,
+            top_colors,
+            top,
+            format,
+            output,
+            distribution,
+            user,
+            heatmap,
+            export,
+            source,
+            ramp,
+            log,
+        } => {
+            let options = UserStatsOptions {
+                window: window.time_window(),
+                top_colors,
+                top_users: top,
+            };
+            users::write_user_stats(
+                &cli.data,
+                &options,
+                format,
+                &output,
+                distribution.as_deref(),
+            )?;
+            if let (Some(user), Some(heatmap)) = (user, heatmap) {
+                let options = HeatmapOptions {
+                    window: options.window,
+                    region: export.region.unwrap_or(CanvasBounds::FULL),
+                    ramp,
+                    log_scale: log,
+                    scale: export.scale,
+                    user: Some(users::find_user(&cli.data, &user)?),
+                };
+                heatmap::write_heatmap(
+                    &cli.data,
+                    source.keyframes.as_deref(),
+                    &options,
+                    &heatmap,
+                    None,
+                )?;
+            }
+            Ok(())
+        }
+        Command::Cooldown {
+            window,
+            cooldown_seconds,
+            bin_ms,
+            min_placements,
+            max_variation,
+            max_entropy,
+            top,
+            format,
+            output,
+        } => {
+            let options = CooldownOptions {
+                window: window.time_window(),
+                cooldown_ms: seconds_to_ms(cooldown_seconds)?,
+                bin_ms,
+                min_placements,
+                max_variation,
+                max_entropy,
+                top_users: top,
+            };
+            cooldown::write_cooldown_report(&cli.data, &options, format, &output)
+        }
+        Command::Factions {
+            window,
+            source,
+            tile_size,
+            bin_minutes,
+            factions,
+            min_placements,
+            max_iterations,
+            format,
+            output,
+            footprints,
+            export,
+            ramp,
+            log,
+        } => {
+            let options = FactionOptions {
+                window: window.time_window(),
+                tile_size,
+                bin_ms: minutes_to_ms(bin_minutes)?,
+                factions,
+                min_placements,
+                max_iterations,
+                region: export.region.unwrap_or(CanvasBounds::FULL),
+                scale: export.scale,
+                ramp,
+                log_scale: log,
+            };
+            factions::write_factions(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                format,
+                &output,
+                &footprints,
+            )
+        }
+        Command::Griefing {
+            window,
+            source,
+            region,
+            min_stable_minutes,
+            revert_seconds,
+            cell_size,
+            gap_seconds,
+            min_pixels,
+            format,
+            output,
+        } => {
+            let options = GriefingOptions {
+                window: window.time_window(),
+                region: region.unwrap_or(CanvasBounds::FULL),
+                min_stable_ms: minutes_to_ms(min_stable_minutes)?,
+                revert_window_ms: seconds_to_ms(revert_seconds)?,
+                cell_size,
+                gap_ms: seconds_to_ms(gap_seconds)?,
+                min_pixels,
+            };
+            griefing::write_griefing(
+                &cli.data,
+                source.keyframes.as_deref(),
+                &options,
+                format,
+                &output,
+            )
+        }
+        Command::Moderation {
+            window,
+            source,
+            region,
+            format,
+            output,
+            crops,
+            margin,
+            scale,
+        } => {
+            let crops = crops.map(|dir| ModerationCrops { dir, margin, scale });
+            moderation::write_moderation(
+                &cli.data,
+                source.keyframes.as_deref(),
+                window.time_window(),
+                region.unwrap_or(CanvasBounds::FULL),
+                format,
+                &output,
+                crops.as_ref(),
+            )
+        }
+    }
 }
@@ -0,0 +1,41 @@
+use std::{fs::File, path::Path};
+
+use serde::{Deserialize, Serialize};
+use snafu::{prelude::*, Whatever};
+
+use crate::{analysis::activity::ActivityEvent, bounds::BoundsTimeline};
+
+/// Metadata about a `pixels.bin` dataset, stored next to it as `<dataset>.meta.json`.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct DatasetMetadata {
+    pub canvas_bounds: BoundsTimeline,
+    /// Spans of unusual activity, marked on the player's timeline and named in its window title
+    /// while they play.
+    #[serde(default)]
+    pub activity_events: Vec<ActivityEvent>,
+    /// End of the activity series the events were detected in, where the player's timeline ends.
+    #[serde(default)]
+    pub activity_end_ms: Option<u32>,
+}
+
+impl DatasetMetadata {
+    pub fn path_for(data_path: &str) -> String {
+        format!("{data_path}.meta.json")
+    }
+
+    /// Loads the metadata of the dataset, or the default metadata if there is none.
+    pub fn load_or_default(data_path: &str) -> Result<Self, Whatever> {
+        let path = Self::path_for(data_path);
+        if !Path::new(&path).exists() {
+            return Ok(Self::default());
+        }
+        let file = File::open(&path).whatever_context("Failed to open metadata")?;
+        serde_json::from_reader(file).whatever_context("Failed to parse metadata")
+    }
+
+    pub fn save(&self, data_path: &str) -> Result<(), Whatever> {
+        let file = File::create(Self::path_for(data_path))
+            .whatever_context("Failed to create metadata")?;
+        serde_json::to_writer_pretty(file, self).whatever_context("Failed to write metadata")
+    }
+}
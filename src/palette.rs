@@ -0,0 +1,136 @@
+use crate::{
+    canvas::{for_each_covered_pixel, CanvasState, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::{PixelColor, PixelData},
+};
+
+/// The 32 colors users could place during r/place 2023.
+pub const PALETTE: [[u8; 3]; 32] = [
+    [0x6d, 0x00, 0x1a],
+    [0xbe, 0x00, 0x39],
+    [0xff, 0x45, 0x00],
+    [0xff, 0xa8, 0x00],
+    [0xff, 0xd6, 0x35],
+    [0xff, 0xf8, 0xb8],
+    [0x00, 0xa3, 0x68],
+    [0x00, 0xcc, 0x78],
+    [0x7e, 0xed, 0x56],
+    [0x00, 0x75, 0x6f],
+    [0x00, 0x9e, 0xaa],
+    [0x00, 0xcc, 0xc0],
+    [0x24, 0x50, 0xa4],
+    [0x36, 0x90, 0xea],
+    [0x51, 0xe9, 0xf4],
+    [0x49, 0x3a, 0xc1],
+    [0x6a, 0x5c, 0xff],
+    [0x94, 0xb3, 0xff],
+    [0x81, 0x1e, 0x9f],
+    [0xb4, 0x4a, 0xc0],
+    [0xe4, 0xab, 0xff],
+    [0xde, 0x10, 0x7f],
+    [0xff, 0x38, 0x81],
+    [0xff, 0x99, 0xaa],
+    [0x6d, 0x48, 0x2f],
+    [0x9c, 0x69, 0x26],
+    [0xff, 0xb4, 0x70],
+    [0x00, 0x00, 0x00],
+    [0x51, 0x52, 0x52],
+    [0x89, 0x8d, 0x90],
+    [0xd4, 0xd7, 0xd9],
+    [0xff, 0xff, 0xff],
+];
+
+/// Index of white, the color of the canvas before anything was placed.
+pub const WHITE: u8 = 31;
+
+/// Returns the palette index of the color, or the closest palette color if it's not in the
+/// palette.
+pub fn palette_index(color: [u8; 3]) -> u8 {
+    if let Some(index) = PALETTE.iter().position(|&c| c == color) {
+        return index as u8;
+    }
+    let distance = |c: &[u8; 3]| -> i32 {
+        c.iter()
+            .zip(color)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum()
+    };
+    PALETTE
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, c)| distance(c))
+        .map(|(index, _)| index as u8)
+        .unwrap()
+}
+
+impl PixelColor {
+    pub fn palette_index(&self) -> u8 {
+        palette_index([self.r, self.g, self.b])
+    }
+}
+
+/// The canvas replayed as palette indices, which is cheaper than [`crate::canvas::CanvasState`]
+/// when only the palette colors matter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteCanvas {
+    indices: Vec<u8>,
+}
+
+impl Default for PaletteCanvas {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PaletteCanvas {
+    /// Creates a white canvas.
+    pub fn new() -> Self {
+        Self {
+            indices: vec![WHITE; CANVAS_WIDTH as usize * CANVAS_HEIGHT as usize],
+        }
+    }
+
+    /// Converts a replayed canvas, such as a keyframe, to palette indices.
+    pub fn from_canvas(canvas: &CanvasState) -> Self {
+        Self {
+            indices: canvas
+                .as_bytes()
+                .chunks_exact(3)
+                .map(|rgb| palette_index([rgb[0], rgb[1], rgb[2]]))
+                .collect(),
+        }
+    }
+
+    pub fn get(&self, (x, y): (u32, u32)) -> u8 {
+        self.indices[y as usize * CANVAS_WIDTH as usize + x as usize]
+    }
+
+    /// Applies the update and calls `f` with the point, the previous and the new palette index of
+    /// every covered pixel, including the ones that already had the color.
+    pub fn apply_with(&mut self, pixel_data: &PixelData, mut f: impl FnMut((u32, u32), u8, u8)) {
+        let new = pixel_data.pixel_color.palette_index();
+        for_each_covered_pixel(&pixel_data.coordinate, |(x, y)| {
+            let index = y as usize * CANVAS_WIDTH as usize + x as usize;
+            f((x, y), self.indices[index], new);
+            self.indices[index] = new;
+        });
+    }
+}
+
+/// Returns the palette color as a hex string like `#FF4500`.
+pub fn hex(index: u8) -> String {
+    let [r, g, b] = PALETTE[index as usize];
+    format!("#{r:02X}{g:02X}{b:02X}")
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_palette_index() {
+        use super::{hex, palette_index, WHITE};
+
+        assert_eq!(palette_index([255, 255, 255]), WHITE);
+        assert_eq!(palette_index([0xff, 0x45, 0x00]), 2);
+        assert_eq!(palette_index([250, 250, 250]), WHITE);
+        assert_eq!(hex(2), "#FF4500");
+    }
+}
@@ -1,26 +1,66 @@
-use std::{fs::File, io::Write};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+};
 
 use chrono::{DateTime, Utc};
 use csv::ReaderBuilder;
-use flate2::read::GzDecoder;
+use flate2::{read::GzDecoder, Compression};
 use rayon::prelude::*;
 use serde::Deserialize;
 use snafu::{prelude::*, Whatever};
 
-use crate::data::PixelData;
+use crate::{
+    data::{PixelData, FIRST_PIXEL_TIME},
+    user_table::{UserIds, UserTable},
+};
+
+/// Start of every stream of pixel updates, such as `pixels.bin` and keyframe deltas, followed by
+/// the format version as a little-endian `u32`.
+const STREAM_MAGIC: &[u8; 4] = b"RPPX";
+/// Version of the bincode layout of [`PixelData`]. Version 1 had no header and no user ids.
+pub const STREAM_VERSION: u32 = 2;
+
+/// Writes the magic and version a stream of pixel updates starts with.
+pub fn write_stream_header(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(STREAM_MAGIC)?;
+    writer.write_all(&STREAM_VERSION.to_le_bytes())
+}
+
+/// Checks the magic and version at the start of a stream of pixel updates.
+fn read_stream_header(reader: &mut impl Read, path: &str) -> Result<(), Whatever> {
+    let mut header = [0; 8];
+    reader
+        .read_exact(&mut header)
+        .with_whatever_context(|_| format!("Failed to read the header of {path}"))?;
+    ensure_whatever!(
+        &header[..4] == STREAM_MAGIC,
+        "{path} was written by an older version without user ids, convert the dataset again with \
+         `convert` and rebuild its keyframes, tiles and history index"
+    );
+    let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+    ensure_whatever!(
+        version == STREAM_VERSION,
+        "{path} has pixel update format version {version} instead of {STREAM_VERSION}, convert \
+         the dataset again with `convert`"
+    );
+    Ok(())
+}
 
 #[derive(Debug, Deserialize)]
 pub struct CsvRecord {
     pub timestamp: String,
-    pub user: String, // We'll ignore this in PixelData
+    pub user: String,
     pub coordinate: String,
     pub pixel_color: String,
 }
 
 impl CsvRecord {
+    /// Converts the record, with `user` as the id of its user hash.
     pub fn to_pixel_data(
         self,
         first_pixel_time: DateTime<Utc>,
+        user: u32,
     ) -> Result<crate::data::PixelData, Whatever> {
         let timestamp = DateTime::parse_from_rfc3339(&self.timestamp.replace(" UTC", "Z"))
             .whatever_context("Invalid timestamp")?;
@@ -40,6 +80,7 @@ impl CsvRecord {
             miliseconds_since_first_pixel,
             coordinate,
             pixel_color,
+            user,
         })
     }
 }
@@ -47,17 +88,20 @@ impl CsvRecord {
 struct GzippedCsvPixelDataReader {
     deserializer: csv::DeserializeRecordsIntoIter<GzDecoder<File>, CsvRecord>,
     first_pixel_time: DateTime<Utc>,
+    /// Ids of the users of this file, in the order they appear in it.
+    users: UserIds,
 }
 
 impl GzippedCsvPixelDataReader {
     fn new(first_pixel_time: DateTime<Utc>, path: &str) -> Result<Self, Whatever> {
-        let file = File::open(path).whatever_context("Failed to open file")?;
+        let file = File::open(path).with_whatever_context(|_| format!("Failed to open {path}"))?;
         let decoder = GzDecoder::new(file);
         let reader = ReaderBuilder::new().has_headers(true).from_reader(decoder);
         let deserializer = reader.into_deserialize();
         Ok(Self {
             deserializer,
             first_pixel_time,
+            users: UserIds::default(),
         })
     }
 }
@@ -67,19 +111,39 @@ impl Iterator for GzippedCsvPixelDataReader {
 
     fn next(&mut self) -> Option<Self::Item> {
         match self.deserializer.next() {
-            Some(Ok(record)) => Some(record.to_pixel_data(self.first_pixel_time)),
+            Some(Ok(record)) => {
+                let user = self.users.id_of(&record.user);
+                Some(record.to_pixel_data(self.first_pixel_time, user))
+            }
             Some(Err(e)) => Some(Err(e).whatever_context("Failed to parse record")),
             None => None,
         }
     }
 }
 
-pub fn parse_and_write_to_bin(parent_dir: &str) {
-    let first_pixel_time = DateTime::parse_from_rfc3339("2023-07-20 13:00:26.088Z")
-        .unwrap()
-        .with_timezone(&Utc);
-    let gz_writer = File::create("pixels.bin").unwrap();
-    let mut gz_writer = flate2::write::GzEncoder::new(gz_writer, flate2::Compression::default());
+/// Encodes the updates of a gzipped CSV file with ids numbered in the order the users appear in
+/// it, and returns them with the hashed user of every id.
+fn encode_csv_file(
+    first_pixel_time: DateTime<Utc>,
+    path: &str,
+) -> Result<(Vec<u8>, UserTable), Whatever> {
+    let mut reader = GzippedCsvPixelDataReader::new(first_pixel_time, path)?;
+
+    // Allocate a vector with a capacity of 32 MiB
+    let mut data = Vec::with_capacity(32 * 1024 * 1024);
+    for pixel_data in &mut reader {
+        let pixel_data =
+            pixel_data.with_whatever_context(|_| format!("Invalid record in {path}"))?;
+        bincode::encode_into_std_write(&pixel_data, &mut data, bincode::config::standard())
+            .whatever_context("Failed to encode record")?;
+    }
+    Ok((data, reader.users.into_table()))
+}
+
+/// Converts the 53 gzipped CSV files of the official dataset in `parent_dir` to a stream of
+/// pixel updates at `output`, and writes the user table next to it.
+pub fn parse_and_write_to_bin(parent_dir: &str, output: &str) -> Result<(), Whatever> {
+    let first_pixel_time = *FIRST_PIXEL_TIME;
     let bincode_config = bincode::config::standard();
 
     // for index in 0..=52 {
@@ -93,28 +157,51 @@ pub fn parse_and_write_to_bin(parent_dir: &str) {
     //     }
     // }
 
-    // Parrallel version
-    let data: Vec<u8> = (0..=52)
+    // Parrallel version. Every file numbers its users on its own, so the ids don't depend on
+    // which file is parsed first. Errors aren't `Send`, so they cross threads as messages.
+    let files: Result<Vec<(Vec<u8>, UserTable)>, String> = (0..=52)
         .into_par_iter()
         .map(|index| {
             let path = format!("{parent_dir}/2023_place_canvas_history-{index:012}.csv.gzip");
             println!("Reading {}", path);
-            let reader = GzippedCsvPixelDataReader::new(first_pixel_time, &path).unwrap();
-
-            // Allocate a vector with a capacity of 32 MiB
-            let mut data = Vec::with_capacity(32 * 1024 * 1024);
-            for pixel_data in reader {
-                let pixel_data = pixel_data.unwrap();
-
-                let current = bincode::encode_to_vec(&pixel_data, bincode_config).unwrap();
-                data.extend(current);
-            }
-            data
+            encode_csv_file(first_pixel_time, &path)
+                .map_err(|e| snafu::Report::from_error(e).to_string())
         })
-        .flatten()
         .collect();
+    let files = match files {
+        Ok(files) => files,
+        Err(message) => whatever!("{message}"),
+    };
 
-    gz_writer.write_all(&data).unwrap();
+    let file =
+        File::create(output).with_whatever_context(|_| format!("Failed to create {output}"))?;
+    let mut gz_writer = flate2::write::GzEncoder::new(BufWriter::new(file), Compression::default());
+    write_stream_header(&mut gz_writer).whatever_context("Failed to write stream header")?;
+    // The files are merged in time order, so the final ids are in the order of the first update
+    // of every user.
+    let mut users = UserIds::default();
+    for (data, file_users) in files {
+        let ids: Vec<u32> = file_users
+            .hashes()
+            .iter()
+            .map(|hash| users.id_of(hash))
+            .collect();
+        let mut offset = 0;
+        while offset < data.len() {
+            let (mut pixel_data, len): (PixelData, usize) =
+                bincode::decode_from_slice(&data[offset..], bincode_config)
+                    .whatever_context("Failed to decode record")?;
+            offset += len;
+            pixel_data.user = ids[pixel_data.user as usize];
+            bincode::encode_into_std_write(&pixel_data, &mut gz_writer, bincode_config)
+                .with_whatever_context(|_| format!("Failed to write {output}"))?;
+        }
+    }
+    gz_writer
+        .finish()
+        .and_then(|mut writer| writer.flush())
+        .with_whatever_context(|_| format!("Failed to write {output}"))?;
+    users.into_table().save(&UserTable::path_for(output))
 }
 
 pub struct GzippedBinPixelDataReader {
@@ -125,7 +212,8 @@ impl GzippedBinPixelDataReader {
     pub fn new(path: &str) -> Result<Self, Whatever> {
         let file = File::open(path).whatever_context("Failed to open file")?;
         let reader = flate2::read::GzDecoder::new(file);
-        let reader = std::io::BufReader::new(reader);
+        let mut reader = std::io::BufReader::new(reader);
+        read_stream_header(&mut reader, path)?;
         Ok(Self { reader })
     }
 }
@@ -150,3 +238,45 @@ impl Iterator for GzippedBinPixelDataReader {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_stream_header() {
+        use std::io::Write;
+
+        use super::{write_stream_header, GzippedBinPixelDataReader};
+        use crate::data::{Coordinate, PixelColor, PixelData};
+
+        let pixel_data = PixelData {
+            miliseconds_since_first_pixel: 5,
+            coordinate: Coordinate::Simple { x: 1, y: 2 },
+            pixel_color: PixelColor { r: 3, g: 4, b: 5 },
+            user: 6,
+        };
+        let write = |path: &str, header: bool| {
+            let file = std::fs::File::create(path).unwrap();
+            let mut writer = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+            if header {
+                write_stream_header(&mut writer).unwrap();
+            }
+            let bytes = bincode::encode_to_vec(&pixel_data, bincode::config::standard()).unwrap();
+            writer.write_all(&bytes).unwrap();
+            writer.finish().unwrap();
+        };
+
+        let path = std::env::temp_dir().join(format!("rplace-stream-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        write(path, true);
+        let read: Vec<_> = GzippedBinPixelDataReader::new(path)
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, [pixel_data.clone()]);
+
+        // Streams from before the header are rejected instead of decoded into garbage.
+        write(path, false);
+        assert!(GzippedBinPixelDataReader::new(path).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+}
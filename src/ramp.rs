@@ -0,0 +1,99 @@
+use crate::export::RgbImage;
+
+/// Color ramps for rendering values as images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorRamp {
+    Inferno,
+    Viridis,
+    /// Black, red, yellow, white.
+    Heat,
+    Grayscale,
+}
+
+impl ColorRamp {
+    /// Evenly spaced colors of the ramp, from low to high.
+    fn stops(self) -> &'static [[u8; 3]] {
+        match self {
+            ColorRamp::Inferno => &[
+                [0, 0, 4],
+                [31, 12, 72],
+                [85, 15, 109],
+                [136, 34, 106],
+                [186, 54, 85],
+                [227, 89, 51],
+                [249, 140, 10],
+                [249, 201, 50],
+                [252, 255, 164],
+            ],
+            ColorRamp::Viridis => &[
+                [68, 1, 84],
+                [71, 44, 122],
+                [59, 81, 139],
+                [44, 113, 142],
+                [33, 144, 141],
+                [39, 173, 129],
+                [92, 200, 99],
+                [170, 220, 50],
+                [253, 231, 37],
+            ],
+            ColorRamp::Heat => &[
+                [0, 0, 0],
+                [128, 0, 0],
+                [255, 0, 0],
+                [255, 128, 0],
+                [255, 255, 0],
+                [255, 255, 255],
+            ],
+            ColorRamp::Grayscale => &[[0, 0, 0], [255, 255, 255]],
+        }
+    }
+
+    /// Returns the color for `t` in `0.0..=1.0`. Values outside are clamped.
+    pub fn sample(self, t: f32) -> [u8; 3] {
+        let stops = self.stops();
+        let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f32;
+        let index = (position as usize).min(stops.len() - 2);
+        let fraction = position - index as f32;
+        let (from, to) = (stops[index], stops[index + 1]);
+        [0, 1, 2].map(|channel| {
+            (from[channel] as f32 + (to[channel] as f32 - from[channel] as f32) * fraction).round()
+                as u8
+        })
+    }
+
+    /// Renders row-major values in `0.0..=1.0` as an image.
+    pub fn render(self, width: u32, height: u32, values: &[f32]) -> RgbImage {
+        RgbImage {
+            width,
+            height,
+            pixels: values.iter().flat_map(|&t| self.sample(t)).collect(),
+        }
+    }
+}
+
+/// Maps a value in `0..=max` to `0.0..=1.0`, optionally on a logarithmic scale, which keeps the
+/// few extremely active pixels from washing out everything else.
+pub fn normalize(value: f64, max: f64, log_scale: bool) -> f32 {
+    if max <= 0.0 {
+        return 0.0;
+    }
+    if log_scale {
+        (value.ln_1p() / max.ln_1p()) as f32
+    } else {
+        (value / max) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_sample() {
+        use super::ColorRamp;
+
+        assert_eq!(ColorRamp::Grayscale.sample(0.0), [0, 0, 0]);
+        assert_eq!(ColorRamp::Grayscale.sample(0.5), [128, 128, 128]);
+        assert_eq!(ColorRamp::Grayscale.sample(2.0), [255, 255, 255]);
+        assert_eq!(ColorRamp::Heat.sample(0.2), [128, 0, 0]);
+        assert_eq!(ColorRamp::Inferno.sample(1.0), [252, 255, 164]);
+    }
+}
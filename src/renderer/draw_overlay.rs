@@ -0,0 +1,403 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{Buffer, BufferCreateInfo, BufferUsage},
+    command_buffer::{
+        allocator::StandardCommandBufferAllocator, CommandBufferBeginInfo, CommandBufferLevel,
+        CommandBufferUsage, RecordingCommandBuffer, RenderingAttachmentInfo, RenderingInfo,
+    },
+    descriptor_set::{DescriptorSet, WriteDescriptorSet},
+    device::Queue,
+    image::view::ImageView,
+    memory::allocator::{AllocationCreateInfo, MemoryTypeFilter},
+    pipeline::{
+        graphics::{
+            color_blend::{AttachmentBlend, ColorBlendAttachmentState, ColorBlendState},
+            input_assembly::InputAssemblyState,
+            multisample::MultisampleState,
+            rasterization::RasterizationState,
+            subpass::PipelineRenderingCreateInfo,
+            vertex_input::VertexInputState,
+            viewport::{Viewport, ViewportState},
+            GraphicsPipelineCreateInfo,
+        },
+        layout::PipelineDescriptorSetLayoutCreateInfo,
+        DynamicState, GraphicsPipeline, Pipeline, PipelineLayout, PipelineShaderStageCreateInfo,
+    },
+    render_pass::{AttachmentLoadOp, AttachmentStoreOp},
+    sync::GpuFuture,
+};
+
+use super::{
+    font::{self, GLYPH_SIZE},
+    App,
+};
+use crate::analysis::activity::ActivityEvent;
+
+/// Draws the timeline with the activity events at the bottom of the window and a label next to
+/// the cursor, over the canvas.
+pub struct DrawOverlayPipeline {
+    gfx_queue: Arc<Queue>,
+    gfx_pipeline: Arc<GraphicsPipeline>,
+    command_buffer_allocator: Arc<StandardCommandBufferAllocator>,
+
+    descriptor_set: Arc<DescriptorSet>,
+
+    /// End of the timeline, `None` to draw no timeline.
+    end_ms: Option<u32>,
+    marker_count: u32,
+    now_ms: u32,
+    /// Cursor position in window pixels.
+    cursor: [f32; 2],
+    label_length: u32,
+    label: [[u32; 4]; 3],
+    label_scale: f32,
+}
+
+impl DrawOverlayPipeline {
+    /// Most characters of a label, longer ones are cut off with an ellipsis.
+    const MAX_LABEL_LENGTH: usize = 48;
+
+    /// Returns the events as `[start, end, kind, 0]`, with start and end as fractions of a
+    /// timeline from the first update to `end_ms`.
+    fn timeline_markers(events: &[ActivityEvent], end_ms: u32) -> Vec<[f32; 4]> {
+        let fraction = |ms: u32| (ms as f64 / end_ms.max(1) as f64).min(1.0) as f32;
+        events
+            .iter()
+            .map(|event| {
+                [
+                    fraction(event.start_ms),
+                    fraction(event.end_ms),
+                    event.kind as u32 as f32,
+                    0.0,
+                ]
+            })
+            .collect()
+    }
+
+    /// Returns the number of characters of the label and their glyph indices, four per word from
+    /// the lowest byte.
+    fn pack_label(text: &str) -> (u32, [[u32; 4]; 3]) {
+        let mut glyphs: Vec<u8> = text.chars().map(font::glyph_index).collect();
+        if glyphs.len() > Self::MAX_LABEL_LENGTH {
+            glyphs.truncate(Self::MAX_LABEL_LENGTH - 3);
+            glyphs.extend([font::glyph_index('.'); 3]);
+        }
+        let mut label = [[0; 4]; 3];
+        for (i, &glyph) in glyphs.iter().enumerate() {
+            label[i / 16][i / 4 % 4] |= (glyph as u32) << (8 * (i % 4));
+        }
+        (glyphs.len() as u32, label)
+    }
+
+    /// Returns the top left of a label of `size` placed `offset` below and right of the cursor, or
+    /// above and left of it where it would leave the window. Everything is in window pixels.
+    fn label_origin(
+        cursor: [f32; 2],
+        size: [f32; 2],
+        offset: f32,
+        window_size: [f32; 2],
+    ) -> [f32; 2] {
+        let mut origin = [0.0; 2];
+        for axis in 0..2 {
+            origin[axis] = if cursor[axis] + offset + size[axis] <= window_size[axis] {
+                cursor[axis] + offset
+            } else {
+                (cursor[axis] - offset - size[axis]).max(0.0)
+            };
+        }
+        origin
+    }
+
+    pub fn new(
+        app: &App,
+        gfx_queue: Arc<Queue>,
+        events: &[ActivityEvent],
+        end_ms: Option<u32>,
+        rendering_info: PipelineRenderingCreateInfo,
+    ) -> Self {
+        let context = &app.context;
+        let memory_allocator = context.memory_allocator().clone();
+
+        let mut markers = Self::timeline_markers(events, end_ms.unwrap_or(0));
+        let marker_count = markers.len() as u32;
+        // Buffers can't be empty.
+        if markers.is_empty() {
+            markers.push([0.0; 4]);
+        }
+        let marker_buffer = Buffer::from_iter(
+            memory_allocator.clone(),
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            markers,
+        )
+        .unwrap();
+        let glyph_buffer = Buffer::from_iter(
+            memory_allocator,
+            BufferCreateInfo {
+                usage: BufferUsage::STORAGE_BUFFER,
+                ..Default::default()
+            },
+            AllocationCreateInfo {
+                memory_type_filter: MemoryTypeFilter::PREFER_DEVICE
+                    | MemoryTypeFilter::HOST_SEQUENTIAL_WRITE,
+                ..Default::default()
+            },
+            font::glyph_words(),
+        )
+        .unwrap();
+
+        let gfx_pipeline = {
+            let device = gfx_queue.device();
+            let vs = vs::load(device.clone())
+                .expect("failed to create shader module")
+                .entry_point("main")
+                .expect("shader entry point not found");
+            let fs = fs::load(device.clone())
+                .expect("failed to create shader module")
+                .entry_point("main")
+                .expect("shader entry point not found");
+            let stages = [
+                PipelineShaderStageCreateInfo::new(vs),
+                PipelineShaderStageCreateInfo::new(fs),
+            ];
+            let layout = PipelineLayout::new(
+                device.clone(),
+                PipelineDescriptorSetLayoutCreateInfo::from_stages(&stages)
+                    .into_pipeline_layout_create_info(device.clone())
+                    .unwrap(),
+            )
+            .unwrap();
+
+            GraphicsPipeline::new(
+                device.clone(),
+                None,
+                GraphicsPipelineCreateInfo {
+                    stages: stages.into_iter().collect(),
+                    vertex_input_state: Some(VertexInputState::default()),
+                    input_assembly_state: Some(InputAssemblyState::default()),
+                    viewport_state: Some(ViewportState::default()),
+                    rasterization_state: Some(RasterizationState::default()),
+                    multisample_state: Some(MultisampleState::default()),
+                    color_blend_state: Some(ColorBlendState::with_attachment_states(
+                        rendering_info.color_attachment_formats.len() as u32,
+                        ColorBlendAttachmentState {
+                            blend: Some(AttachmentBlend::alpha()),
+                            ..Default::default()
+                        },
+                    )),
+                    dynamic_state: [DynamicState::Viewport].into_iter().collect(),
+                    subpass: Some(rendering_info.into()),
+                    ..GraphicsPipelineCreateInfo::layout(layout)
+                },
+            )
+            .unwrap()
+        };
+
+        let descriptor_set = DescriptorSet::new(
+            app.descriptor_set_allocator.clone(),
+            gfx_pipeline.layout().set_layouts()[0].clone(),
+            [
+                WriteDescriptorSet::buffer(0, marker_buffer),
+                WriteDescriptorSet::buffer(1, glyph_buffer),
+            ],
+            [],
+        )
+        .unwrap();
+
+        Self {
+            gfx_queue,
+            gfx_pipeline,
+            command_buffer_allocator: app.command_buffer_allocator.clone(),
+            descriptor_set,
+
+            end_ms,
+            marker_count,
+            now_ms: 0,
+            cursor: [0.0; 2],
+            label_length: 0,
+            label: [[0; 4]; 3],
+            label_scale: 1.0,
+        }
+    }
+
+    /// Sets the current playback time shown by the playhead.
+    pub fn set_time(&mut self, now_ms: u32) {
+        self.now_ms = now_ms;
+    }
+
+    /// Shows a label next to the cursor at a position in window pixels, like the name of the
+    /// artwork under it, or no label for `None`. `scale` is the size of a glyph pixel in window
+    /// pixels.
+    pub fn set_label(&mut self, label: Option<(&str, [f32; 2])>, scale: f32) {
+        let (text, cursor) = label.unwrap_or(("", [0.0; 2]));
+        (self.label_length, self.label) = Self::pack_label(text);
+        self.cursor = cursor;
+        self.label_scale = scale.max(1.0);
+    }
+
+    pub fn draw(
+        &self,
+        before: Box<dyn GpuFuture>,
+        dst_image: Arc<ImageView>,
+    ) -> Box<dyn GpuFuture> {
+        let mut builder = RecordingCommandBuffer::new(
+            self.command_buffer_allocator.clone(),
+            self.gfx_queue.queue_family_index(),
+            CommandBufferLevel::Primary,
+            CommandBufferBeginInfo {
+                usage: CommandBufferUsage::OneTimeSubmit,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let extent = dst_image.image().extent();
+        let window_size = [extent[0] as f32, extent[1] as f32];
+        let progress = match self.end_ms {
+            Some(end_ms) => (self.now_ms as f64 / end_ms.max(1) as f64).min(1.0) as f32,
+            None => -1.0,
+        };
+        // The shader pads the text with a glyph pixel on every side.
+        let label_size = [
+            (self.label_length * GLYPH_SIZE + 2) as f32 * self.label_scale,
+            (GLYPH_SIZE + 2) as f32 * self.label_scale,
+        ];
+        let label_origin = Self::label_origin(
+            self.cursor,
+            label_size,
+            GLYPH_SIZE as f32 * self.label_scale,
+            window_size,
+        );
+
+        builder
+            .begin_rendering(RenderingInfo {
+                color_attachments: vec![Some(RenderingAttachmentInfo {
+                    // Drawn over the canvas.
+                    load_op: AttachmentLoadOp::Load,
+                    store_op: AttachmentStoreOp::Store,
+                    ..RenderingAttachmentInfo::image_view(dst_image)
+                })],
+                ..Default::default()
+            })
+            .unwrap()
+            .set_viewport(
+                0,
+                [Viewport {
+                    extent: window_size,
+                    ..Default::default()
+                }]
+                .into_iter()
+                .collect(),
+            )
+            .unwrap()
+            .bind_pipeline_graphics(self.gfx_pipeline.clone())
+            .unwrap()
+            .bind_descriptor_sets(
+                self.gfx_pipeline.bind_point(),
+                self.gfx_pipeline.layout().clone(),
+                0,
+                self.descriptor_set.clone(),
+            )
+            .unwrap()
+            .push_constants(
+                self.gfx_pipeline.layout().clone(),
+                0,
+                fs::PushConstants {
+                    window_size,
+                    progress,
+                    marker_count: self.marker_count,
+                    label_origin,
+                    label_length: self.label_length,
+                    label_scale: self.label_scale,
+                    label: self.label,
+                },
+            )
+            .unwrap();
+
+        unsafe {
+            builder.draw(3, 1, 0, 0).unwrap();
+        }
+
+        builder.end_rendering().unwrap();
+
+        let command_buffer = builder.end().unwrap();
+
+        before
+            .then_execute(self.gfx_queue.clone(), command_buffer)
+            .unwrap()
+            .boxed()
+    }
+}
+
+mod vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        path: "src/renderer/shaders/draw_overlay.vert"
+    }
+}
+
+mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        path: "src/renderer/shaders/draw_overlay.frag"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_timeline_markers() {
+        use super::DrawOverlayPipeline;
+        use crate::analysis::activity::{ActivityEvent, ActivityEventKind};
+
+        let event = |kind, start_ms, end_ms| ActivityEvent {
+            kind,
+            start_ms,
+            end_ms,
+            peak_rate: 0.0,
+            baseline_rate: 0.0,
+        };
+        let events = [
+            event(ActivityEventKind::Spike, 0, 250),
+            event(ActivityEventKind::Whiteout, 900, 1100),
+        ];
+        let markers = DrawOverlayPipeline::timeline_markers(&events, 1000);
+        assert_eq!(markers, vec![[0.0, 0.25, 0.0, 0.0], [0.9, 1.0, 4.0, 0.0]]);
+    }
+
+    #[test]
+    fn test_pack_label() {
+        use super::DrawOverlayPipeline;
+
+        let (length, label) = DrawOverlayPipeline::pack_label("Hi é");
+        assert_eq!(length, 4);
+        // 'H', 'i', ' ' and '?' for the character without a glyph.
+        assert_eq!(label[0][0], u32::from_le_bytes([40, 73, 0, 31]));
+        assert_eq!(label[0][1], 0);
+
+        let (length, label) = DrawOverlayPipeline::pack_label(&"a".repeat(60));
+        assert_eq!(length, 48);
+        // The last word ends with the ellipsis.
+        assert_eq!(label[2][3], u32::from_le_bytes([65, 14, 14, 14]));
+    }
+
+    #[test]
+    fn test_label_origin() {
+        use super::DrawOverlayPipeline;
+
+        let window_size = [1280.0, 720.0];
+        let origin =
+            |cursor| DrawOverlayPipeline::label_origin(cursor, [200.0, 20.0], 16.0, window_size);
+        assert_eq!(origin([100.0, 100.0]), [116.0, 116.0]);
+        // Too close to the right and bottom border, so the label goes above and left.
+        assert_eq!(origin([1200.0, 700.0]), [984.0, 664.0]);
+    }
+}
@@ -42,6 +42,15 @@ pub struct DrawQuadPipeline {
     src_image: Arc<ImageView>,
 
     descriptor_set: Arc<DescriptorSet>,
+
+    window_aspect_ratio: f32,
+    // Part of the texture shown in the window: (u1, v1, u2, v2)
+    view: [f32; 4],
+    active_bounds: [f32; 4],
+    highlight_bounds: [f32; 4],
+    show_age: bool,
+    now_ms: u32,
+    max_age_ms: f32,
 }
 
 #[derive(BufferContents, Vertex, Clone, Copy)]
@@ -92,19 +101,61 @@ impl DrawQuadPipeline {
         ]
     }
 
+    /// Fits the `view` part of the texture into the window.
+    fn view_quad(
+        window_aspect_ratio: f32,
+        view: [f32; 4],
+        texture_extent: [u32; 3],
+    ) -> [TexturedVertex; 4] {
+        let (view_width, view_height) = (view[2] - view[0], view[3] - view[1]);
+        let view_aspect_ratio =
+            view_width * texture_extent[0] as f32 / (view_height * texture_extent[1] as f32);
+
+        let mut vertices = Self::fit_quad(window_aspect_ratio, view_aspect_ratio);
+        for vertex in &mut vertices {
+            vertex.uv = [
+                view[0] + vertex.uv[0] * view_width,
+                view[1] + vertex.uv[1] * view_height,
+            ];
+        }
+        vertices
+    }
+
+    /// Returns the texel of the quad under a position in window pixels, if there is one.
+    fn quad_texel(
+        vertices: &[TexturedVertex; 4],
+        texture_extent: [u32; 3],
+        position: [f64; 2],
+        window_size: [f64; 2],
+    ) -> Option<(u32, u32)> {
+        let (first, last) = (vertices[0], vertices[3]);
+        let mut texel = [0; 2];
+        for axis in 0..2 {
+            let ndc = (2.0 * position[axis] / window_size[axis] - 1.0) as f32;
+            let t = (ndc - first.position[axis]) / (last.position[axis] - first.position[axis]);
+            if !(0.0..1.0).contains(&t) {
+                return None;
+            }
+            let uv = first.uv[axis] + t * (last.uv[axis] - first.uv[axis]);
+            texel[axis] = ((uv * texture_extent[axis] as f32) as u32).min(texture_extent[axis] - 1);
+        }
+        Some((texel[0], texel[1]))
+    }
+
     pub fn new(
         app: &App,
         gfx_queue: Arc<Queue>,
         window_aspect_ratio: f32,
         src_image: Arc<ImageView>,
+        age_image: Arc<ImageView>,
+        max_age_ms: u32,
         rendering_info: PipelineRenderingCreateInfo,
     ) -> Self {
         let context = &app.context;
         let memory_allocator = context.memory_allocator().clone();
 
-        let src_image_extent = src_image.image().extent();
-        let src_image_aspect_ratio = src_image_extent[0] as f32 / src_image_extent[1] as f32;
-        let vertices = Self::fit_quad(window_aspect_ratio, src_image_aspect_ratio);
+        let view = [0.0, 0.0, 1.0, 1.0];
+        let vertices = Self::view_quad(window_aspect_ratio, view, src_image.image().extent());
 
         let vertex_buffer = {
             Buffer::from_iter(
@@ -188,11 +239,10 @@ impl DrawQuadPipeline {
             let descriptor_set = DescriptorSet::new(
                 app.descriptor_set_allocator.clone(),
                 desc_layout,
-                [WriteDescriptorSet::image_view_sampler(
-                    0,
-                    src_image.clone(),
-                    sampler,
-                )],
+                [
+                    WriteDescriptorSet::image_view_sampler(0, src_image.clone(), sampler.clone()),
+                    WriteDescriptorSet::image_view_sampler(1, age_image, sampler),
+                ],
                 [],
             )
             .unwrap();
@@ -206,13 +256,23 @@ impl DrawQuadPipeline {
             vertex_buffer,
             command_buffer_allocator: app.command_buffer_allocator.clone(),
             descriptor_set,
+
+            window_aspect_ratio,
+            view,
+            active_bounds: [0.0, 0.0, 1.0, 1.0],
+            highlight_bounds: [0.0; 4],
+            show_age: false,
+            now_ms: 0,
+            max_age_ms: max_age_ms.max(1) as f32,
         }
     }
 
-    pub fn update_window_aspect_ratio(&mut self, window_aspect_ratio: f32) {
-        let src_image_extent = self.src_image.image().extent();
-        let src_image_aspect_ratio = src_image_extent[0] as f32 / src_image_extent[1] as f32;
-        let vertices = Self::fit_quad(window_aspect_ratio, src_image_aspect_ratio);
+    fn update_vertices(&mut self) {
+        let vertices = Self::view_quad(
+            self.window_aspect_ratio,
+            self.view,
+            self.src_image.image().extent(),
+        );
 
         self.vertex_buffer
             .write()
@@ -220,6 +280,45 @@ impl DrawQuadPipeline {
             .copy_from_slice(&vertices);
     }
 
+    pub fn update_window_aspect_ratio(&mut self, window_aspect_ratio: f32) {
+        self.window_aspect_ratio = window_aspect_ratio;
+        self.update_vertices();
+    }
+
+    /// Returns the texel under a position in window pixels, if it is on the canvas.
+    pub fn texel_at(&self, position: [f64; 2], window_size: [f64; 2]) -> Option<(u32, u32)> {
+        let extent = self.src_image.image().extent();
+        let vertices = Self::view_quad(self.window_aspect_ratio, self.view, extent);
+        Self::quad_texel(&vertices, extent, position, window_size)
+    }
+
+    /// Sets the part of the texture shown in the window as `[u1, v1, u2, v2]`.
+    pub fn set_view(&mut self, view: [f32; 4]) {
+        self.view = view;
+        self.update_vertices();
+    }
+
+    /// Sets the opened area of the canvas as `[u1, v1, u2, v2]`.
+    /// Everything outside of it is drawn as out of bounds.
+    pub fn set_active_bounds(&mut self, active_bounds: [f32; 4]) {
+        self.active_bounds = active_bounds;
+    }
+
+    /// Outlines the part of the texture `[u1, v1, u2, v2]`, like the artwork under the cursor.
+    pub fn set_highlight_bounds(&mut self, highlight_bounds: Option<[f32; 4]>) {
+        self.highlight_bounds = highlight_bounds.unwrap_or([0.0; 4]);
+    }
+
+    /// Switches between the colors and the time since each pixel last changed.
+    pub fn toggle_age_mode(&mut self) {
+        self.show_age = !self.show_age;
+    }
+
+    /// Sets the current playback time the ages are measured from.
+    pub fn set_time(&mut self, now_ms: u32) {
+        self.now_ms = now_ms;
+    }
+
     pub fn draw(
         &self,
         before: Box<dyn GpuFuture>,
@@ -267,6 +366,18 @@ impl DrawQuadPipeline {
                 0,
                 self.descriptor_set.clone(),
             )
+            .unwrap()
+            .push_constants(
+                self.gfx_pipeline.layout().clone(),
+                0,
+                fs::PushConstants {
+                    active_bounds: self.active_bounds,
+                    highlight_bounds: self.highlight_bounds,
+                    mode: self.show_age as u32,
+                    now_ms: self.now_ms,
+                    max_age_ms: self.max_age_ms,
+                },
+            )
             .unwrap();
 
         unsafe {
@@ -307,4 +418,30 @@ mod tests {
         let vertices = super::DrawQuadPipeline::fit_quad(window_aspect_ratio, texture_aspect_ratio);
         assert_eq!(vertices[0].position, [-0.84375, 1.0]);
     }
+
+    #[test]
+    fn test_view_quad() {
+        let window_aspect_ratio = 1920.0 / 1080.0;
+        let view = [0.25, 0.5, 0.75, 1.0];
+        let vertices =
+            super::DrawQuadPipeline::view_quad(window_aspect_ratio, view, [3000, 2000, 1]);
+        assert_eq!(vertices[0].uv, [0.25, 0.5]);
+        assert_eq!(vertices[3].uv, [0.75, 1.0]);
+        assert_eq!(vertices[0].position, [-0.84375, 1.0]);
+    }
+
+    #[test]
+    fn test_quad_texel() {
+        use super::DrawQuadPipeline;
+
+        let extent = [3000, 2000, 1];
+        let window_size = [1920.0, 1080.0];
+        let vertices = DrawQuadPipeline::view_quad(1920.0 / 1080.0, [0.0, 0.0, 1.0, 1.0], extent);
+        let texel =
+            |position| DrawQuadPipeline::quad_texel(&vertices, extent, position, window_size);
+        assert_eq!(texel([960.0, 540.0]), Some((1500, 1000)));
+        assert_eq!(texel([150.0, 810.0]), Some((0, 500)));
+        // The window is wider than the canvas, so there are margins on the left and right.
+        assert_eq!(texel([100.0, 540.0]), None);
+    }
 }
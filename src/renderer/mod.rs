@@ -12,18 +12,46 @@ use vulkano_util::{
     window::{VulkanoWindows, WindowDescriptor},
 };
 use winit::{
+    dpi::PhysicalPosition,
     event::{ElementState, Event, KeyEvent, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     keyboard::{Key, NamedKey},
 };
 
-use crate::parse::GzippedBinPixelDataReader;
+use crate::{
+    analysis::activity::ActivityEvent,
+    atlas::Atlas,
+    bounds::{BoundsTimeline, CanvasBounds},
+    data::to_utc,
+    parse::GzippedBinPixelDataReader,
+};
 
-use self::{draw_quad::DrawQuadPipeline, update_texture::UpdateTexturePipeline};
+use self::{
+    draw_overlay::DrawOverlayPipeline, draw_quad::DrawQuadPipeline,
+    update_texture::UpdateTexturePipeline,
+};
 
+mod draw_overlay;
 mod draw_quad;
+mod font;
 pub mod update_texture;
 
+pub struct PlayerOptions {
+    pub playback_speed: u32,
+    pub canvas_bounds: BoundsTimeline,
+    /// Frame the view to the opened area of the canvas as it grows.
+    pub follow_bounds: bool,
+    /// Age at which pixels get the coldest color when showing the time since the last change.
+    pub max_age_ms: u32,
+    /// Spans of unusual activity, marked on the timeline and named in the window title while
+    /// they play.
+    pub events: Vec<ActivityEvent>,
+    /// End of the timeline drawn at the bottom of the window. No timeline is drawn without it.
+    pub timeline_end_ms: Option<u32>,
+    /// Artworks outlined and named next to the cursor when it is over them.
+    pub atlas: Option<Atlas>,
+}
+
 pub struct App {
     context: VulkanoContext,
     windows: VulkanoWindows,
@@ -67,7 +95,9 @@ impl App {
         }
     }
 
-    pub fn run(&mut self, mut data_reader: GzippedBinPixelDataReader, playback_speed: u32) {
+    pub fn run(&mut self, mut data_reader: GzippedBinPixelDataReader, options: PlayerOptions) {
+        let playback_speed = options.playback_speed;
+
         let event_loop = EventLoop::new().unwrap();
         event_loop.set_control_flow(ControlFlow::Poll);
 
@@ -93,6 +123,24 @@ impl App {
             queue.clone(),
             1280.0 / 720.0,
             update_texture_pipeline.canvas_image().clone(),
+            update_texture_pipeline.age_image().clone(),
+            options.max_age_ms,
+            PipelineRenderingCreateInfo {
+                color_attachment_formats: vec![Some(
+                    self.windows
+                        .get_renderer(window_id)
+                        .unwrap()
+                        .swapchain_format(),
+                )],
+                ..Default::default()
+            },
+        );
+
+        let mut draw_overlay_pipeline = DrawOverlayPipeline::new(
+            self,
+            queue.clone(),
+            &options.events,
+            options.timeline_end_ms,
             PipelineRenderingCreateInfo {
                 color_attachment_formats: vec![Some(
                     self.windows
@@ -107,11 +155,62 @@ impl App {
         let render_start = Instant::now();
 
         let mut buffer = Vec::new();
+        let mut active_bounds: Option<CanvasBounds> = None;
+        let mut title = String::new();
+        let mut cursor: Option<PhysicalPosition<f64>> = None;
 
         let mut redraw = |renderer: &mut VulkanoWindowRenderer,
-                          draw_quad_pipeline: &DrawQuadPipeline| {
+                          draw_quad_pipeline: &mut DrawQuadPipeline,
+                          cursor: Option<PhysicalPosition<f64>>| {
             let elapsed_ms = render_start.elapsed().as_millis() as u32 * playback_speed;
             debug!("Render started at {}ms", elapsed_ms);
+            draw_quad_pipeline.set_time(elapsed_ms);
+            draw_overlay_pipeline.set_time(elapsed_ms);
+
+            let event = options
+                .events
+                .iter()
+                .find(|event| event.contains(elapsed_ms));
+            let artwork = options
+                .atlas
+                .as_ref()
+                .zip(cursor)
+                .and_then(|(atlas, cursor)| {
+                    let size = renderer.window().inner_size();
+                    let texel = draw_quad_pipeline.texel_at(
+                        [cursor.x, cursor.y],
+                        [size.width as f64, size.height as f64],
+                    )?;
+                    atlas.artwork_at(texel)
+                });
+            let mut new_title = format!(
+                "r/place 2023 Player - {}",
+                to_utc(elapsed_ms).format("%Y-%m-%d %H:%M:%S")
+            );
+            if let Some(event) = event {
+                new_title = format!("{new_title} - {}", event.kind.label());
+            }
+            draw_quad_pipeline.set_highlight_bounds(artwork.map(|artwork| artwork.bounds.to_uv()));
+            draw_overlay_pipeline.set_label(
+                artwork.zip(cursor).map(|(artwork, cursor)| {
+                    (artwork.name.as_str(), [cursor.x as f32, cursor.y as f32])
+                }),
+                (2.0 * renderer.window().scale_factor()).round() as f32,
+            );
+            if new_title != title {
+                renderer.window().set_title(&new_title);
+                title = new_title;
+            }
+
+            let bounds = options.canvas_bounds.bounds_at(elapsed_ms);
+            if active_bounds != Some(bounds) {
+                debug!("Active canvas bounds: {:?}", bounds);
+                active_bounds = Some(bounds);
+                draw_quad_pipeline.set_active_bounds(bounds.to_uv());
+                if options.follow_bounds {
+                    draw_quad_pipeline.set_view(bounds.to_uv());
+                }
+            }
 
             for pixel_data in &mut data_reader {
                 let pixel_data = pixel_data.unwrap();
@@ -143,8 +242,10 @@ impl App {
 
             let after_draw =
                 draw_quad_pipeline.draw(after_compute, renderer.swapchain_image_view());
+            let after_overlay =
+                draw_overlay_pipeline.draw(after_draw, renderer.swapchain_image_view());
 
-            renderer.present(after_draw, true);
+            renderer.present(after_overlay, true);
         };
 
         event_loop
@@ -162,6 +263,17 @@ impl App {
                                 },
                             ..
                         } => elwt.exit(),
+                        WindowEvent::KeyboardInput {
+                            event:
+                                KeyEvent {
+                                    state: ElementState::Pressed,
+                                    logical_key: Key::Character(character),
+                                    ..
+                                },
+                            ..
+                        } if character.as_str() == "a" => {
+                            draw_quad_pipeline.toggle_age_mode();
+                        }
                         WindowEvent::Resized(size) => {
                             draw_quad_pipeline
                                 .update_window_aspect_ratio(size.width as f32 / size.height as f32);
@@ -170,8 +282,14 @@ impl App {
                         WindowEvent::ScaleFactorChanged { .. } => {
                             renderer.resize();
                         }
+                        WindowEvent::CursorMoved { position, .. } => {
+                            cursor = Some(position);
+                        }
+                        WindowEvent::CursorLeft { .. } => {
+                            cursor = None;
+                        }
                         WindowEvent::RedrawRequested => {
-                            redraw(renderer, &draw_quad_pipeline);
+                            redraw(renderer, &mut draw_quad_pipeline, cursor);
                         }
                         _ => {}
                     },
@@ -0,0 +1,88 @@
+#version 460
+
+layout(location = 0) out vec4 fragColor; // Output color
+
+// Activity events on the timeline: (start, end, kind, unused), with start and end as fractions of
+// the timeline.
+layout(std430, binding = 0) readonly buffer Markers { vec4 markers[]; };
+// 8x8 glyphs of the printable ASCII characters, a row per byte from the top with the leftmost
+// pixel in the lowest bit.
+layout(std430, binding = 1) readonly buffer Glyphs { uvec2 glyphs[]; };
+
+layout(push_constant) uniform PushConstants {
+  vec2 window_size; // In pixels
+  float progress;   // Played fraction of the timeline, negative for no timeline
+  uint marker_count;
+  vec2 label_origin; // Top left of the label in window pixels
+  uint label_length; // In characters, 0 for no label
+  float label_scale; // Window pixels per glyph pixel
+  uvec4 label[3];    // Glyph indices, four per word from the lowest byte
+};
+
+const float MARGIN = 12.0;      // Between the timeline and the window border, in pixels
+const float STRIP_HEIGHT = 8.0; // In pixels
+const float GLYPH_SIZE = 8.0;
+
+// Colors of the event kinds, in the order of `ActivityEventKind`.
+const vec3 KIND_COLORS[5] = vec3[](vec3(1.0, 0.27, 0.0),   // Spike
+                                   vec3(0.21, 0.56, 0.92), // Lull
+                                   vec3(0.0, 0.8, 0.47),   // Expansion
+                                   vec3(0.75, 0.0, 0.22),  // Outage
+                                   vec3(1.0, 1.0, 1.0));   // Whiteout
+
+// Color of the timeline at a window pixel, transparent outside of it.
+vec4 timeline_color(vec2 position) {
+  float width = window_size.x - 2.0 * MARGIN;
+  float top = window_size.y - MARGIN - STRIP_HEIGHT;
+  if (progress < 0.0 || position.x < MARGIN || position.x >= MARGIN + width ||
+      position.y < top || position.y >= top + STRIP_HEIGHT) {
+    return vec4(0.0);
+  }
+
+  float t = (position.x - MARGIN) / width;
+  vec3 color = t < progress ? vec3(0.6) : vec3(0.3); // Played or ahead
+  for (uint i = 0u; i < marker_count; i++) {
+    vec4 marker = markers[i];
+    // At least a pixel wide, so short events stay visible.
+    if (t >= marker.x && t < max(marker.y, marker.x + 1.0 / width)) {
+      // Events ahead are dimmed like the rest of the timeline.
+      color = KIND_COLORS[int(marker.z)] * (t < progress ? 1.0 : 0.6);
+    }
+  }
+  if (abs(t - progress) * width < 1.0) {
+    color = vec3(1.0); // Playhead
+  }
+  return vec4(color, 0.9);
+}
+
+// Whether the label text covers a position in glyph pixels from its top left.
+bool on_label_text(vec2 position) {
+  if (any(lessThan(position, vec2(0.0))) || position.y >= GLYPH_SIZE ||
+      position.x >= float(label_length) * GLYPH_SIZE) {
+    return false;
+  }
+  uint index = uint(position.x / GLYPH_SIZE);
+  uint glyph = (label[index / 16u][(index / 4u) % 4u] >> (8u * (index % 4u))) & 0xFFu;
+  uvec2 rows = glyphs[glyph];
+  uint row = uint(position.y);
+  uint bits = (row < 4u ? rows.x >> (8u * row) : rows.y >> (8u * (row - 4u))) & 0xFFu;
+  return ((bits >> uint(mod(position.x, GLYPH_SIZE))) & 1u) == 1u;
+}
+
+void main() {
+  vec2 position = gl_FragCoord.xy; // Window pixels from the top left
+  vec4 color = timeline_color(position);
+
+  // The label is a glyph pixel of padding around the text, in glyph pixels.
+  vec2 on_label = (position - label_origin) / label_scale;
+  vec2 label_size = vec2(float(label_length) * GLYPH_SIZE, GLYPH_SIZE) + 2.0;
+  if (label_length > 0u && all(greaterThanEqual(on_label, vec2(0.0))) &&
+      all(lessThan(on_label, label_size))) {
+    color = on_label_text(on_label - 1.0) ? vec4(1.0) : vec4(0.0, 0.0, 0.0, 0.7);
+  }
+
+  if (color.a == 0.0) {
+    discard;
+  }
+  fragColor = color;
+}
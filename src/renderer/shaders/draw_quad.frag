@@ -5,7 +5,54 @@ layout(location = 0) out vec4 fragColor; // Output color
 layout(location = 0) in vec2 v_uv; // Input UV from vertex shader
 
 layout(binding = 0) uniform sampler2D u_myTexture; // Texture uniform
+layout(binding = 1) uniform usampler2D u_lastChange; // Time of the last change
+
+layout(push_constant) uniform PushConstants {
+  vec4 active_bounds;    // Opened area of the canvas in UV: (u1, v1, u2, v2)
+  vec4 highlight_bounds; // Outlined area in UV, empty for none
+  uint mode;             // 0: colors, 1: time since the last change
+  uint now_ms;
+  float max_age_ms;
+};
+
+// Inferno color map, from cold to hot.
+const vec3 INFERNO[9] = vec3[](
+    vec3(0, 0, 4), vec3(31, 12, 72), vec3(85, 15, 109), vec3(136, 34, 106),
+    vec3(186, 54, 85), vec3(227, 89, 51), vec3(249, 140, 10),
+    vec3(249, 201, 50), vec3(252, 255, 164));
+
+vec3 inferno(float t) {
+  float position = clamp(t, 0.0, 1.0) * 8.0;
+  int index = min(int(position), 7);
+  return mix(INFERNO[index], INFERNO[index + 1], position - float(index)) /
+         255.0;
+}
+
+// Whether the UV is inside the highlighted area and less than `width` from its border.
+bool on_outline(vec2 uv, vec2 width) {
+  return all(greaterThanEqual(uv, highlight_bounds.xy)) &&
+         all(lessThan(uv, highlight_bounds.zw)) &&
+         (any(lessThan(uv, highlight_bounds.xy + width)) ||
+          any(greaterThanEqual(uv, highlight_bounds.zw - width)));
+}
 
 void main() {
+  // Two window pixels, taken before any branch so the derivatives are defined.
+  vec2 outline_width = 2.0 * fwidth(v_uv);
+  if (any(lessThan(v_uv, active_bounds.xy)) ||
+      any(greaterThanEqual(v_uv, active_bounds.zw))) {
+    fragColor = vec4(0.2, 0.2, 0.2, 1.0); // Not opened yet
+    return;
+  }
+  if (mode == 1u) {
+    uint last_change = texture(u_lastChange, v_uv).r;
+    float age = float(now_ms - min(last_change, now_ms));
+    // Recently changed pixels are the hottest.
+    fragColor = vec4(inferno(1.0 - age / max_age_ms), 1.0);
+  } else {
     fragColor = texture(u_myTexture, v_uv);
+  }
+  if (on_outline(v_uv, outline_width)) {
+    fragColor = vec4(1.0 - fragColor.rgb, 1.0); // Inverted to stand out on any color
+  }
 }
@@ -29,6 +29,7 @@ pub struct UpdateTexturePipeline {
     // descriptor_set_allocator: Arc<StandardDescriptorSetAllocator>,
     pixel_updates_buffer: Subbuffer<cs::PixelUpdates>,
     canvas_image: Arc<ImageView>,
+    age_image: Arc<ImageView>,
     atomic_buffer: Subbuffer<cs::LastIndex>,
 
     descriptor_set: Arc<DescriptorSet>,
@@ -128,6 +129,22 @@ impl UpdateTexturePipeline {
         )
         .unwrap();
 
+        let age_image = ImageView::new_default(
+            Image::new(
+                allocator.clone(),
+                ImageCreateInfo {
+                    image_type: ImageType::Dim2d,
+                    format: Format::R32_UINT,
+                    extent: [canvas_size.0, canvas_size.1, 1],
+                    usage: ImageUsage::TRANSFER_DST | ImageUsage::SAMPLED | ImageUsage::STORAGE,
+                    ..Default::default()
+                },
+                AllocationCreateInfo::default(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
         let descriptor_set = {
             let desc_layout = compute_pipeline.layout().set_layouts()[0].clone();
             let descriptor_set = DescriptorSet::new(
@@ -138,6 +155,7 @@ impl UpdateTexturePipeline {
                     WriteDescriptorSet::image_view(1, canvas_image.clone()),
                     WriteDescriptorSet::buffer(2, atomic_buffer.clone()),
                     WriteDescriptorSet::buffer(3, canvas_size_buffer),
+                    WriteDescriptorSet::image_view(4, age_image.clone()),
                 ],
                 [],
             )
@@ -153,6 +171,7 @@ impl UpdateTexturePipeline {
             pixel_updates_buffer,
             atomic_buffer,
             canvas_image,
+            age_image,
 
             descriptor_set,
 
@@ -164,6 +183,11 @@ impl UpdateTexturePipeline {
         &self.canvas_image
     }
 
+    /// Time of the last update that changed the color of each pixel, as `R32_UINT`.
+    pub fn age_image(&self) -> &Arc<ImageView> {
+        &self.age_image
+    }
+
     pub fn compute(
         &mut self,
         before: Box<dyn GpuFuture>,
@@ -206,6 +230,12 @@ impl UpdateTexturePipeline {
                     ..ClearColorImageInfo::image(self.canvas_image.image().clone())
                 })
                 .unwrap();
+            builder
+                .clear_color_image(ClearColorImageInfo {
+                    clear_value: ClearColorValue::Uint([0; 4]),
+                    ..ClearColorImageInfo::image(self.age_image.image().clone())
+                })
+                .unwrap();
             self.should_clear_canvas = false;
         }
 
@@ -238,7 +268,7 @@ impl UpdateTexturePipeline {
 mod cs {
     use vulkano::padded::Padded;
 
-    use crate::data;
+    use crate::{canvas::rectangle_ranges, data};
 
     vulkano_shaders::shader! {
         ty: "compute",
@@ -260,9 +290,9 @@ mod cs {
                     (0, [x, y, 0, 0])
                 }
                 data::Coordinate::Rectangle { x1, y1, x2, y2 } => {
-                    let (x1, y1) = convert((x1, y1));
-                    let (x2, y2) = convert((x2, y2));
-                    (1, [x1, y1, x2, y2])
+                    // Ordered and clipped like on the CPU, the shader fills `start..end`.
+                    let (xs, ys) = rectangle_ranges((x1, y1), (x2, y2));
+                    (1, [xs.start, ys.start, xs.end, ys.end])
                 }
                 data::Coordinate::Circle { x, y, radius } => {
                     let (x, y) = convert((x, y));
@@ -0,0 +1,440 @@
+use std::{
+    collections::{btree_map, BTreeMap},
+    fs::{self, File},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    vec,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use snafu::{prelude::*, Whatever};
+
+use crate::{
+    analysis::TimeWindow,
+    bounds::CanvasBounds,
+    canvas::{covered_bounds, CANVAS_HEIGHT, CANVAS_WIDTH},
+    data::PixelData,
+    parse::STREAM_VERSION,
+};
+
+/// The updates of one tile during one time bucket.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct TileBlock {
+    pub bucket: u32,
+    /// Position of the gzipped block in `blocks.bin`.
+    pub offset: u64,
+    pub len: u64,
+    /// Number of updates in the block.
+    pub updates: u64,
+}
+
+/// Identifies the dataset tiles were built from, without reading it: its length and the gzip
+/// trailer, which holds the CRC32 and length of the decompressed updates.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceFingerprint {
+    pub len: u64,
+    pub gzip_trailer: u64,
+}
+
+impl SourceFingerprint {
+    pub fn of(data_path: &str) -> Result<Self, Whatever> {
+        let mut file = File::open(data_path)
+            .with_whatever_context(|_| format!("Failed to open {data_path}"))?;
+        let mut trailer = [0; 8];
+        let len = file
+            .seek(SeekFrom::End(0))
+            .and_then(|len| {
+                file.seek(SeekFrom::Start(len.saturating_sub(8)))?;
+                file.read_exact(&mut trailer)?;
+                Ok(len)
+            })
+            .with_whatever_context(|_| format!("Failed to read the end of {data_path}"))?;
+        Ok(Self {
+            len,
+            gzip_trailer: u64::from_le_bytes(trailer),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TileIndex {
+    /// [`STREAM_VERSION`] of the updates in the blocks. Missing from indices built before it was
+    /// recorded.
+    #[serde(default)]
+    pub version: u32,
+    pub tile_size: u32,
+    pub bucket_ms: u32,
+    /// The dataset the tiles were built from. Missing from indices built before it was recorded.
+    #[serde(default)]
+    pub source: SourceFingerprint,
+    /// Time of the last update of the dataset, which a query of a region may not reach. Missing
+    /// from indices built before it was recorded.
+    #[serde(default)]
+    pub end_ms: Option<u32>,
+    /// Blocks of every row-major tile, sorted by bucket. Buckets without updates are left out.
+    pub tiles: Vec<Vec<TileBlock>>,
+}
+
+impl TileIndex {
+    fn columns(&self) -> u32 {
+        CANVAS_WIDTH.div_ceil(self.tile_size)
+    }
+
+    /// Returns the row-major indices of the tiles overlapping the region.
+    fn tiles_in(&self, region: CanvasBounds) -> impl Iterator<Item = usize> + '_ {
+        let size = self.tile_size;
+        (region.y1 / size..region.y2.div_ceil(size)).flat_map(move |row| {
+            (region.x1 / size..region.x2.div_ceil(size))
+                .map(move |column| (row * self.columns() + column) as usize)
+        })
+    }
+}
+
+/// The updates split into square tiles of the canvas and buckets of event time, so a query only
+/// decodes the blocks overlapping its region and time window. Stored in a directory as:
+///
+/// - `index.json`: the [`TileIndex`].
+/// - `blocks.bin`: the gzipped blocks. Every update is stored with its position in the stream,
+///   in every tile the bounding box of its covered pixels overlaps.
+pub struct Tiles {
+    dir: String,
+    index: TileIndex,
+}
+
+fn index_path(dir: &str) -> String {
+    format!("{dir}/index.json")
+}
+
+fn blocks_path(dir: &str) -> String {
+    format!("{dir}/blocks.bin")
+}
+
+/// The block of a tile being written and its number of updates.
+type OpenBlock = Option<(GzEncoder<Vec<u8>>, u64)>;
+
+/// Finishes the open block of every tile and appends it to `blocks.bin`.
+fn finish_bucket(
+    bucket: u32,
+    open_blocks: &mut [OpenBlock],
+    tiles: &mut [Vec<TileBlock>],
+    writer: &mut impl Write,
+    offset: &mut u64,
+) -> Result<(), Whatever> {
+    for (tile, open_block) in open_blocks.iter_mut().enumerate() {
+        let Some((encoder, updates)) = open_block.take() else {
+            continue;
+        };
+        let bytes = encoder
+            .finish()
+            .whatever_context("Failed to finish tile block")?;
+        writer
+            .write_all(&bytes)
+            .whatever_context("Failed to write tile block")?;
+        tiles[tile].push(TileBlock {
+            bucket,
+            offset: *offset,
+            len: bytes.len() as u64,
+            updates,
+        });
+        *offset += bytes.len() as u64;
+    }
+    Ok(())
+}
+
+/// Splits the updates into tiles of `tile_size` pixels and buckets of `bucket_ms` and writes
+/// them to `dir`, recording the `source` dataset they come from.
+pub fn write_tiles(
+    iter: impl Iterator<Item = Result<PixelData, Whatever>>,
+    source: SourceFingerprint,
+    dir: &str,
+    tile_size: u32,
+    bucket_ms: u32,
+) -> Result<TileIndex, Whatever> {
+    ensure_whatever!(
+        tile_size > 0 && bucket_ms > 0,
+        "Tile size and bucket duration must be greater than zero"
+    );
+    fs::create_dir_all(dir).whatever_context("Failed to create tile directory")?;
+    let file = File::create(blocks_path(dir)).whatever_context("Failed to create tile blocks")?;
+    let mut writer = BufWriter::new(file);
+    let bincode_config = bincode::config::standard();
+
+    let mut index = TileIndex {
+        version: STREAM_VERSION,
+        tile_size,
+        bucket_ms,
+        source,
+        end_ms: None,
+        tiles: Vec::new(),
+    };
+    let tile_count = index.columns() as usize * CANVAS_HEIGHT.div_ceil(tile_size) as usize;
+    index.tiles = vec![Vec::new(); tile_count];
+    let mut open_blocks: Vec<OpenBlock> = (0..tile_count).map(|_| None).collect();
+    let mut offset = 0;
+    let mut bucket = 0;
+
+    for (position, pixel_data) in iter.enumerate() {
+        let pixel_data = pixel_data?;
+        index.end_ms = Some(pixel_data.miliseconds_since_first_pixel);
+        let pixel_bucket = pixel_data.miliseconds_since_first_pixel / bucket_ms;
+        if pixel_bucket != bucket {
+            finish_bucket(
+                bucket,
+                &mut open_blocks,
+                &mut index.tiles,
+                &mut writer,
+                &mut offset,
+            )?;
+            println!("Tiled bucket {}", bucket);
+            bucket = pixel_bucket;
+        }
+        let Some(bounds) = covered_bounds(&pixel_data.coordinate) else {
+            continue;
+        };
+        for tile in index.tiles_in(bounds) {
+            let (encoder, updates) = open_blocks[tile]
+                .get_or_insert_with(|| (GzEncoder::new(Vec::new(), Compression::fast()), 0));
+            bincode::encode_into_std_write(position as u64, encoder, bincode_config)
+                .and_then(|_| bincode::encode_into_std_write(&pixel_data, encoder, bincode_config))
+                .whatever_context("Failed to write tile update")?;
+            *updates += 1;
+        }
+    }
+    finish_bucket(
+        bucket,
+        &mut open_blocks,
+        &mut index.tiles,
+        &mut writer,
+        &mut offset,
+    )?;
+    writer
+        .flush()
+        .whatever_context("Failed to flush tile blocks")?;
+
+    let file = File::create(index_path(dir)).whatever_context("Failed to create tile index")?;
+    serde_json::to_writer(BufWriter::new(file), &index)
+        .whatever_context("Failed to write tile index")?;
+    Ok(index)
+}
+
+impl Tiles {
+    pub fn path_for(data_path: &str) -> String {
+        format!("{data_path}.tiles")
+    }
+
+    pub fn open(dir: &str) -> Result<Self, Whatever> {
+        let file = File::open(index_path(dir)).whatever_context("Failed to open tile index")?;
+        let index: TileIndex =
+            serde_json::from_reader(BufReader::new(file)).whatever_context("Invalid tile index")?;
+        ensure_whatever!(
+            index.version == STREAM_VERSION,
+            "Tiles in {dir} were built by an older version, rebuild them with `tiles`"
+        );
+        Ok(Self {
+            dir: dir.to_string(),
+            index,
+        })
+    }
+
+    /// Opens the tiles next to the dataset if they have been built, and checks they were built
+    /// from it rather than from an earlier version of it.
+    pub fn open_for(data_path: &str) -> Result<Option<Self>, Whatever> {
+        let dir = Self::path_for(data_path);
+        if !Path::new(&index_path(&dir)).exists() {
+            return Ok(None);
+        }
+        let tiles = Self::open(&dir)?;
+        ensure_whatever!(
+            tiles.index.source == SourceFingerprint::of(data_path)?,
+            "Tiles in {dir} were built from another version of {data_path}, rebuild them with \
+             `tiles`"
+        );
+        Ok(Some(tiles))
+    }
+
+    pub fn index(&self) -> &TileIndex {
+        &self.index
+    }
+
+    /// Returns the updates during the time window whose bounding box overlaps the region, in
+    /// stream order.
+    pub fn query(&self, region: CanvasBounds, window: TimeWindow) -> Result<TileQuery, Whatever> {
+        let first_bucket = window.start_ms / self.index.bucket_ms;
+        let last_bucket = window.end_ms / self.index.bucket_ms;
+        let mut buckets: BTreeMap<u32, Vec<TileBlock>> = BTreeMap::new();
+        for tile in self.index.tiles_in(region) {
+            for block in &self.index.tiles[tile] {
+                if (first_bucket..=last_bucket).contains(&block.bucket) {
+                    buckets.entry(block.bucket).or_default().push(block.clone());
+                }
+            }
+        }
+        let file = File::open(blocks_path(&self.dir)).whatever_context("Failed to open tiles")?;
+        Ok(TileQuery {
+            file,
+            region,
+            window,
+            buckets: buckets.into_iter(),
+            pending: Vec::new().into_iter(),
+        })
+    }
+}
+
+/// Iterator over the updates of a tile query. Loads one time bucket at a time.
+pub struct TileQuery {
+    file: File,
+    region: CanvasBounds,
+    window: TimeWindow,
+    buckets: btree_map::IntoIter<u32, Vec<TileBlock>>,
+    pending: vec::IntoIter<PixelData>,
+}
+
+impl TileQuery {
+    /// Decodes the blocks of a bucket, keeping every update once and in stream order.
+    fn load_bucket(&mut self, blocks: &[TileBlock]) -> Result<Vec<PixelData>, Whatever> {
+        let bincode_config = bincode::config::standard();
+        let mut updates = Vec::new();
+        for block in blocks {
+            let mut bytes = vec![0; block.len as usize];
+            self.file
+                .seek(SeekFrom::Start(block.offset))
+                .and_then(|_| self.file.read_exact(&mut bytes))
+                .whatever_context("Failed to read tile block")?;
+            let mut decoder = GzDecoder::new(&bytes[..]);
+            for _ in 0..block.updates {
+                let position: u64 = bincode::decode_from_std_read(&mut decoder, bincode_config)
+                    .whatever_context("Failed to read tile update")?;
+                let pixel_data: PixelData =
+                    bincode::decode_from_std_read(&mut decoder, bincode_config)
+                        .whatever_context("Failed to read tile update")?;
+                let in_region = covered_bounds(&pixel_data.coordinate)
+                    .is_some_and(|bounds| bounds.intersects(&self.region));
+                if in_region
+                    && self
+                        .window
+                        .contains(pixel_data.miliseconds_since_first_pixel)
+                {
+                    updates.push((position, pixel_data));
+                }
+            }
+        }
+        // Shapes overlapping several tiles are stored in all of them.
+        updates.sort_unstable_by_key(|(position, _)| *position);
+        updates.dedup_by_key(|(position, _)| *position);
+        Ok(updates
+            .into_iter()
+            .map(|(_, pixel_data)| pixel_data)
+            .collect())
+    }
+}
+
+impl Iterator for TileQuery {
+    type Item = Result<PixelData, Whatever>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(pixel_data) = self.pending.next() {
+                return Some(Ok(pixel_data));
+            }
+            let (_, blocks) = self.buckets.next()?;
+            match self.load_bucket(&blocks) {
+                Ok(updates) => self.pending = updates.into_iter(),
+                Err(e) => {
+                    self.buckets = BTreeMap::new().into_iter();
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_tiles_query() {
+        use super::{write_tiles, SourceFingerprint, Tiles};
+        use crate::{
+            analysis::TimeWindow,
+            bounds::CanvasBounds,
+            data::{Coordinate, PixelColor, PixelData},
+        };
+
+        let pixel = |miliseconds_since_first_pixel, coordinate| PixelData {
+            miliseconds_since_first_pixel,
+            coordinate,
+            pixel_color: PixelColor { r: 0, g: 0, b: 0 },
+            user: 0,
+        };
+        let pixels = vec![
+            pixel(0, Coordinate::Simple { x: -1500, y: 999 }),
+            pixel(5, Coordinate::Simple { x: 0, y: 0 }),
+            pixel(
+                12,
+                Coordinate::Rectangle {
+                    x1: -1500,
+                    y1: 999,
+                    x2: 1499,
+                    y2: -1000,
+                },
+            ),
+            pixel(15, Coordinate::Simple { x: 1, y: 0 }),
+            pixel(30, Coordinate::Simple { x: -1500, y: 999 }),
+        ];
+
+        let dir = std::env::temp_dir().join(format!("rplace-tiles-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        write_tiles(
+            pixels.iter().cloned().map(Ok),
+            SourceFingerprint::default(),
+            dir,
+            100,
+            10,
+        )
+        .unwrap();
+        let tiles = Tiles::open(dir).unwrap();
+        assert_eq!(tiles.index().end_ms, Some(30));
+
+        let query = |region: &str, start_ms, end_ms| {
+            let region: CanvasBounds = region.parse().unwrap();
+            tiles
+                .query(region, TimeWindow { start_ms, end_ms })
+                .unwrap()
+                .map(|pixel_data| pixel_data.unwrap().miliseconds_since_first_pixel)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(query("-1500,999,-1490,990", 0, u32::MAX), vec![0, 12, 30]);
+        assert_eq!(query("0,0,1,0", 0, u32::MAX), vec![5, 12, 15]);
+        assert_eq!(query("1,0,1,0", 10, 20), vec![12, 15]);
+        assert_eq!(query("-1500,999,1499,-1000", 5, 29), vec![5, 12, 15]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_tiles_source() {
+        use std::io::Write;
+
+        use super::{write_tiles, SourceFingerprint, Tiles};
+
+        let data_path =
+            std::env::temp_dir().join(format!("rplace-tiles-source-{}.bin", std::process::id()));
+        let data_path = data_path.to_str().unwrap();
+        std::fs::write(data_path, b"first version of the dataset").unwrap();
+        let dir = Tiles::path_for(data_path);
+        let source = SourceFingerprint::of(data_path).unwrap();
+        write_tiles(std::iter::empty(), source, &dir, 100, 10).unwrap();
+        assert!(Tiles::open_for(data_path).unwrap().is_some());
+
+        // Regenerating the dataset leaves the tiles stale.
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(data_path)
+            .unwrap();
+        file.write_all(b", and more").unwrap();
+        assert!(Tiles::open_for(data_path).is_err());
+
+        std::fs::remove_file(data_path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+};
+
+use snafu::{prelude::*, Whatever};
+
+use crate::{bounds::CanvasBounds, canvas::CanvasState, export::RgbImage, keyframe};
+
+/// Frames taken at a fixed interval of event time.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRange {
+    pub start_ms: u32,
+    /// Frames are taken until the end of the stream if not given.
+    pub end_ms: Option<u32>,
+    pub interval_ms: u32,
+}
+
+/// Replays the pixel updates and calls `f` with the time and the canvas of every frame in the
+/// range. A frame includes every update up to and including its time. Only `region` of the
+/// canvas is guaranteed to be correct, see [`keyframe::replay_from`].
+pub fn for_each_frame(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    range: FrameRange,
+    region: CanvasBounds,
+    mut f: impl FnMut(u32, &CanvasState) -> Result<(), Whatever>,
+) -> Result<(), Whatever> {
+    ensure_whatever!(range.interval_ms > 0, "Interval must be greater than zero");
+    let end_ms = range.end_ms.unwrap_or(u32::MAX) as u64;
+    let (mut canvas, updates) =
+        keyframe::replay_from(data_path, keyframes_dir, range.start_ms, region)?;
+
+    let mut frame_ms = range.start_ms as u64;
+    for pixel_data in updates {
+        let pixel_data = pixel_data?;
+        while pixel_data.miliseconds_since_first_pixel as u64 > frame_ms {
+            if frame_ms > end_ms {
+                return Ok(());
+            }
+            f(frame_ms as u32, &canvas)?;
+            frame_ms += range.interval_ms as u64;
+        }
+        canvas.apply(&pixel_data);
+    }
+
+    // The stream ended, so the canvas stays the same for the remaining frames.
+    match range.end_ms {
+        Some(_) => {
+            while frame_ms <= end_ms {
+                f(frame_ms as u32, &canvas)?;
+                frame_ms += range.interval_ms as u64;
+            }
+        }
+        None => f(frame_ms as u32, &canvas)?,
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimelapseFormat {
+    /// Numbered PNG files in the output directory.
+    Png,
+    /// Raw YUV 4:4:4 stream that can be piped to an encoder. `-` writes to stdout.
+    Y4m,
+}
+
+pub struct TimelapseOptions {
+    pub range: FrameRange,
+    pub region: CanvasBounds,
+    pub scale: u32,
+    pub format: TimelapseFormat,
+    /// Frame rate written to the Y4M header.
+    pub fps: u32,
+}
+
+/// Writes frames either as a numbered PNG sequence or as a Y4M stream.
+pub enum FrameWriter {
+    PngSequence { dir: String, frames: u32 },
+    Y4m { writer: Box<dyn Write>, frames: u32 },
+}
+
+impl FrameWriter {
+    pub fn create(
+        format: TimelapseFormat,
+        output: &str,
+        (width, height): (u32, u32),
+        fps: u32,
+    ) -> Result<Self, Whatever> {
+        match format {
+            TimelapseFormat::Png => {
+                fs::create_dir_all(output).whatever_context("Failed to create output directory")?;
+                Ok(FrameWriter::PngSequence {
+                    dir: output.to_string(),
+                    frames: 0,
+                })
+            }
+            TimelapseFormat::Y4m => {
+                let mut writer: Box<dyn Write> = if output == "-" {
+                    Box::new(BufWriter::new(io::stdout().lock()))
+                } else {
+                    let file = File::create(output)
+                        .with_whatever_context(|_| format!("Failed to create {output}"))?;
+                    Box::new(BufWriter::new(file))
+                };
+                writeln!(writer, "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C444")
+                    .whatever_context("Failed to write Y4M header")?;
+                Ok(FrameWriter::Y4m { writer, frames: 0 })
+            }
+        }
+    }
+
+    pub fn write(&mut self, image: &RgbImage) -> Result<(), Whatever> {
+        match self {
+            FrameWriter::PngSequence { dir, frames } => {
+                image.write_png(&format!("{dir}/frame-{frames:06}.png"))?;
+                *frames += 1;
+            }
+            FrameWriter::Y4m { writer, frames } => {
+                writer
+                    .write_all(b"FRAME\n")
+                    .and_then(|_| writer.write_all(&rgb_to_yuv444_planes(image)))
+                    .whatever_context("Failed to write Y4M frame")?;
+                *frames += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<u32, Whatever> {
+        match self {
+            FrameWriter::PngSequence { frames, .. } => Ok(frames),
+            FrameWriter::Y4m { mut writer, frames } => {
+                writer.flush().whatever_context("Failed to flush Y4M")?;
+                Ok(frames)
+            }
+        }
+    }
+}
+
+/// Converts the image to BT.601 limited range Y, U and V planes.
+fn rgb_to_yuv444_planes(image: &RgbImage) -> Vec<u8> {
+    let len = image.pixels.len() / 3;
+    let mut planes = vec![0; len * 3];
+    for (i, rgb) in image.pixels.chunks_exact(3).enumerate() {
+        let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+        planes[i] = (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
+        planes[len + i] = (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8;
+        planes[len * 2 + i] = (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8;
+    }
+    planes
+}
+
+/// Replays the pixel updates and writes a frame of the region every interval of event time.
+/// Returns the number of frames written.
+pub fn write_timelapse(
+    data_path: &str,
+    keyframes_dir: Option<&str>,
+    options: &TimelapseOptions,
+    output: &str,
+) -> Result<u32, Whatever> {
+    let scale = options.scale.max(1);
+    let size = (
+        options.region.width() * scale,
+        options.region.height() * scale,
+    );
+    let mut writer = FrameWriter::create(options.format, output, size, options.fps)?;
+
+    for_each_frame(
+        data_path,
+        keyframes_dir,
+        options.range,
+        options.region,
+        |_, canvas| writer.write(&RgbImage::from_canvas(canvas, options.region).scaled(scale)),
+    )?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_rgb_to_yuv444_planes() {
+        use super::rgb_to_yuv444_planes;
+        use crate::export::RgbImage;
+
+        let image = RgbImage {
+            width: 2,
+            height: 1,
+            pixels: vec![255, 255, 255, 0, 0, 0],
+        };
+        assert_eq!(
+            rgb_to_yuv444_planes(&image),
+            vec![235, 16, 128, 128, 128, 128]
+        );
+    }
+}
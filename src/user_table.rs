@@ -0,0 +1,128 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use snafu::{prelude::*, Whatever};
+
+/// The hashed user of every user id in the dataset. Stored gzipped next to the dataset with the
+/// hash of every id on its own line.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UserTable {
+    hashes: Vec<String>,
+}
+
+impl UserTable {
+    pub fn path_for(data_path: &str) -> String {
+        format!("{data_path}.users.gz")
+    }
+
+    pub fn load(path: &str) -> Result<Self, Whatever> {
+        let file = File::open(path).with_whatever_context(|_| format!("Failed to open {path}"))?;
+        let hashes = BufReader::new(GzDecoder::new(file))
+            .lines()
+            .collect::<Result<_, _>>()
+            .with_whatever_context(|_| format!("Failed to read {path}"))?;
+        Ok(Self { hashes })
+    }
+
+    /// Loads the table next to the dataset if it has been written.
+    pub fn load_for(data_path: &str) -> Result<Option<Self>, Whatever> {
+        let path = Self::path_for(data_path);
+        if !Path::new(&path).exists() {
+            return Ok(None);
+        }
+        Self::load(&path).map(Some)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Whatever> {
+        let file =
+            File::create(path).with_whatever_context(|_| format!("Failed to create {path}"))?;
+        let mut writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+        self.hashes
+            .iter()
+            .try_for_each(|hash| writeln!(writer, "{hash}"))
+            .and_then(|_| writer.finish()?.flush())
+            .with_whatever_context(|_| format!("Failed to write {path}"))
+    }
+
+    pub fn hashes(&self) -> &[String] {
+        &self.hashes
+    }
+
+    pub fn hash(&self, user: u32) -> Option<&str> {
+        self.hashes.get(user as usize).map(String::as_str)
+    }
+
+    /// Finds a user by the hash, or by the id.
+    pub fn find(&self, key: &str) -> Option<u32> {
+        self.hashes
+            .iter()
+            .position(|hash| hash == key)
+            .map(|user| user as u32)
+            .or_else(|| key.parse().ok().filter(|&user| self.hash(user).is_some()))
+    }
+
+    /// Names a user by the hash, or by the id if the hash isn't known.
+    pub fn name(table: Option<&Self>, user: u32) -> String {
+        match table.and_then(|table| table.hash(user)) {
+            Some(hash) => hash.to_string(),
+            None => user.to_string(),
+        }
+    }
+}
+
+/// Assigns ids to user hashes in the order they're first seen.
+#[derive(Debug, Default)]
+pub struct UserIds {
+    ids: HashMap<String, u32>,
+}
+
+impl UserIds {
+    pub fn id_of(&mut self, hash: &str) -> u32 {
+        if let Some(&id) = self.ids.get(hash) {
+            return id;
+        }
+        let id = self.ids.len() as u32;
+        self.ids.insert(hash.to_string(), id);
+        id
+    }
+
+    pub fn into_table(self) -> UserTable {
+        let mut hashes = vec![String::new(); self.ids.len()];
+        for (hash, id) in self.ids {
+            hashes[id as usize] = hash;
+        }
+        UserTable { hashes }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_user_table() {
+        use super::{UserIds, UserTable};
+
+        let mut ids = UserIds::default();
+        assert_eq!(ids.id_of("b"), 0);
+        assert_eq!(ids.id_of("a"), 1);
+        assert_eq!(ids.id_of("b"), 0);
+        let table = ids.into_table();
+        assert_eq!(table.hashes(), ["b", "a"]);
+
+        let path = std::env::temp_dir().join(format!("rplace-users-{}.gz", std::process::id()));
+        let path = path.to_str().unwrap();
+        table.save(path).unwrap();
+        let table = UserTable::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(table.find("a"), Some(1));
+        assert_eq!(table.find("0"), Some(0));
+        assert_eq!(table.find("2"), None);
+        assert_eq!(UserTable::name(Some(&table), 1), "a");
+        assert_eq!(UserTable::name(None, 1), "1");
+    }
+}